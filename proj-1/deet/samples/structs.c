@@ -0,0 +1,38 @@
+#include <stdio.h>
+
+struct node {
+    int value;
+    struct node *next;
+};
+
+typedef struct point {
+    double x, y;
+} point_t;
+
+enum color { RED, GREEN = 5, BLUE };
+
+int counter = 0;
+
+int sum_list(struct node *head, int scale) {
+    int sum = 0;
+    for (struct node *p = head; p; p = p->next) {
+        sum += p->value * scale;
+    }
+    return sum;
+}
+
+int main() {
+    struct node n3 = {3, NULL};
+    struct node n2 = {2, &n3};
+    struct node n1 = {1, &n2};
+    int arr[5] = {10, 20, 30, 40, 50};
+    point_t pt = {1.0, 2.5};
+    enum color col = GREEN;
+    const char *msg = "hello";
+    char c = 'A';
+    for (int i = 0; i < 5; i++) {
+        counter += arr[i];
+    }
+    printf("%d %f %d %s %c %d\n", sum_list(&n1, 10), pt.y, col, msg, c, counter);
+    return 0;
+}
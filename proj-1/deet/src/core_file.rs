@@ -0,0 +1,448 @@
+//! Post-mortem debugging: registers and memory of a crashed program, read out of its ELF core
+//! dump instead of a live process.
+
+use crate::inferior::{self, Tracee};
+use crate::registers::FpRegisters;
+use nix::errno::Errno;
+use nix::unistd::Pid;
+use object::{Object, ObjectSegment};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::os::unix::fs::FileExt;
+
+// ELF constants we need that `object` doesn't expose for core files
+const ET_CORE: u16 = 4;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRFPREG: u32 = 2;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+const NT_X86_XSTATE: u32 = 0x202;
+
+// Offsets into the x86-64 `struct elf_prstatus` and `struct elf_prpsinfo`
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REG: usize = 112;
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+/// A PT_LOAD segment: `data` holds the bytes the kernel dumped, which may be fewer than
+/// `mem_size` if the rest can be read from the mapped file.
+struct Segment {
+    address: usize,
+    mem_size: usize,
+    data: Vec<u8>,
+}
+
+/// A file mapping from the NT_FILE note: `[start, end)` maps `path` from `file_offset` on.
+struct MappedFile {
+    start: usize,
+    end: usize,
+    file_offset: usize,
+    path: String,
+}
+
+/// A thread of the dumped program: its NT_PRSTATUS note and the register notes that follow it.
+pub struct CoreThread {
+    pub tid: Pid,
+    pub regs: libc::user_regs_struct,
+    signal: i32,
+    fxsave: Option<Vec<u8>>,
+    xsave: Option<Vec<u8>>,
+}
+
+pub struct CoreFile {
+    /// The thread that crashed comes first. Threads are numbered from 1 in this order.
+    threads: Vec<CoreThread>,
+    current: usize,
+    command: String,
+    segments: Vec<Segment>,
+    mapped_files: Vec<MappedFile>,
+    /// The files in `mapped_files`, opened once, by path. A file that can't be opened is left out.
+    files: HashMap<String, fs::File>,
+    auxv: Option<Vec<(u64, u64)>>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?) as usize)
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Returns the (type, descriptor) of every note in the PT_NOTE segments of the ELF64 file `data`.
+fn read_notes(data: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let phoff = read_u64(data, 0x20)?;
+    let phentsize = read_u16(data, 0x36)? as usize;
+    let phnum = read_u16(data, 0x38)? as usize;
+    let mut notes = Vec::new();
+    for i in 0..phnum {
+        let phdr = phoff + i * phentsize;
+        if read_u32(data, phdr)? != PT_NOTE {
+            continue;
+        }
+        let offset = read_u64(data, phdr + 8)?;
+        let size = read_u64(data, phdr + 32)?;
+        let segment = data.get(offset..offset + size)?;
+        let mut pos = 0;
+        while pos + 12 <= segment.len() {
+            let name_size = read_u32(segment, pos)? as usize;
+            let desc_size = read_u32(segment, pos + 4)? as usize;
+            let note_type = read_u32(segment, pos + 8)?;
+            let desc_start = pos + 12 + align4(name_size);
+            notes.push((note_type, segment.get(desc_start..desc_start + desc_size)?));
+            pos = desc_start + align4(desc_size);
+        }
+    }
+    Some(notes)
+}
+
+/// Parses an NT_FILE note: a count and page size, `count` (start, end, page offset) triples,
+/// then `count` NUL-terminated paths.
+fn parse_file_note(desc: &[u8]) -> Option<Vec<MappedFile>> {
+    let count = read_u64(desc, 0)?;
+    let page_size = read_u64(desc, 8)?;
+    let mut paths = desc.get(count.checked_mul(24)?.checked_add(16)?..)?.split(|&b| b == 0);
+    (0..count)
+        .map(|i| {
+            let entry = 16 + i * 24;
+            Some(MappedFile {
+                start: read_u64(desc, entry)?,
+                end: read_u64(desc, entry + 8)?,
+                file_offset: read_u64(desc, entry + 16)?.checked_mul(page_size)?,
+                path: String::from_utf8_lossy(paths.next()?).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Collects the register notes of each thread. A thread's NT_PRSTATUS comes before its
+/// NT_PRFPREG and NT_X86_XSTATE; the notes about the whole process follow the first thread's.
+/// Returns None if an NT_PRSTATUS note is truncated.
+fn parse_threads(notes: &[(u32, &[u8])]) -> Option<Vec<CoreThread>> {
+    let mut threads: Vec<CoreThread> = Vec::new();
+    for &(note_type, desc) in notes {
+        match note_type {
+            NT_PRSTATUS => {
+                let reg_bytes = desc
+                    .get(PRSTATUS_REG..PRSTATUS_REG + std::mem::size_of::<libc::user_regs_struct>())?;
+                // pr_reg is laid out exactly like user_regs_struct
+                let regs = unsafe {
+                    std::ptr::read_unaligned(reg_bytes.as_ptr() as *const libc::user_regs_struct)
+                };
+                threads.push(CoreThread {
+                    tid: Pid::from_raw(read_u32(desc, PRSTATUS_PID)? as i32),
+                    regs,
+                    signal: read_u16(desc, PRSTATUS_CURSIG)? as i32,
+                    fxsave: None,
+                    xsave: None,
+                });
+            }
+            NT_PRFPREG => {
+                if let Some(thread) = threads.last_mut() {
+                    thread.fxsave = Some(desc.to_vec());
+                }
+            }
+            NT_X86_XSTATE => {
+                if let Some(thread) = threads.last_mut() {
+                    thread.xsave = Some(desc.to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(threads)
+}
+
+impl CoreFile {
+    /// Loads the core dump at `path`. The thread that crashed, which the kernel always writes
+    /// first, is selected.
+    pub fn open(path: &str) -> Result<CoreFile, String> {
+        let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let object = object::File::parse(&data).map_err(|err| format!("{}: {}", path, err))?;
+        if read_u16(&data, 0x10) != Some(ET_CORE) {
+            return Err(format!("\"{}\" is not a core dump", path));
+        }
+
+        let segments = object
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                let data = data
+                    .get(offset as usize..(offset + size) as usize)
+                    .unwrap_or(&[])
+                    .to_vec();
+                Segment {
+                    address: segment.address() as usize,
+                    mem_size: segment.size() as usize,
+                    data,
+                }
+            })
+            .collect();
+
+        let notes = read_notes(&data).ok_or(format!("{}: malformed program headers", path))?;
+        let threads = parse_threads(&notes).ok_or(format!("{}: truncated NT_PRSTATUS note", path))?;
+        if threads.is_empty() {
+            return Err(format!("{}: no NT_PRSTATUS note", path));
+        }
+
+        let command = notes
+            .iter()
+            .find(|(note_type, _)| *note_type == NT_PRPSINFO)
+            .and_then(|(_, desc)| desc.get(PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN))
+            .map(|psargs| {
+                let len = psargs.iter().position(|&b| b == 0).unwrap_or(psargs.len());
+                String::from_utf8_lossy(&psargs[..len]).trim().to_string()
+            })
+            .unwrap_or_default();
+        let mapped_files: Vec<MappedFile> = notes
+            .iter()
+            .find(|(note_type, _)| *note_type == NT_FILE)
+            .and_then(|(_, desc)| parse_file_note(desc))
+            .unwrap_or_default();
+        let mut files = HashMap::new();
+        for mapping in &mapped_files {
+            if !files.contains_key(&mapping.path) {
+                if let Ok(file) = fs::File::open(&mapping.path) {
+                    files.insert(mapping.path.clone(), file);
+                }
+            }
+        }
+        let auxv = notes
+            .iter()
+            .find(|(note_type, _)| *note_type == NT_AUXV)
+            .map(|(_, desc)| inferior::parse_auxv(desc));
+
+        Ok(CoreFile {
+            threads,
+            current: 0,
+            command,
+            segments,
+            mapped_files,
+            files,
+            auxv,
+        })
+    }
+
+    /// The signal that killed the program, or 0 if the dump didn't record one.
+    pub fn signal(&self) -> i32 {
+        self.threads[0].signal
+    }
+
+    pub fn threads(&self) -> &[CoreThread] {
+        &self.threads
+    }
+
+    /// Returns the number of the selected thread.
+    pub fn current_thread(&self) -> usize {
+        self.current + 1
+    }
+
+    /// Returns the thread id of the selected thread.
+    pub fn tid(&self) -> Pid {
+        self.threads[self.current].tid
+    }
+
+    /// Makes thread `num` the one whose registers we look at. Returns false if there is no such
+    /// thread.
+    pub fn select_thread(&mut self, num: usize) -> bool {
+        if num == 0 || num > self.threads.len() {
+            return false;
+        }
+        self.current = num - 1;
+        true
+    }
+
+    /// The command line the program was started with.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Reads as much as possible of `len` bytes at `addr` from a single source: the dumped
+    /// segment data, or failing that the file mapped there (read-only text usually isn't
+    /// dumped).
+    fn read_chunk(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        for segment in &self.segments {
+            if addr >= segment.address && addr < segment.address + segment.data.len() {
+                let start = addr - segment.address;
+                let end = (start + len).min(segment.data.len());
+                return Some(segment.data[start..end].to_vec());
+            }
+        }
+        let mapping = self
+            .mapped_files
+            .iter()
+            .find(|mapping| addr >= mapping.start && addr < mapping.end)?;
+        let len = len.min(mapping.end - addr);
+        let file = self.files.get(&mapping.path)?;
+        let mut bytes = vec![0; len];
+        let read = file
+            .read_at(&mut bytes, (mapping.file_offset + addr - mapping.start) as u64)
+            .ok()?;
+        bytes.truncate(read);
+        if bytes.is_empty() {
+            // Past the end of the file, e.g. a mapping's zero-filled tail
+            let segment = self.segments.iter().find(|segment| {
+                addr >= segment.address && addr < segment.address + segment.mem_size
+            })?;
+            return Some(vec![0; len.min(segment.address + segment.mem_size - addr)]);
+        }
+        Some(bytes)
+    }
+}
+
+impl Tracee for CoreFile {
+    fn get_regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.threads[self.current].regs)
+    }
+
+    fn get_fpregs(&self) -> Result<FpRegisters, nix::Error> {
+        let thread = &self.threads[self.current];
+        match &thread.fxsave {
+            Some(fxsave) if fxsave.len() >= 512 => {
+                Ok(FpRegisters::new(fxsave.clone(), thread.xsave.as_deref()))
+            }
+            _ => Err(nix::Error::Sys(Errno::ENODATA)),
+        }
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            match self.read_chunk(addr + bytes.len(), len - bytes.len()) {
+                Some(ref chunk) if !chunk.is_empty() => bytes.extend_from_slice(chunk),
+                _ => return Err(nix::Error::Sys(Errno::EIO)),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn auxv(&self) -> Option<Vec<(u64, u64)>> {
+        self.auxv.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Encodes a note as the kernel writes it: sizes and type, then the name and descriptor, each
+    /// padded to 4 bytes.
+    fn note(note_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&5u32.to_le_bytes());
+        bytes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&note_type.to_le_bytes());
+        bytes.extend_from_slice(b"CORE\0\0\0\0");
+        bytes.extend_from_slice(desc);
+        bytes.resize(align4(bytes.len()), 0);
+        bytes
+    }
+
+    /// An NT_PRSTATUS descriptor for thread `tid`, stopped at `rip` by `signal`.
+    fn prstatus(tid: u32, rip: u64, signal: u16) -> Vec<u8> {
+        let mut desc = vec![0; PRSTATUS_REG + std::mem::size_of::<libc::user_regs_struct>() + 8];
+        desc[PRSTATUS_CURSIG..PRSTATUS_CURSIG + 2].copy_from_slice(&signal.to_le_bytes());
+        desc[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&tid.to_le_bytes());
+        // rip follows 16 other registers in user_regs_struct
+        let rip_offset = PRSTATUS_REG + 16 * 8;
+        desc[rip_offset..rip_offset + 8].copy_from_slice(&rip.to_le_bytes());
+        desc
+    }
+
+    /// Wraps `notes` in an ELF64 header and a single PT_NOTE program header.
+    fn elf_with_notes(notes: &[u8]) -> Vec<u8> {
+        let (phoff, phentsize) = (64, 56);
+        let mut data = vec![0; phoff + phentsize];
+        data[0x20..0x28].copy_from_slice(&(phoff as u64).to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&(phentsize as u16).to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        data[phoff..phoff + 4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        data[phoff + 8..phoff + 16].copy_from_slice(&((phoff + phentsize) as u64).to_le_bytes());
+        data[phoff + 32..phoff + 40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        data.extend_from_slice(notes);
+        data
+    }
+
+    #[test]
+    fn test_read_notes() {
+        let mut notes = note(NT_PRSTATUS, &prstatus(100, 0x401000, 11));
+        // A descriptor that needs padding
+        notes.extend(note(NT_PRPSINFO, b"abcdef"));
+        notes.extend(note(NT_AUXV, &[]));
+        let data = elf_with_notes(&notes);
+        let notes = read_notes(&data).unwrap();
+        let types: Vec<u32> = notes.iter().map(|(note_type, _)| *note_type).collect();
+        assert_eq!(types, vec![NT_PRSTATUS, NT_PRPSINFO, NT_AUXV]);
+        assert_eq!(notes[1].1, b"abcdef");
+        assert!(notes[2].1.is_empty());
+
+        // A note that runs past the end of its segment
+        let mut data = elf_with_notes(&note(NT_PRPSINFO, b"abcdef"));
+        // The descriptor size of the only note
+        data[64 + 56 + 4..64 + 56 + 8].copy_from_slice(&100u32.to_le_bytes());
+        assert!(read_notes(&data).is_none());
+    }
+
+    #[test]
+    fn test_parse_file_note() {
+        let mut desc = Vec::new();
+        for word in &[2u64, 0x1000, 0x400000, 0x401000, 0, 0x7f0000, 0x7f2000, 3] {
+            desc.extend_from_slice(&word.to_le_bytes());
+        }
+        desc.extend_from_slice(b"/bin/prog\0/lib/libc.so.6\0");
+        let files = parse_file_note(&desc).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].start, files[0].end), (0x400000, 0x401000));
+        assert_eq!(files[0].path, "/bin/prog");
+        // The offset is counted in pages
+        assert_eq!(files[1].file_offset, 0x3000);
+        assert_eq!(files[1].path, "/lib/libc.so.6");
+
+        // More entries than paths
+        desc[0..8].copy_from_slice(&3u64.to_le_bytes());
+        assert!(parse_file_note(&desc).is_none());
+    }
+
+    #[test]
+    fn test_parse_threads() {
+        let fxsave = vec![1; 512];
+        let xsave = vec![2; 1024];
+        let first = prstatus(100, 0x401000, 6);
+        let second = prstatus(101, 0x402000, 0);
+        let notes: Vec<(u32, &[u8])> = vec![
+            (NT_PRSTATUS, &first),
+            // The process-wide notes come between the first thread's NT_PRSTATUS and its
+            // register notes
+            (NT_PRPSINFO, b"psinfo"),
+            (NT_PRFPREG, &fxsave),
+            (NT_X86_XSTATE, &xsave),
+            (NT_PRSTATUS, &second),
+            (NT_PRFPREG, &fxsave),
+        ];
+        let threads = parse_threads(&notes).unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].tid, Pid::from_raw(100));
+        assert_eq!(threads[0].regs.rip, 0x401000);
+        assert_eq!(threads[0].signal, 6);
+        assert_eq!(threads[0].xsave.as_deref(), Some(&xsave[..]));
+        assert_eq!(threads[1].tid, Pid::from_raw(101));
+        assert_eq!(threads[1].regs.rip, 0x402000);
+        assert_eq!(threads[1].fxsave.as_deref(), Some(&fxsave[..]));
+        assert_eq!(threads[1].xsave, None);
+
+        let truncated = &first[..PRSTATUS_REG];
+        assert!(parse_threads(&[(NT_PRSTATUS, truncated)]).is_none());
+    }
+}
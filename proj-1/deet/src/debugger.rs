@@ -1,34 +1,112 @@
-use crate::debugger_command::DebuggerCommand;
+use crate::core_file::CoreFile;
+use crate::debugger_command::{apply_handle_flag, parse_signal, split_linespec, DebuggerCommand, ExamineFormat};
+use crate::disassembler;
 use crate::inferior::Inferior;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use crate::dwarf_data::{DwarfData, Error as DwarfError}; // for milestone3
-use crate::inferior::Restorepoint;  // for milestone6
+use crate::dwarf_data::{BaseEncoding, Line, Type, TypeKind};
+use crate::expression::{self, read_int, read_uint, Evaluator, Expr, Value, ValueType};
+use crate::inferior::{self, CatchKind, ForkOptions, Frame, Restorepoint, SignalAction, Status, Tracee, WatchKind};  // for milestone6
+use crate::registers;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use std::collections::HashMap;      // for milestone6
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+/// A user breakpoint. Breakpoints are numbered in the order they were created, starting at 1.
+struct Breakpoint {
+    num: usize,
+    addr: usize,
+    enabled: bool,
+    /// What the user asked for, e.g. `walk` or `vars.c:27`, to find it again after an exec
+    spec: String,
+    /// Only stop when this expression is true, from `break ... if` or `condition`
+    condition: Option<String>,
+    /// How many more hits to let pass, from `ignore`
+    ignore_count: usize,
+    hits: usize,
+    /// Waiting for a shared library that defines `spec` to be loaded; `addr` means nothing
+    pending: bool,
+}
+
+/// A `catch fork`, `catch vfork` or `catch exec`. Catchpoints share their numbers with
+/// breakpoints.
+struct Catchpoint {
+    num: usize,
+    kind: CatchKind,
+    enabled: bool,
+}
+
+/// A hardware watchpoint on `expr`, which lived at `addr` when the watchpoint was set.
+/// Watchpoints share their numbers with breakpoints.
+struct Watchpoint {
+    num: usize,
+    kind: WatchKind,
+    expr: String,
+    addr: usize,
+    value_type: ValueType,
+    old_value: Vec<u8>,
+    enabled: bool,
+}
 
 pub struct Debugger {
     target: String,
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    core: Option<CoreFile>,
     debug_data: DwarfData,      // for milestone3
-    break_list: Vec<usize>,     // for milestone5
+    break_list: Vec<Breakpoint>,    // for milestone5
+    watch_list: Vec<Watchpoint>,
+    catch_list: Vec<Catchpoint>,
+    next_break_num: usize,
     restore_map: HashMap<usize, Restorepoint>,  // for milestone6
+    /// The format and size letters `x` uses when none are given, and where a bare `x` continues
+    examine_format: (char, char),
+    next_examine_addr: Option<usize>,
+    /// Where to look for sources that are no longer where they were compiled, set by `directory`
+    source_dirs: Vec<String>,
+    /// The file and first line a bare `list` shows next, and the %rip it was last centred on
+    list_position: Option<(String, usize)>,
+    list_stop: Option<usize>,
+    /// `set follow-fork-mode child` and `set detach-on-fork`
+    follow_fork_child: bool,
+    detach_on_fork: bool,
+    /// `set disable-randomization`: start the program with address space randomization off, so
+    /// that a position-independent program lands at the same addresses every run
+    disable_randomization: bool,
+    /// What happens to each signal the inferior receives, set by `handle`
+    signal_actions: HashMap<Signal, SignalAction>,
+    /// The frame chosen with `frame`, `up` or `down`, or None for the innermost one. It goes
+    /// back to the innermost frame whenever the tracee moves
+    selected_frame: Option<Frame>,
+    /// Where the dynamic linker keeps its list of loaded libraries (its `_r_debug`)
+    r_debug: Option<usize>,
+    /// `set debug-file-directory`: where to look for the separate debug info of stripped files
+    debug_file_dirs: Vec<String>,
 }
 
 impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
         // TODO (milestone 3): initialize the DwarfData
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
+        // Carry on without a program if need be: `attach` or a core file may still be useful
+        let debug_file_dirs = vec![DEFAULT_DEBUG_FILE_DIR.to_string()];
+        let debug_data = match DwarfData::from_file(target, &debug_file_dirs) {
+            Ok(val) => {
+                report_debug_info(target, &val);
+                val
+            }
             Err(DwarfError::ErrorOpeningFile) => {
                 println!("Could not open file {}", target);
-                std::process::exit(1);
+                DwarfData::empty()
             }
             Err(DwarfError::DwarfFormatError(err)) => {
                 println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
+                DwarfData::empty()
             }
         };
         //debug_data.print(); // for milestone6
@@ -45,9 +123,27 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data,     // for milestone3
             break_list,     // for milestone5
+            watch_list: Vec::new(),
+            catch_list: Vec::new(),
+            next_break_num: 1,
             restore_map,    // for milestone6
+            examine_format: ('x', 'w'),
+            next_examine_addr: None,
+            source_dirs: Vec::new(),
+            list_position: None,
+            list_stop: None,
+            follow_fork_child: false,
+            detach_on_fork: true,
+            disable_randomization: true,
+            signal_actions: Signal::iterator()
+                .map(|signal| (signal, SignalAction::default_for(signal)))
+                .collect(),
+            selected_frame: None,
+            r_debug: None,
+            debug_file_dirs,
         }
     }
 
@@ -57,43 +153,39 @@ impl Debugger {
                 DebuggerCommand::Run(args) => {
                     // for milestone2
                     // kill previous tracee before we run a new tracee
-                    let obj = self.inferior.as_mut();
-                    if let Some(i) = obj {
-                        println!("Killing running inferior (pid {})", i.pid());
-                        i.kill_myself().unwrap(); 
-                    }
+                    self.drop_inferior();
+                    self.core = None;
                     
-                    if let Some(inferior) = Inferior::new(&self.target, &args) {
+                    if let Some(inferior) = Inferior::new(&self.target, &args, self.disable_randomization) {
                         // Create the inferior
                         self.inferior = Some(inferior);
-                        
-                        // milestone5
-                        let tracee = self.inferior.as_mut().unwrap();
-                        tracee.set_breakpoint(
-                            &self.break_list, 
-                            &mut self.restore_map  // milestone6
-                        ).unwrap();
-                        self.break_list.clear();
+                        self.insert_breakpoints();
 
                         // TODO (milestone 1): make the inferior run
                         // You may use self.inferior.as_mut().unwrap() to get a mutable reference
                         // to the Inferior object
-                        if let Ok(status) = tracee.wake_up(&self.restore_map) {
+                        if let Ok((status, watched)) = self.resume() {
                             use crate::inferior::Status;
                             match status {
                                 Status::Exited(exit_code) => {
                                     println!("Child exited (status {})", exit_code);
+                                    self.inferior = None;
                                 },
                                 Status::Signaled(signal) => {
                                     println!("Child got a signal ({})", signal);
+                                    self.inferior = None;
                                 },
                                 Status::Stopped(signal, rip) => {
-                                    println!("Child stopped with signal: {}", signal);
+                                    if !watched {
+                                        println!("Child stopped with signal: {}", signal);
+                                    }
                                     let line = DwarfData::get_line_from_addr(&self.debug_data, rip);
                                     if let Some(i) = line {
                                         println!("Stopped at: {}", i);
                                     }
                                 }
+                                // resume() hands these back as Stopped
+                                Status::Forked(..) | Status::Execed(..) | Status::LibrariesChanged => {}
                             }
                         }
                     } else {
@@ -102,135 +194,2493 @@ impl Debugger {
                 }
                 DebuggerCommand::Quit => {
                     // Kill remaining tracee before exit
-                    let obj = self.inferior.as_mut();
-                    if let Some(i) = obj {
-                        if i.try_wait() {
-                            return;
-                        }
-                        println!("Killing running inferior (pid {})", i.pid());
-                        i.kill_myself().unwrap(); 
-                    }
+                    self.drop_inferior();
                     return;
                 }
+                DebuggerCommand::Attach(pid) => self.attach(pid),
+                DebuggerCommand::Detach => match self.inferior.as_mut() {
+                    Some(tracee) => {
+                        let pid = tracee.pid();
+                        match tracee.detach(&mut self.restore_map) {
+                            Ok(()) => println!("Detaching from program: {}, process {}", self.target, pid),
+                            Err(err) => eprintln!("Could not detach from process {}: {}", pid, err),
+                        }
+                        self.inferior = None;
+                    }
+                    None => eprintln!("The program is not being run."),
+                },
                 DebuggerCommand::Continue => {
                     let obj = self.inferior.as_mut();
-                    if let None = obj {
-                        eprintln!("You need to run a tracee first!");
-                    } else {
-                        let tracee = obj.unwrap();
-                        if let Ok(status) = tracee.wake_up(&self.restore_map) {
+                    if obj.is_some() {
+                        if let Ok((status, watched)) = self.resume() {
                             use crate::inferior::Status;
                             match status {
                                 Status::Exited(exit_code) => {
                                     println!("Continuing... Child exited (status {})", exit_code);
+                                    self.inferior = None;
                                 },
                                 Status::Signaled(signal) => {
                                     println!("Continuing... Child got a signal ({})", signal);
+                                    self.inferior = None;
                                 },
                                 Status::Stopped(signal, rip) => {
-                                    println!("Continuing... Child stopped with signal: {}", signal);
+                                    if !watched {
+                                        println!("Continuing... Child stopped with signal: {}", signal);
+                                    }
                                     let line = DwarfData::get_line_from_addr(&self.debug_data, rip);
                                     if let Some(i) = line {
                                         println!("Stopped at: {}", i);
                                         println!("%rip = {:#x}", rip);
                                     }
                                 },
+                                // resume() hands these back as Stopped
+                                Status::Forked(..) | Status::Execed(..) | Status::LibrariesChanged => {}
                             }
                         }
-                        
-                        if !self.break_list.is_empty() {
-                            tracee.set_breakpoint(
-                                &self.break_list, 
-                                &mut self.restore_map
-                            ).unwrap();
-                            self.break_list.clear();
-                        }
+                    } else {
+                        eprintln!("You need to run a tracee first!");
                     }
                 }
                 DebuggerCommand::Backtrace => {
-                    let obj = self.inferior.as_mut().unwrap();
-                    obj.print_backtrace(&self.debug_data).unwrap();
-                }
-                DebuggerCommand::Break(br_arg) => {
-                    // Check if first char is '*'
-                    let addr_without_0x = if br_arg[..]
-                        .to_lowercase()
-                        .starts_with("*0x") 
-                    {
-                        &br_arg[3..]
-                    } else {
-                        // for milestone7
-                        let br_addr: Option<usize>;
-                        if let Ok(i) = br_arg.parse::<usize>() {
-                            br_addr = DwarfData::get_addr_for_line(
-                                &self.debug_data, 
-                                None, 
-                                i
-                            );
-                        } else {
-                            br_addr = DwarfData::get_addr_for_function(
-                                &self.debug_data, 
-                                None, 
-                                &br_arg[..]
-                            );
+                    match self.tracee() {
+                        Some(obj) => {
+                            if let Err(err) = obj.print_backtrace(&self.debug_data) {
+                                eprintln!("Cannot unwind: {}", err);
+                            }
                         }
-                        if let None = br_addr {
-                            eprintln!("Invalid breakpoint!");
-                            continue;
+                        None => eprintln!("You need to run a tracee first!"),
+                    }
+                }
+                DebuggerCommand::Step => self.step_line(true),
+                DebuggerCommand::Next => self.step_line(false),
+                DebuggerCommand::Finish => self.finish(),
+                DebuggerCommand::StepInstruction(count) => self.step_instruction(count, true),
+                DebuggerCommand::NextInstruction(count) => self.step_instruction(count, false),
+                DebuggerCommand::Disassemble(spec) => self.disassemble(spec.as_deref()),
+                DebuggerCommand::InfoBreakpoints => self.print_breakpoints(),
+                DebuggerCommand::InfoRegisters(names) => self.print_registers(&names, false),
+                DebuggerCommand::InfoAllRegisters(names) => self.print_registers(&names, true),
+                DebuggerCommand::InfoThreads => self.print_threads(),
+                DebuggerCommand::InfoSharedLibrary => self.print_shared_libraries(),
+                DebuggerCommand::InfoSymbol(expr) => self.print_symbol(&expr),
+                DebuggerCommand::Thread(num) => self.select_thread(num),
+                DebuggerCommand::Delete(nums) => {
+                    for num in self.breakpoint_nums(&nums) {
+                        match self.break_list.iter().position(|bp| bp.num == num) {
+                            Some(index) => {
+                                let bp = self.break_list.remove(index);
+                                self.sync_breakpoint(bp.addr);
+                            }
+                            None if self.catch_list.iter().any(|cp| cp.num == num) => {
+                                self.catch_list.retain(|cp| cp.num != num);
+                                self.sync_fork_options();
+                            }
+                            None => {
+                                self.watch_list.retain(|wp| wp.num != num);
+                                self.sync_watchpoints();
+                            }
                         }
-                        self.break_list.push( br_addr.unwrap() );
-                        println!("Set a breakpoint at {:#x}", 
-                                 self.break_list.last().unwrap());
-                        continue;
-                    };
-                    let br_addr = usize::from_str_radix(addr_without_0x, 16).unwrap();
-                    self.break_list.push( br_addr );
-                    println!("Set a breakpoint at {:#x}", 
-                             self.break_list.last().unwrap());
-                    
+                    }
                 }
+                DebuggerCommand::Disable(nums) => self.set_breakpoints_enabled(&nums, false),
+                DebuggerCommand::Enable(nums) => self.set_breakpoints_enabled(&nums, true),
+                DebuggerCommand::Print(expr) => self.print_expression(&expr),
+                DebuggerCommand::SetVar(assignment) => self.set_variable(&assignment),
+                DebuggerCommand::Watch(kind, expr) => self.add_watchpoint(kind, &expr),
+                DebuggerCommand::Examine(format, expr) => self.examine(format, &expr),
+                DebuggerCommand::List(spec) => self.list(spec.as_deref()),
+                DebuggerCommand::Directory(dirs) => self.add_source_directories(&dirs),
+                DebuggerCommand::Break(br_arg, condition) => self.break_at(&br_arg, condition),
+                DebuggerCommand::Condition(num, condition) => self.set_condition(num, condition),
+                DebuggerCommand::Ignore(num, count) => self.set_ignore_count(num, count),
+                DebuggerCommand::Catch(kind) => self.add_catchpoint(kind),
+                DebuggerCommand::SetOption(name, value) => self.set_option(&name, &value),
+                DebuggerCommand::Show(name) => self.show_option(&name),
+                DebuggerCommand::Handle(args) => self.handle_signals(&args),
+                DebuggerCommand::InfoSignals(name) => self.print_signals(name.as_deref()),
+                DebuggerCommand::Frame(level) => self.select_frame(level),
+                DebuggerCommand::Up(count) => self.move_frame(count as isize),
+                DebuggerCommand::Down(count) => self.move_frame(-(count as isize)),
+                DebuggerCommand::InfoLocals => self.print_frame_variables(false),
+                DebuggerCommand::InfoArgs => self.print_frame_variables(true),
             }
         }
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
-        loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
-                Err(ReadlineError::Interrupted) => {
-                    // User pressed ctrl+c. We're going to ignore it
-                    println!("Type \"quit\" to exit");
+    /// Loads the core dump at `path` so that it can be inspected like a stopped inferior.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match CoreFile::open(path) {
+            Ok(core) => core,
+            Err(err) => {
+                eprintln!("Could not load core file {}", err);
+                return;
+            }
+        };
+        if !core.command().is_empty() {
+            println!("Core was generated by `{}'.", core.command());
+        }
+        if let Ok(signal) = nix::sys::signal::Signal::try_from(core.signal()) {
+            println!("Program terminated with signal {}.", signal);
+        }
+        self.selected_frame = None;
+        let rip = core.get_regs().unwrap().rip as usize;
+        self.core = Some(core);
+        self.relocate();
+        self.debug_data.clear_libraries();
+        self.find_dynamic_linker();
+        self.update_shared_libraries();
+        match self.debug_data.get_line_from_addr(rip) {
+            Some(line) => match self.debug_data.get_function_from_addr(rip) {
+                Some(func) => println!("#0  {} ({})", func, line),
+                None => println!("#0  {}", line),
+            },
+            None => println!("#0  {}", format_frame(&self.debug_data, rip)),
+        }
+    }
+
+    /// The program being inspected: the running inferior or, failing that, the core dump.
+    fn tracee(&self) -> Option<&dyn Tracee> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior),
+            (None, Some(core)) => Some(core),
+            (None, None) => None,
+        }
+    }
+
+    /// Kills the inferior if we started it, or detaches from it if we attached to it.
+    fn drop_inferior(&mut self) {
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => return,
+        };
+        if tracee.try_wait() {
+            // It has already exited
+        } else if tracee.is_attached() {
+            println!("Detaching from process {}", tracee.pid());
+            if let Err(err) = tracee.detach(&mut self.restore_map) {
+                eprintln!("Could not detach from process {}: {}", tracee.pid(), err);
+            }
+        } else {
+            println!("Killing running inferior (pid {})", tracee.pid());
+            if let Err(err) = tracee.kill_myself() {
+                eprintln!("Could not kill process {}: {}", tracee.pid(), err);
+            }
+        }
+        self.inferior = None;
+    }
+
+    /// Works out where the tracee's program was loaded by comparing the entry point the kernel
+    /// used with the one in the ELF header, and moves the debug info and every breakpoint and
+    /// watchpoint address by however much that changed since last time. `*ADDR` breakpoints
+    /// were given as absolute addresses and stay put.
+    fn relocate(&mut self) {
+        let entry = match self.tracee().and_then(|tracee| tracee.entry_point()) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let bias = entry.wrapping_sub(self.debug_data.entry_point());
+        let delta = bias.wrapping_sub(self.debug_data.load_bias());
+        if delta == 0 {
+            return;
+        }
+        self.debug_data.set_load_bias(bias);
+        // Breakpoints in shared libraries were made pending before we got here
+        for bp in self.break_list.iter_mut().filter(|bp| !bp.pending && !bp.spec.starts_with('*')) {
+            bp.addr = bp.addr.wrapping_add(delta);
+        }
+        for wp in self.watch_list.iter_mut() {
+            wp.addr = wp.addr.wrapping_add(delta);
+        }
+        self.list_stop = None;
+    }
+
+    /// Inserts every enabled breakpoint and watchpoint into a freshly started or attached
+    /// inferior.
+    fn insert_breakpoints(&mut self) {
+        // Whatever libraries the last process had, this one loads its own
+        let names: Vec<String> = self.debug_data.libraries().iter().map(|lib| lib.name.clone()).collect();
+        self.forget_libraries(&names);
+        self.relocate();
+        let solib_breakpoint = self.find_dynamic_linker();
+        // milestone5
+        // The traps from a previous run died with that process, so insert
+        // every breakpoint afresh
+        let fork_options = self.fork_options();
+        let tracee = self.inferior.as_mut().unwrap();
+        let br_addrs: Vec<usize> = self.break_list
+            .iter()
+            .filter(|bp| bp.enabled && !bp.pending)
+            .map(|bp| bp.addr)
+            .chain(solib_breakpoint)
+            .collect();
+        self.restore_map.clear();
+        tracee.set_breakpoint(
+            &br_addrs, 
+            &mut self.restore_map  // milestone6
+        ).unwrap();
+        tracee.set_solib_breakpoint(solib_breakpoint);
+        tracee.set_fork_options(fork_options);
+        tracee.set_signal_actions(self.signal_actions.clone());
+
+        // Debug registers don't survive the old process either. Watched
+        // addresses are kept, so this is only meaningful for globals
+        for wp in self.watch_list.iter_mut() {
+            if let Ok(bytes) = tracee.read_memory(wp.addr, wp.old_value.len()) {
+                wp.old_value = bytes;
+            }
+        }
+        self.sync_watchpoints();
+        // An attached process has its libraries already
+        self.update_shared_libraries();
+    }
+
+    /// Finds the dynamic linker's `r_debug`, and the `_dl_debug_state` function it calls each
+    /// time it has loaded or unloaded libraries, from the symbols of the dynamic linker the
+    /// program asks for and the address the kernel put it at. Returns where to set a trap
+    /// to hear about library changes, or None for a statically linked program.
+    fn find_dynamic_linker(&mut self) -> Option<usize> {
+        self.r_debug = None;
+        let base = self.tracee()?.interpreter_base()?;
+        let interpreter = self.debug_data.interpreter()?.to_string();
+        // Only its symbol tables are needed
+        let ld_so = match DwarfData::from_file(&interpreter, &[]) {
+            Ok(ld_so) => ld_so,
+            Err(err) => {
+                eprintln!("Could not read the dynamic linker {}: {:?}", interpreter, err);
+                return None;
+            }
+        };
+        let r_debug = ld_so.get_symbol_address("_r_debug")?;
+        let debug_state = ld_so.get_symbol_address("_dl_debug_state")?;
+        self.r_debug = Some(base + r_debug);
+        Some(base + debug_state)
+    }
+
+    /// Brings our list of shared libraries in line with the dynamic linker's: new ones have
+    /// their debug info loaded, and pending breakpoints are tried in them; breakpoints in
+    /// libraries that are gone become pending again.
+    fn update_shared_libraries(&mut self) {
+        let loaded = match (self.r_debug, self.tracee()) {
+            (Some(r_debug), Some(tracee)) => match tracee.link_map(r_debug) {
+                Ok(loaded) => loaded,
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        // The list is only filled in once the dynamic linker has started up
+        if loaded.is_empty() {
+            return;
+        }
+        let gone: Vec<String> = self
+            .debug_data
+            .libraries()
+            .iter()
+            .filter(|lib| !loaded.contains(&(lib.name.clone(), lib.debug_data.load_bias())))
+            .map(|lib| lib.name.clone())
+            .collect();
+        self.forget_libraries(&gone);
+        let mut added = false;
+        for (name, base) in loaded {
+            if self.debug_data.libraries().iter().any(|lib| lib.name == name) {
+                continue;
+            }
+            match self.debug_data.add_library(&name, base, &self.debug_file_dirs) {
+                Ok(()) => added = true,
+                // Such as the vDSO, which has no file
+                Err(DwarfError::ErrorOpeningFile) => {}
+                Err(err) => eprintln!("Could not load symbols for {}: {:?}", name, err),
+            }
+        }
+        if added {
+            self.resolve_pending_breakpoints();
+        }
+    }
+
+    /// Drops the libraries called `names`, making their breakpoints pending. Their traps went
+    /// away with the library's memory, so there is nothing to take out.
+    fn forget_libraries(&mut self, names: &[String]) {
+        for name in names {
+            for bp in self.break_list.iter_mut().filter(|bp| !bp.pending && !bp.spec.starts_with('*')) {
+                let in_library = match self.debug_data.get_library_for_addr(bp.addr) {
+                    Some(lib) => lib.name == *name,
+                    None => false,
+                };
+                if in_library {
+                    self.restore_map.remove(&bp.addr);
+                    bp.pending = true;
                 }
-                Err(ReadlineError::Eof) => {
-                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
+            }
+            self.debug_data.remove_library(name);
+        }
+    }
+
+    /// Tries the pending breakpoints again, now that more libraries are loaded.
+    /// Like `break`, a spec that matches several places gets a breakpoint at each of them.
+    fn resolve_pending_breakpoints(&mut self) {
+        let len = self.break_list.len();
+        for i in 0..len {
+            if !self.break_list[i].pending {
+                continue;
+            }
+            let spec = self.break_list[i].spec.clone();
+            let locations = match self.resolve_breakpoint(&spec) {
+                Ok(locations) => locations,
+                Err(_) => continue,
+            };
+            for (addr, _) in locations {
+                let taken = |bp: &Breakpoint| !bp.pending && bp.spec == spec && bp.addr == addr;
+                if self.break_list.iter().any(taken) {
+                    continue;
+                }
+                // Fill the breakpoints waiting on this spec before making new ones
+                let waiting = (i..len).find(|&j| self.break_list[j].pending && self.break_list[j].spec == spec);
+                match waiting {
+                    Some(j) => {
+                        self.break_list[j].addr = addr;
+                        self.break_list[j].pending = false;
+                    }
+                    None => {
+                        self.add_breakpoint(addr, &spec);
+                        let condition = self.break_list[i].condition.clone();
+                        let enabled = self.break_list[i].enabled;
+                        let bp = self.break_list.last_mut().unwrap();
+                        bp.condition = condition;
+                        bp.enabled = enabled;
+                    }
+                }
+                self.sync_breakpoint(addr);
+            }
+        }
+    }
+
+    /// Implements `info symbol ADDR`: names the function or variable `ADDR` is in, and its
+    /// section, and library if it's in one.
+    fn print_symbol(&self, expr: &str) {
+        let addr = match self.tracee() {
+            Some(tracee) => expression::parse(expr, &self.debug_data).and_then(|parsed| {
+                let evaluator = Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref())?;
+                evaluator.address(&evaluator.evaluate(&parsed)?)
+            }),
+            // Without a process, only addresses and function names can be looked up
+            None => parse_address(expr)
+                .or_else(|| self.debug_data.get_addr_for_function(None, expr))
+                .ok_or_else(|| format!("No symbol \"{}\" in current context.", expr)),
+        };
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let (name, offset) = match self.debug_data.get_symbol_for_addr(addr) {
+            Some(symbol) => symbol,
+            None => {
+                println!("No symbol matches {}.", expr);
+                return;
+            }
+        };
+        let mut text = match offset {
+            0 => name.to_string(),
+            _ => format!("{} + {}", name, offset),
+        };
+        if let Some(section) = self.debug_data.get_section_for_addr(addr) {
+            text += &format!(" in section {}", section);
+        }
+        if let Some(library) = self.debug_data.get_library_for_addr(addr) {
+            text += &format!(" of {}", library.name);
+        }
+        println!("{}", text);
+    }
+
+    /// Implements `info sharedlibrary`.
+    fn print_shared_libraries(&self) {
+        let libraries = self.debug_data.libraries();
+        if libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("From                To                  Syms Read   Shared Object Library");
+        for lib in libraries {
+            let (from, to) = lib.debug_data.text_range();
+            let syms_read = if lib.debug_data.has_debug_info() { "Yes" } else { "Yes (*)" };
+            println!("{:#018x}  {:#018x}  {:<12}{}", from, to, syms_read, lib.name);
+        }
+        if libraries.iter().any(|lib| !lib.debug_data.has_debug_info()) {
+            println!("(*): Shared library is missing debugging information.");
+        }
+    }
+
+    /// Implements `attach` and `deet -p`: stops the running process `pid` and debugs it. If it
+    /// is running a different program than the one we loaded, that program's debug info is
+    /// loaded instead.
+    pub fn attach(&mut self, pid: usize) {
+        self.drop_inferior();
+        self.core = None;
+        let exe = match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().to_string(),
+            Err(err) => {
+                eprintln!("Could not attach to process {}: {}", pid, err);
+                return;
+            }
+        };
+        if exe != self.target {
+            match DwarfData::from_file(&exe, &self.debug_file_dirs) {
+                Ok(debug_data) => {
+                    println!("Reading symbols from {}...", exe);
+                    report_debug_info(&exe, &debug_data);
+                    self.debug_data = debug_data;
+                    self.target = exe;
                 }
                 Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    eprintln!("Could not load debugging symbols from {}: {:?}", exe, err);
+                    return;
                 }
-                Ok(line) => {
-                    if line.trim().len() == 0 {
-                        continue;
-                    }
-                    self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+            }
+        }
+        let inferior = match Inferior::attach(nix::unistd::Pid::from_raw(pid as i32)) {
+            Ok(inferior) => inferior,
+            Err(err) => {
+                eprintln!("Could not attach to process {}: {}", pid, err);
+                eprintln!("(Attaching needs the same user and, with Yama, ptrace_scope 0 or root.)");
+                return;
+            }
+        };
+        println!("Attaching to program: {}, process {}", self.target, pid);
+        self.inferior = Some(inferior);
+        self.selected_frame = None;
+        self.insert_breakpoints();
+        match self.inferior.as_ref().unwrap().get_regs() {
+            Ok(regs) => self.print_step_status(Status::Stopped(
+                nix::sys::signal::Signal::SIGSTOP,
+                regs.rip as usize,
+            )),
+            Err(err) => eprintln!("Could not read registers: {}", err),
+        }
+    }
+
+    /// Implements `break`: `*0x401126`, `42`, `func`, `file.c:42` or `file.c:func`. A name that
+    /// matches several compilation units gets a breakpoint in each of them.
+    fn break_at(&mut self, spec: &str, condition: Option<String>) {
+        if spec.is_empty() {
+            eprintln!("Argument required (location).");
+            return;
+        }
+        if let Some(condition) = &condition {
+            if let Err(err) = self.check_condition(condition) {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+        match self.resolve_breakpoint(spec) {
+            Ok(locations) => {
+                for (addr, note) in locations {
+                    if let Some(note) = note {
+                        println!("{}", note);
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
+                    self.add_breakpoint(addr, spec);
+                    self.break_list.last_mut().unwrap().condition = condition.clone();
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                if can_be_pending(spec) {
+                    self.add_pending_breakpoint(spec);
+                    self.break_list.last_mut().unwrap().condition = condition;
+                }
+            }
+        }
+    }
+
+    /// Makes sure a breakpoint condition at least parses, so mistakes show up when it is set
+    /// rather than each time the breakpoint is hit.
+    fn check_condition(&self, condition: &str) -> Result<(), String> {
+        if condition.trim().is_empty() {
+            return Err("Argument required (boolean expression).".to_string());
+        }
+        expression::parse(condition, &self.debug_data).map(|_| ())
+    }
+
+    /// Implements `condition N [expr]`, which sets or (without `expr`) removes the condition
+    /// of breakpoint N.
+    fn set_condition(&mut self, num: usize, condition: Option<String>) {
+        if let Some(condition) = &condition {
+            if let Err(err) = self.check_condition(condition) {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+        let bp = match self.break_list.iter_mut().find(|bp| bp.num == num) {
+            Some(bp) => bp,
+            None => {
+                eprintln!("No breakpoint number {}.", num);
+                return;
+            }
+        };
+        if condition.is_none() {
+            println!("Breakpoint {} now unconditional.", num);
+        }
+        bp.condition = condition;
+    }
+
+    /// Implements `ignore N count`: breakpoint N doesn't stop the next `count` times it is hit.
+    fn set_ignore_count(&mut self, num: usize, count: usize) {
+        let bp = match self.break_list.iter_mut().find(|bp| bp.num == num) {
+            Some(bp) => bp,
+            None => {
+                eprintln!("No breakpoint number {}.", num);
+                return;
+            }
+        };
+        bp.ignore_count = count;
+        match count {
+            0 => println!("Will stop next time breakpoint {} is reached.", num),
+            1 => println!("Will ignore next crossing of breakpoint {}.", num),
+            _ => println!("Will ignore next {} crossings of breakpoint {}.", count, num),
+        }
+    }
+
+    /// Finds the addresses a `break` location refers to. Each comes with a note to print when
+    /// the line asked for has no code and a later one is used instead.
+    fn resolve_breakpoint(&self, spec: &str) -> Result<Vec<(usize, Option<String>)>, String> {
+        if spec.to_lowercase().starts_with("*0x") {
+            return match usize::from_str_radix(&spec[3..], 16) {
+                Ok(addr) => Ok(vec![(addr, None)]),
+                Err(_) => Err("Invalid breakpoint!".to_string()),
+            };
+        }
+        let (file, rest) = split_linespec(spec);
+        if let Some(file) = file {
+            if self.debug_data.get_file_name(file).is_none() {
+                return Err(format!("No source file named {}.", file));
+            }
+        }
+        if let Ok(number) = rest.parse::<usize>() {
+            let lines = self.debug_data.get_lines_for_line(file, number);
+            if lines.is_empty() {
+                return Err(match file {
+                    Some(file) => format!("No line {} in file \"{}\".", number, file),
+                    None => format!("No line {} in the current file.", number),
+                });
+            }
+            Ok(lines
+                .into_iter()
+                .map(|line| {
+                    let note = if line.number != number {
+                        Some(format!(
+                            "Line {} of \"{}\" has no code, using line {}.",
+                            number, line.file, line.number
+                        ))
                     } else {
-                        println!("Unrecognized command.");
-                    }
+                        None
+                    };
+                    (line.address, note)
+                })
+                .collect())
+        } else {
+            let addrs = self.debug_data.get_addrs_for_function(file, rest);
+            if addrs.is_empty() {
+                return Err(match file {
+                    Some(file) => format!("Function \"{}\" not defined in \"{}\".", rest, file),
+                    None => format!("Function \"{}\" not defined.", rest),
+                });
+            }
+            Ok(addrs.into_iter().map(|addr| (addr, None)).collect())
+        }
+    }
+
+    /// Implements `catch fork`, `catch vfork` and `catch exec`.
+    fn add_catchpoint(&mut self, kind: CatchKind) {
+        let num = self.next_break_num;
+        self.next_break_num += 1;
+        self.catch_list.push(Catchpoint {
+            num,
+            kind,
+            enabled: true,
+        });
+        self.sync_fork_options();
+        println!("Catchpoint {} ({})", num, catch_name(kind));
+    }
+
+    fn fork_options(&self) -> ForkOptions {
+        let catching = |kind| self.catch_list.iter().any(|cp| cp.enabled && cp.kind == kind);
+        ForkOptions {
+            follow_child: self.follow_fork_child,
+            detach_on_fork: self.detach_on_fork,
+            catch_fork: catching(CatchKind::Fork),
+            catch_vfork: catching(CatchKind::Vfork),
+        }
+    }
+
+    /// Tells the running inferior (if any) about changed fork settings or catchpoints.
+    fn sync_fork_options(&mut self) {
+        let options = self.fork_options();
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_fork_options(options);
+        }
+    }
+
+    /// Implements `set <setting> <value>` for the debugger's own settings.
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "follow-fork-mode" => match value {
+                "parent" => self.follow_fork_child = false,
+                "child" => self.follow_fork_child = true,
+                _ => {
+                    eprintln!("Undefined item: \"{}\".", value);
+                    return;
+                }
+            },
+            "detach-on-fork" => match parse_on_off(value) {
+                Some(on) => self.detach_on_fork = on,
+                None => {
+                    eprintln!("\"on\" or \"off\" expected.");
+                    return;
+                }
+            },
+            "disable-randomization" => match parse_on_off(value) {
+                Some(on) => self.disable_randomization = on,
+                None => eprintln!("\"on\" or \"off\" expected."),
+            },
+            "debug-file-directory" => {
+                self.debug_file_dirs = value.split(':').map(|dir| dir.to_string()).collect();
+                self.reload_symbols();
+            }
+            _ => {
+                eprintln!("Undefined set command: \"{}\".", name);
+                return;
+            }
+        }
+        self.sync_fork_options();
+    }
+
+    /// Implements `show <setting>`.
+    fn show_option(&self, name: &str) {
+        match name {
+            "follow-fork-mode" => println!(
+                "Debugger response to a program call of fork or vfork is \"{}\".",
+                if self.follow_fork_child { "child" } else { "parent" }
+            ),
+            "detach-on-fork" => println!(
+                "Whether deet will detach the child of a fork is {}.",
+                if self.detach_on_fork { "on" } else { "off" }
+            ),
+            "disable-randomization" => println!(
+                "Disabling randomization of debuggee's virtual address space is {}.",
+                if self.disable_randomization { "on" } else { "off" }
+            ),
+            "debug-file-directory" => println!(
+                "The directory where separate debug symbols are searched for is \"{}\".",
+                self.debug_file_dirs.join(":")
+            ),
+            _ => eprintln!("Undefined show command: \"{}\".", name),
+        }
+    }
+
+    /// After `set debug-file-directory`, looks again for the separate debug info of a program
+    /// that had none. Once it is running, the new directories only apply to what gets loaded
+    /// from then on.
+    fn reload_symbols(&mut self) {
+        if self.debug_data.has_debug_info() || self.tracee().is_some() {
+            return;
+        }
+        if let Ok(debug_data) = DwarfData::from_file(&self.target, &self.debug_file_dirs) {
+            if debug_data.has_debug_info() {
+                println!("Reading symbols from {}...", self.target);
+                report_debug_info(&self.target, &debug_data);
+                self.debug_data = debug_data;
+                self.resolve_pending_breakpoints();
+            }
+        }
+    }
+
+    /// Implements `handle SIG [stop|nostop] [print|noprint] [pass|nopass]`, `SIG` being a signal
+    /// name with or without the `SIG`, or `all`. Prints the resulting table entries.
+    fn handle_signals(&mut self, args: &[String]) {
+        let signals: Vec<Signal> = if args[0] == "all" {
+            // The debugger's own signals are left alone
+            Signal::iterator()
+                .filter(|&signal| signal != Signal::SIGTRAP && signal != Signal::SIGINT)
+                .collect()
+        } else {
+            match parse_signal(&args[0]) {
+                Some(Signal::SIGTRAP) => {
+                    eprintln!("SIGTRAP is used by the debugger.");
+                    return;
+                }
+                Some(signal) => vec![signal],
+                None => {
+                    eprintln!("Unrecognized or ambiguous flag word: \"{}\".", args[0]);
+                    return;
+                }
+            }
+        };
+        for word in &args[1..] {
+            for signal in &signals {
+                let action = self.signal_actions.get_mut(signal).unwrap();
+                if !apply_handle_flag(action, word) {
+                    eprintln!("Unrecognized or ambiguous flag word: \"{}\".", word);
+                    return;
+                }
+            }
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_signal_actions(self.signal_actions.clone());
+        }
+        print_signal_header();
+        for signal in signals {
+            print_signal_row(signal, &self.signal_actions[&signal]);
+        }
+    }
+
+    /// Implements `info signals [SIG]`.
+    fn print_signals(&self, name: Option<&str>) {
+        let signals: Vec<Signal> = match name {
+            Some(name) => match parse_signal(name) {
+                Some(signal) => vec![signal],
+                None if name.parse::<i32>().is_ok() => {
+                    eprintln!("Only signals 1-15 are valid as numeric signals.");
+                    return;
+                }
+                None => {
+                    eprintln!("Unrecognized or ambiguous flag word: \"{}\".", name);
+                    return;
+                }
+            },
+            None => Signal::iterator().collect(),
+        };
+        print_signal_header();
+        for signal in signals {
+            print_signal_row(signal, &self.signal_actions[&signal]);
+        }
+    }
+
+    /// Called when the inferior has exec'd: loads the new program's debug info, and moves the
+    /// breakpoints over to it, since the old image and our traps in it are gone.
+    fn follow_exec(&mut self) {
+        let pid = self.inferior.as_ref().unwrap().pid();
+        let exe = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().to_string(),
+            Err(_) => self.target.clone(),
+        };
+        println!("process {} is executing new program: {}", pid, exe);
+        let loaded = match DwarfData::from_file(&exe, &self.debug_file_dirs) {
+            Ok(debug_data) => {
+                report_debug_info(&exe, &debug_data);
+                self.debug_data = debug_data;
+                true
+            }
+            Err(err) => {
+                eprintln!("Could not load debugging symbols from {}: {:?}", exe, err);
+                false
+            }
+        };
+        self.target = exe;
+        for i in 0..self.break_list.len() {
+            let bp = &self.break_list[i];
+            // Raw addresses are taken to mean the same thing in the new program
+            if bp.spec.starts_with('*') {
+                continue;
+            }
+            let nth = self.break_list[..i].iter().filter(|other| other.spec == bp.spec).count();
+            let location = if loaded {
+                self.resolve_breakpoint(&bp.spec)
+                    .and_then(|locations| locations.get(nth).map(|l| l.0).ok_or_else(String::new))
+            } else {
+                Err(String::new())
+            };
+            match location {
+                Ok(addr) => {
+                    self.break_list[i].addr = addr;
+                    self.break_list[i].pending = false;
                 }
+                // It may turn up in one of the new program's libraries
+                Err(_) if can_be_pending(&bp.spec) => self.break_list[i].pending = true,
+                Err(err) => {
+                    eprintln!("Error in re-setting breakpoint {}: {}", bp.num, err);
+                    self.break_list[i].enabled = false;
+                }
+            }
+        }
+        for wp in self.watch_list.drain(..) {
+            println!("Watchpoint {} deleted because the program it watched has been replaced.", wp.num);
+        }
+        self.insert_breakpoints();
+    }
+
+    /// Handles the inferior stopping at a fork or exec: after an exec, the new program is
+    /// loaded. If a catchpoint caught the event, says so and returns where the inferior is
+    /// stopped.
+    fn report_catch(&mut self, status: &Status) -> Option<usize> {
+        let (kind, rip, what) = match *status {
+            Status::Forked(kind, pid, rip) => {
+                let verb = if kind == CatchKind::Vfork { "vforked" } else { "forked" };
+                (kind, rip, format!("{} process {}", verb, pid))
+            }
+            Status::Execed(rip) => {
+                self.follow_exec();
+                (CatchKind::Exec, rip, format!("exec'd {}", self.target))
+            }
+            _ => return None,
+        };
+        let cp = self.catch_list.iter().find(|cp| cp.enabled && cp.kind == kind)?;
+        println!("\nCatchpoint {} ({})", cp.num, what);
+        Some(rip)
+    }
+
+    /// Records a breakpoint. If the inferior is already running, the trap is written into it
+    /// right away rather than waiting for the next `run`.
+    fn add_breakpoint(&mut self, addr: usize, spec: &str) {
+        let num = self.next_break_num;
+        self.next_break_num += 1;
+        self.break_list.push(Breakpoint {
+            num,
+            addr,
+            enabled: true,
+            spec: spec.to_string(),
+            condition: None,
+            ignore_count: 0,
+            hits: 0,
+            pending: false,
+        });
+        self.sync_breakpoint(addr);
+        println!("Set breakpoint {} at {:#x}", num, addr);
+    }
+
+    /// Records a breakpoint on something no loaded library has yet.
+    fn add_pending_breakpoint(&mut self, spec: &str) {
+        let num = self.next_break_num;
+        self.next_break_num += 1;
+        self.break_list.push(Breakpoint {
+            num,
+            addr: 0,
+            enabled: true,
+            spec: spec.to_string(),
+            condition: None,
+            ignore_count: 0,
+            hits: 0,
+            pending: true,
+        });
+        println!("Breakpoint {} ({}) pending.", num, spec);
+    }
+
+    /// Makes the trap at `addr` in the running inferior (if any) match the breakpoint table: it
+    /// is inserted if some enabled breakpoint lives there, and removed otherwise.
+    fn sync_breakpoint(&mut self, addr: usize) {
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => return,
+        };
+        let result = if self.break_list.iter().any(|bp| bp.enabled && !bp.pending && bp.addr == addr) {
+            tracee.set_breakpoint(&vec![addr], &mut self.restore_map).map(|_| ())
+        } else {
+            tracee.remove_breakpoint(addr, &mut self.restore_map)
+        };
+        if let Err(err) = result {
+            eprintln!("Could not update breakpoint at {:#x}: {}", addr, err);
+        }
+    }
+
+    /// Validates the breakpoint numbers given to delete/enable/disable. An empty list means
+    /// every breakpoint.
+    fn breakpoint_nums(&self, nums: &[usize]) -> Vec<usize> {
+        let all_nums = self
+            .break_list
+            .iter()
+            .map(|bp| bp.num)
+            .chain(self.watch_list.iter().map(|wp| wp.num))
+            .chain(self.catch_list.iter().map(|cp| cp.num));
+        if nums.is_empty() {
+            return all_nums.collect();
+        }
+        let all_nums: Vec<usize> = all_nums.collect();
+        nums.iter()
+            .filter(|num| {
+                let exists = all_nums.contains(num);
+                if !exists {
+                    eprintln!("No breakpoint number {}.", num);
+                }
+                exists
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn set_breakpoints_enabled(&mut self, nums: &[usize], enabled: bool) {
+        for num in self.breakpoint_nums(nums) {
+            if let Some(bp) = self.break_list.iter_mut().find(|bp| bp.num == num) {
+                bp.enabled = enabled;
+                let addr = bp.addr;
+                self.sync_breakpoint(addr);
+                continue;
+            }
+            if let Some(cp) = self.catch_list.iter_mut().find(|cp| cp.num == num) {
+                cp.enabled = enabled;
+                self.sync_fork_options();
+                continue;
             }
+            let wp = self.watch_list.iter_mut().find(|wp| wp.num == num).unwrap();
+            wp.enabled = enabled;
+            if self.watch_slots().len() > MAX_WATCH_SLOTS {
+                eprintln!("Not enough hardware debug registers to enable watchpoint {}.", num);
+                self.watch_list.iter_mut().find(|wp| wp.num == num).unwrap().enabled = false;
+            }
+            self.sync_watchpoints();
+        }
+    }
+
+    fn print_breakpoints(&self) {
+        if self.break_list.is_empty() && self.watch_list.is_empty() && self.catch_list.is_empty() {
+            println!("No breakpoints.");
+            return;
         }
+        let mut rows: Vec<(usize, bool, String, String)> = self
+            .break_list
+            .iter()
+            .map(|bp| {
+                let mut details = String::new();
+                if let Some(condition) = &bp.condition {
+                    details += &format!("\n\tstop only if {}", condition);
+                }
+                match bp.hits {
+                    0 => {}
+                    1 => details += "\n\tbreakpoint already hit 1 time",
+                    hits => details += &format!("\n\tbreakpoint already hit {} times", hits),
+                }
+                if bp.ignore_count > 0 {
+                    details += &format!("\n\tWill ignore next {} crossings of breakpoint.", bp.ignore_count);
+                }
+                if bp.pending {
+                    return (bp.num, bp.enabled, "<PENDING>".to_string(), bp.spec.clone() + &details);
+                }
+                let what = match (
+                    self.debug_data.get_function_from_addr(bp.addr),
+                    self.debug_data.get_line_from_addr(bp.addr),
+                ) {
+                    (Some(func), Some(line)) => format!("in {} at {}", func, line),
+                    (Some(func), None) => format!("in {}", func),
+                    _ => String::new(),
+                };
+                (bp.num, bp.enabled, format!("{:#018x}", bp.addr), what + &details)
+            })
+            .collect();
+        for wp in &self.watch_list {
+            let what = match wp.kind {
+                WatchKind::Write => "hw watchpoint",
+                WatchKind::Read => "read watchpoint",
+                WatchKind::Access => "acc watchpoint",
+            };
+            rows.push((wp.num, wp.enabled, format!("{:#018x}", wp.addr), format!("{} {}", what, wp.expr)));
+        }
+        for cp in &self.catch_list {
+            rows.push((cp.num, cp.enabled, String::new(), format!("catchpoint {}", catch_name(cp.kind))));
+        }
+        rows.sort_by_key(|row| row.0);
+        println!("Num     Enb Address            What");
+        for (num, enabled, addr, what) in rows {
+            println!(
+                "{:<7} {:<3} {:<18} {}",
+                num,
+                if enabled { "y" } else { "n" },
+                addr,
+                what
+            );
+        }
+    }
+
+    /// Implements `watch`, `rwatch` and `awatch`: evaluates `expr` to find the memory to watch
+    /// and programs a debug register for it. A bare address such as `0x404040` watches the int
+    /// there.
+    fn add_watchpoint(&mut self, kind: WatchKind, expr: &str) {
+        let tracee = match self.inferior.as_ref() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let evaluate = |expr: &str| {
+            let parsed = expression::parse(expr, &self.debug_data)?;
+            Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref())?.evaluate(&parsed)
+        };
+        let value = match evaluate(expr) {
+            Ok(Value { addr: None, .. }) => evaluate(&format!("*(int *)({})", expr)),
+            other => other,
+        };
+        let value = match value {
+            Ok(value) => value,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let num = self.next_break_num;
+        self.watch_list.push(Watchpoint {
+            num,
+            kind,
+            expr: expr.to_string(),
+            addr: value.addr.unwrap(),
+            value_type: value.value_type,
+            old_value: value.bytes,
+            enabled: true,
+        });
+        if self.watch_slots().len() > MAX_WATCH_SLOTS {
+            self.watch_list.pop();
+            eprintln!("Not enough hardware debug registers to watch {}.", expr);
+            return;
+        }
+        self.next_break_num += 1;
+        self.sync_watchpoints();
+        let what = match kind {
+            WatchKind::Write => "Hardware watchpoint",
+            WatchKind::Read => "Hardware read watchpoint",
+            WatchKind::Access => "Hardware access (read/write) watchpoint",
+        };
+        println!("{} {}: {}", what, num, expr);
+    }
+
+    /// Splits the enabled watchpoints into debug register slots, as (watchpoint number, addr,
+    /// len, kind).
+    fn watch_slots(&self) -> Vec<(usize, usize, usize, WatchKind)> {
+        self.watch_list
+            .iter()
+            .filter(|wp| wp.enabled)
+            .flat_map(|wp| {
+                watch_chunks(wp.addr, wp.value_type.size())
+                    .into_iter()
+                    .map(move |(addr, len)| (wp.num, addr, len, wp.kind))
+            })
+            .collect()
+    }
+
+    /// Programs the running inferior's (if any) debug registers to match the watchpoint table.
+    fn sync_watchpoints(&mut self) {
+        let slots: Vec<(usize, usize, WatchKind)> = self
+            .watch_slots()
+            .into_iter()
+            .map(|(_, addr, len, kind)| (addr, len, kind))
+            .collect();
+        if let Some(tracee) = self.inferior.as_mut() {
+            if let Err(err) = tracee.set_watchpoints(&slots) {
+                eprintln!("Could not set hardware watchpoints: {}", err);
+            }
+        }
+    }
+
+    /// After a SIGTRAP, checks DR6 for triggered watchpoints and reports their values. Returns
+    /// `Some(true)` if something was reported, and `Some(false)` if watchpoints triggered but
+    /// nothing is worth stopping for: a write that didn't change the value, or a write seen by a
+    /// read watchpoint.
+    fn report_watchpoints(&mut self) -> Result<Option<bool>, nix::Error> {
+        let slots = self.watch_slots();
+        let debug_data = &self.debug_data;
+        let tracee = self.inferior.as_mut().unwrap();
+        let hits = tracee.take_watchpoint_hits()?;
+        if hits.is_empty() {
+            return Ok(None);
+        }
+        let mut hit_nums: Vec<usize> = hits.iter().filter_map(|&i| slots.get(i)).map(|slot| slot.0).collect();
+        hit_nums.dedup();
+        let mut reported = false;
+        for num in hit_nums {
+            let wp = self.watch_list.iter_mut().find(|wp| wp.num == num).unwrap();
+            let new_value = tracee.read_memory(wp.addr, wp.old_value.len())?;
+            let changed = new_value != wp.old_value;
+            let old = Value {
+                value_type: wp.value_type.clone(),
+                bytes: std::mem::replace(&mut wp.old_value, new_value.clone()),
+                addr: Some(wp.addr),
+            };
+            let new = Value {
+                bytes: new_value,
+                ..old.clone()
+            };
+            let format = |value: &Value| format_result(debug_data, tracee, value);
+            match (wp.kind, changed) {
+                (WatchKind::Write, false) | (WatchKind::Read, true) => continue,
+                (WatchKind::Write, true) => {
+                    println!("\nHardware watchpoint {}: {}\n", num, wp.expr);
+                    println!("Old value = {}\nNew value = {}", format(&old), format(&new));
+                }
+                (WatchKind::Read, false) => {
+                    println!("\nHardware read watchpoint {}: {}\n", num, wp.expr);
+                    println!("Value = {}", format(&new));
+                }
+                (WatchKind::Access, true) => {
+                    println!("\nHardware access (read/write) watchpoint {}: {}\n", num, wp.expr);
+                    println!("Old value = {}\nNew value = {}", format(&old), format(&new));
+                }
+                (WatchKind::Access, false) => {
+                    println!("\nHardware access (read/write) watchpoint {}: {}\n", num, wp.expr);
+                    println!("Value = {}", format(&new));
+                }
+            }
+            reported = true;
+        }
+        Ok(Some(reported))
+    }
+
+    /// Continues the inferior until it stops somewhere worth telling the user about. Returns the
+    /// status, and whether the stop was a watchpoint that has already been reported.
+    fn resume(&mut self) -> Result<(Status, bool), nix::Error> {
+        self.selected_frame = None;
+        loop {
+            let tracee = self.inferior.as_mut().unwrap();
+            let (break_list, debug_data) = (&mut self.break_list, &self.debug_data);
+            let status = tracee.wake_up(&self.restore_map, &mut |tracee: &Inferior, addr| {
+                breakpoint_hit(break_list, debug_data, tracee, addr)
+            })?;
+            if let Status::LibrariesChanged = status {
+                self.update_shared_libraries();
+                continue;
+            }
+            if let Status::Forked(..) | Status::Execed(..) = status {
+                match self.report_catch(&status) {
+                    Some(rip) => {
+                        return Ok((Status::Stopped(Signal::SIGTRAP, rip), true))
+                    }
+                    None => continue,
+                }
+            }
+            if let Status::Stopped(Signal::SIGTRAP, _) = status {
+                if self.watch_list.is_empty() {
+                    return Ok((status, false));
+                }
+                match self.report_watchpoints()? {
+                    Some(true) => return Ok((status, true)),
+                    Some(false) => continue,
+                    None => {}
+                }
+            }
+            return Ok((status, false));
+        }
+    }
+
+    /// Implements `info threads`, marking the selected thread with a `*`.
+    fn print_threads(&self) {
+        let rows: Vec<(bool, usize, String, Option<usize>)> = match (&self.inferior, &self.core) {
+            (Some(inferior), _) => inferior
+                .threads()
+                .iter()
+                .map(|thread| {
+                    let rip = inferior.get_thread_regs(thread.tid).ok().map(|regs| regs.rip as usize);
+                    (thread.tid == inferior.tid(), thread.num, format!("LWP {}", thread.tid), rip)
+                })
+                .collect(),
+            (None, Some(core)) => core
+                .threads()
+                .iter()
+                .enumerate()
+                .map(|(i, thread)| {
+                    let rip = Some(thread.regs.rip as usize);
+                    (i + 1 == core.current_thread(), i + 1, format!("LWP {}", thread.tid), rip)
+                })
+                .collect(),
+            (None, None) => {
+                println!("No threads.");
+                return;
+            }
+        };
+        println!("  Id   Target Id        Frame");
+        for (current, num, target_id, rip) in rows {
+            let frame = match rip {
+                Some(rip) => format_frame(&self.debug_data, rip),
+                None => "(running)".to_string(),
+            };
+            let marker = if current { "*" } else { " " };
+            println!("{} {:<5}{:<17}{}", marker, num, target_id, frame);
+        }
+    }
+
+    /// Implements `thread N`, which selects the thread that `info registers`, `backtrace`,
+    /// `print` and stepping work on. A bare `thread` says which one is selected.
+    fn select_thread(&mut self, num: Option<usize>) {
+        let (current, tid) = match self.selected_thread() {
+            Some(selected) => selected,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let num = match num {
+            Some(num) => num,
+            None => {
+                println!("[Current thread is {} (LWP {})]", current, tid);
+                return;
+            }
+        };
+        let found = match (self.inferior.as_mut(), self.core.as_mut()) {
+            (Some(inferior), _) => inferior.select_thread(num),
+            (None, Some(core)) => core.select_thread(num),
+            (None, None) => false,
+        };
+        if !found {
+            eprintln!("Invalid thread ID: {}", num);
+            return;
+        }
+        self.selected_frame = None;
+        if let Some((_, tid)) = self.selected_thread() {
+            println!("[Switching to thread {} (LWP {})]", num, tid);
+        }
+        if let Some(Ok(regs)) = self.tracee().map(|tracee| tracee.get_regs()) {
+            println!("#0  {}", format_frame(&self.debug_data, regs.rip as usize));
+        }
+    }
+
+    /// The number and thread id of the selected thread, of the inferior or else the core dump.
+    fn selected_thread(&self) -> Option<(usize, Pid)> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some((inferior.current_thread(), inferior.tid())),
+            (None, Some(core)) => Some((core.current_thread(), core.tid())),
+            (None, None) => None,
+        }
+    }
+
+    /// Implements `frame [N]`: selects frame N of the backtrace, or with no argument shows the
+    /// selected frame.
+    fn select_frame(&mut self, level: Option<usize>) {
+        let frames = match self.frames() {
+            Some(frames) => frames,
+            None => return,
+        };
+        let level = level.unwrap_or_else(|| self.selected_frame.as_ref().map_or(0, |frame| frame.level));
+        match frames.into_iter().nth(level) {
+            Some(frame) => {
+                self.print_frame(&frame);
+                self.selected_frame = Some(frame);
+            }
+            None => eprintln!("No frame at level {}.", level),
+        }
+    }
+
+    /// Implements `up` (positive `count`) and `down` (negative), which move towards the
+    /// outermost and innermost frame respectively, stopping at the last one.
+    fn move_frame(&mut self, count: isize) {
+        let frames = match self.frames() {
+            Some(frames) => frames,
+            None => return,
+        };
+        let current = self.selected_frame.as_ref().map_or(0, |frame| frame.level);
+        if count > 0 && current + 1 >= frames.len() {
+            eprintln!("Initial frame selected; you cannot go up.");
+            return;
+        }
+        if count < 0 && current == 0 {
+            eprintln!("Bottom (innermost) frame selected; you cannot go down.");
+            return;
+        }
+        let level = (current as isize + count).max(0).min(frames.len() as isize - 1);
+        let frame = frames.into_iter().nth(level as usize).unwrap();
+        self.print_frame(&frame);
+        self.selected_frame = Some(frame);
+    }
+
+    /// The tracee's stack frames, innermost first, or None (after saying why) if there aren't any.
+    fn frames(&self) -> Option<Vec<Frame>> {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return None;
+            }
+        };
+        match tracee.frames(&self.debug_data) {
+            Ok(frames) => Some(frames),
+            Err(err) => {
+                eprintln!("Could not unwind the stack: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Prints a frame the way `backtrace` does, followed by the source line it is at.
+    fn print_frame(&self, frame: &Frame) {
+        println!("#{:<3}{}", frame.level, frame);
+        if let Some(line) = &frame.line {
+            let text = self.read_source(&line.file).and_then(|lines| {
+                line.number
+                    .checked_sub(1)
+                    .and_then(|i| lines.get(i))
+                    .cloned()
+            });
+            if let Some(text) = text {
+                println!("{}\t{}", line.number, text);
+            }
+        }
+    }
+
+    /// Implements `info locals` (or, with `args` set, `info args`): prints the local variables
+    /// or the arguments of the selected frame's function.
+    fn print_frame_variables(&self, args: bool) {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let evaluator = match Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref()) {
+            Ok(evaluator) => evaluator,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+        let addr = match &self.selected_frame {
+            Some(frame) => frame.regs.lookup_addr(frame.level == 0),
+            None => tracee.get_regs().map(|regs| regs.rip as usize).unwrap_or(0),
+        };
+        let variables: Vec<_> = match self.debug_data.get_function_for_addr(addr) {
+            Some(func) => func.variables.iter().filter(|var| var.is_parameter == args).collect(),
+            None => {
+                eprintln!("No symbol table info available.");
+                return;
+            }
+        };
+        if variables.is_empty() {
+            println!("{}", if args { "No arguments." } else { "No locals." });
+        }
+        for var in variables {
+            match evaluator.variable_value(var) {
+                Ok(value) => println!("{} = {}", var.name, format_result(&self.debug_data, tracee, &value)),
+                Err(err) => println!("{} = <error: {}>", var.name, err),
+            }
+        }
+    }
+
+    /// Implements `print`: evaluates the C expression `expr` in the selected frame and prints its
+    /// value.
+    fn print_expression(&self, expr: &str) {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let value = expression::parse(expr, &self.debug_data).and_then(|parsed| {
+            Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref())?.evaluate(&parsed)
+        });
+        let value = match value {
+            Ok(value) => value,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        println!("{} = {}", expr, format_result(&self.debug_data, tracee, &value));
+    }
+
+    /// Implements `x`: prints `format.count` units of memory starting at the address `expr`
+    /// evaluates to, or where the previous `x` stopped if `expr` is empty.
+    fn examine(&mut self, format: ExamineFormat, expr: &str) {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let addr = if expr.is_empty() {
+            self.next_examine_addr
+                .ok_or("Argument required (starting display address).".to_string())
+        } else {
+            expression::parse(expr, &self.debug_data).and_then(|parsed| {
+                let evaluator = Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref())?;
+                evaluator.address(&evaluator.evaluate(&parsed)?)
+            })
+        };
+        let mut addr = match addr {
+            Ok(addr) => addr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let letter = format.format.unwrap_or(self.examine_format.0);
+        let size = match (format.size, letter) {
+            (Some(size), _) => size,
+            (None, 'a') | (None, 'f') => 'g',
+            (None, 'c') => 'b',
+            (None, _) => self.examine_format.1,
+        };
+
+        // Show the program's own bytes rather than our traps
+        let live = self.inferior.is_some();
+        let restore_map = &self.restore_map;
+        let read = |addr: usize, len: usize| {
+            let mut bytes = tracee.read_memory(addr, len)?;
+            if live {
+                inferior::restore_original_bytes(restore_map, addr, &mut bytes);
+            }
+            Ok::<Vec<u8>, nix::Error>(bytes)
+        };
+        let rip = tracee.get_regs().map(|regs| regs.rip as usize).unwrap_or(0);
+        let label = |addr: usize| format!("{:#x}{}", addr, format_symbol(&self.debug_data, addr));
+
+        let unit = match size {
+            'b' => 1,
+            'h' => 2,
+            'w' => 4,
+            _ => 8,
+        };
+        let per_line = match letter {
+            's' | 'i' => 1,
+            _ => 16 / unit.max(2),
+        };
+        let mut remaining = format.count;
+        while remaining > 0 {
+            let line = match letter {
+                's' => read_c_string_at(&read, addr).map(|(text, len)| {
+                    let line = format!("{}:\t{}", label(addr), text);
+                    addr += len;
+                    line
+                }),
+                'i' => read_instruction(&read, addr).map(|bytes| {
+                    let insn = disassembler::decode(&bytes, addr);
+                    let marker = if insn.addr == rip { "=> " } else { "   " };
+                    let line = format!(
+                        "{}{}:\t{}",
+                        marker,
+                        label(addr),
+                        format_instruction(&self.debug_data, &insn)
+                    );
+                    addr += insn.len;
+                    line
+                }),
+                _ => {
+                    let count = remaining.min(per_line);
+                    read(addr, count * unit).map(|bytes| {
+                        let units: Vec<String> = bytes
+                            .chunks(unit)
+                            .map(|bytes| format_unit(&self.debug_data, letter, bytes))
+                            .collect();
+                        let line = format!("{}:\t{}", label(addr), units.join("\t"));
+                        addr += count * unit;
+                        line
+                    })
+                }
+            };
+            match line {
+                Ok(line) => println!("{}", line),
+                Err(_) => {
+                    println!("Cannot access memory at address {:#x}", addr);
+                    break;
+                }
+            }
+            remaining -= match letter {
+                's' | 'i' => 1,
+                _ => remaining.min(per_line),
+            };
+        }
+
+        self.examine_format = match letter {
+            's' | 'i' => (letter, self.examine_format.1),
+            _ => (letter, size),
+        };
+        self.next_examine_addr = Some(addr);
+    }
+
+    /// Implements `info registers` (`all` unset) and `info all-registers`. If `names` is
+    /// non-empty only those registers are shown, otherwise the general purpose registers and,
+    /// for `all`, the floating point and vector registers too.
+    fn print_registers(&self, names: &[String], all: bool) {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let regs = match tracee.get_regs() {
+            Ok(regs) => regs,
+            Err(err) => {
+                println!("Could not read registers: {}", err);
+                return;
+            }
+        };
+        let fp_rows = || match tracee.get_fpregs() {
+            Ok(fp) => registers::format_fp_registers(&fp),
+            Err(err) => {
+                println!("Could not read floating point registers: {}", err);
+                Vec::new()
+            }
+        };
+
+        let mut rows = Vec::new();
+        if names.is_empty() {
+            for name in registers::GENERAL_REGISTERS.iter().chain(registers::BASE_REGISTERS.iter()) {
+                let (value, size) = registers::get_register(&regs, name).unwrap();
+                rows.push(self.format_register(name, value, size));
+            }
+            if all {
+                rows.extend(fp_rows());
+            }
+        } else {
+            let mut fp = None;
+            for name in names {
+                let name = name.trim_start_matches('$');
+                if let Some((value, size)) = registers::get_register(&regs, name) {
+                    rows.push(self.format_register(name, value, size));
+                    continue;
+                }
+                let fp = fp.get_or_insert_with(fp_rows);
+                match fp.iter().find(|row| row.0 == name) {
+                    Some(row) => rows.push(row.clone()),
+                    None => {
+                        println!("Invalid register `{}'", name);
+                        return;
+                    }
+                }
+            }
+        }
+        for (name, raw, natural) in rows {
+            println!("{}", format!("{:<15}{:<19}{}", name, raw, natural).trim_end());
+        }
+    }
+
+    /// Formats a general purpose register for `info registers`: its name, its value in hex and
+    /// its value in the register's natural form.
+    fn format_register(&self, name: &str, value: u64, size: usize) -> (String, String, String) {
+        let natural = match name {
+            "rip" | "pc" => format!("{:#x}{}", value, format_symbol(&self.debug_data, value as usize)),
+            "rsp" | "rbp" | "sp" | "fp" => format!("{:#x}", value),
+            "eflags" => registers::format_eflags(value),
+            // Sign extend from the register's size
+            _ => (((value << (64 - 8 * size)) as i64) >> (64 - 8 * size)).to_string(),
+        };
+        (name.to_string(), format!("{:#x}", value), natural)
+    }
+
+    /// Implements `set $reg = value`: converts `value` to the register's type and writes it
+    /// with PTRACE_SETREGS.
+    fn set_register(&mut self, name: &str, rhs: &Expr) {
+        let debug_data = &self.debug_data;
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let result = Evaluator::new(debug_data, tracee).and_then(|evaluator| {
+            let target = evaluator.evaluate(&Expr::Var(format!("${}", name)))?;
+            evaluator.convert(&evaluator.evaluate(rhs)?, &target.value_type)
+        });
+        let bytes = match result {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let result = tracee.get_regs().and_then(|mut regs| {
+            registers::set_register(&mut regs, name, read_uint(&bytes)).unwrap();
+            tracee.set_regs(regs)
+        });
+        if let Err(err) = result {
+            println!("Could not set register ${}: {}", name, err);
+        }
+    }
+
+    /// Implements `set var`: evaluates an assignment `lvalue = expr` and writes the converted
+    /// value into the inferior's memory. Assignments to `$reg` go to `set_register`.
+    fn set_variable(&mut self, assignment: &str) {
+        if let Ok(Expr::Assign(lhs, rhs)) = expression::parse(assignment, &self.debug_data) {
+            if let Expr::Var(name) = *lhs {
+                if name.starts_with('$') {
+                    self.set_register(&name[1..], &rhs);
+                    return;
+                }
+            }
+        }
+
+        let debug_data = &self.debug_data;
+        let selected_frame = self.selected_frame.as_ref();
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let result = expression::parse(assignment, debug_data).and_then(|parsed| {
+            let (lhs, rhs) = match parsed {
+                Expr::Assign(lhs, rhs) => (lhs, rhs),
+                _ => return Err("Expected an assignment of the form \"set var x = value\".".to_string()),
+            };
+            let evaluator = Evaluator::for_frame(debug_data, tracee, selected_frame)?;
+            let target = evaluator.evaluate(&lhs)?;
+            let addr = target
+                .addr
+                .ok_or("Left operand of assignment is not an lvalue.".to_string())?;
+            let bytes = evaluator.convert(&evaluator.evaluate(&rhs)?, &target.value_type)?;
+            Ok((addr, bytes))
+        });
+        match result {
+            Ok((addr, bytes)) => {
+                if let Err(err) = tracee.write_memory(addr, &bytes) {
+                    println!("Cannot access memory at address {:#x}: {}", addr, err);
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// The source line the tracee is stopped at, with its %rip.
+    fn stop_line(&self) -> Option<(usize, Line)> {
+        if let Some(frame) = &self.selected_frame {
+            return Some((frame.regs.rip, frame.line.clone()?));
+        }
+        let rip = self.tracee()?.get_regs().ok()?.rip as usize;
+        Some((rip, self.debug_data.get_line_from_addr(rip)?))
+    }
+
+    /// Implements `list`. Without an argument it lists around where the tracee stopped, or
+    /// carries on after the previous listing; otherwise `spec` is a line number, a function, or
+    /// either of those prefixed with `file:`.
+    fn list(&mut self, spec: Option<&str>) {
+        let stop_line = self.stop_line();
+        let (file, first) = match (spec, &stop_line, &self.list_position) {
+            (None, Some((rip, line)), _) if self.list_stop != Some(*rip) => {
+                (line.file.clone(), list_start(line.number))
+            }
+            (None, _, Some((file, next))) => (file.clone(), *next),
+            (None, _, None) => match self.resolve_linespec("main") {
+                Ok((file, number)) => (file, list_start(number)),
+                Err(_) => {
+                    eprintln!("No symbol table is loaded.");
+                    return;
+                }
+            },
+            (Some(spec), _, _) => match self.resolve_linespec(spec) {
+                Ok((file, number)) => (file, list_start(number)),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                }
+            },
+        };
+
+        let source = match self.read_source(&file) {
+            Some(source) => source,
+            None => {
+                eprintln!("{}: No such file or directory.", file);
+                return;
+            }
+        };
+        if first > source.len() {
+            eprintln!("Line number {} out of range; \"{}\" has {} lines.", first, file, source.len());
+            return;
+        }
+        self.list_stop = stop_line.as_ref().map(|(rip, _)| *rip);
+        let last = (first + 9).min(source.len());
+        for number in first..=last {
+            let current = match &stop_line {
+                Some((_, line)) => line.number == number && same_source(&line.file, &file),
+                None => false,
+            };
+            let marker = if current { "=> " } else { "" };
+            println!("{}{}\t{}", marker, number, source[number - 1]);
+        }
+        self.list_position = Some((file, last + 1));
+    }
+
+    /// Turns a `list` argument into a file and line: `42`, `func`, `file.c:42` or `file.c:func`.
+    /// A bare line number refers to the file listed last, or else the one we are stopped in.
+    fn resolve_linespec(&self, spec: &str) -> Result<(String, usize), String> {
+        let (file, rest) = split_linespec(spec);
+        if let Ok(number) = rest.parse::<usize>() {
+            let file = match file {
+                Some(file) => match self.debug_data.get_file_name(file) {
+                    Some(name) => name.to_string(),
+                    None if self.read_source(file).is_some() => file.to_string(),
+                    None => return Err(format!("No source file named {}.", file)),
+                },
+                None => match (&self.list_position, self.stop_line()) {
+                    (Some((file, _)), _) => file.clone(),
+                    (None, Some((_, line))) => line.file,
+                    (None, None) => self.resolve_linespec("main")?.0,
+                },
+            };
+            return Ok((file, number));
+        }
+        let line = self
+            .debug_data
+            .get_addr_for_function(file, rest)
+            .and_then(|addr| self.debug_data.get_line_from_addr(addr));
+        match (line, file) {
+            (Some(line), _) => Ok((line.file, line.number)),
+            (None, Some(file)) => Err(format!("Function \"{}\" not defined in \"{}\".", rest, file)),
+            (None, None) => Err(format!("Function \"{}\" not defined.", rest)),
+        }
+    }
+
+    /// Reads the lines of a source file, looking in the `directory` search path first and then
+    /// at the path recorded in the debug info.
+    fn read_source(&self, file: &str) -> Option<Vec<String>> {
+        let path = Path::new(file);
+        let mut candidates = Vec::new();
+        for dir in &self.source_dirs {
+            if path.is_relative() {
+                candidates.push(Path::new(dir).join(path));
+            }
+            if let Some(basename) = path.file_name() {
+                candidates.push(Path::new(dir).join(basename));
+            }
+        }
+        candidates.push(path.to_path_buf());
+        let bytes = candidates.iter().find_map(|candidate| fs::read(candidate).ok())?;
+        Some(String::from_utf8_lossy(&bytes).lines().map(|line| line.to_string()).collect())
+    }
+
+    /// Implements `directory`: puts `dirs` (which may be colon-separated) at the front of the
+    /// source search path, or empties it when none are given.
+    fn add_source_directories(&mut self, dirs: &[String]) {
+        if dirs.is_empty() {
+            self.source_dirs.clear();
+        } else {
+            let mut new_dirs: Vec<String> = Vec::new();
+            for dir in dirs.iter().flat_map(|dirs| dirs.split(':')) {
+                if !dir.is_empty() && !new_dirs.iter().any(|d| d == dir) {
+                    new_dirs.push(dir.to_string());
+                }
+            }
+            self.source_dirs.retain(|dir| !new_dirs.contains(dir));
+            new_dirs.append(&mut self.source_dirs);
+            self.source_dirs = new_dirs;
+        }
+        let mut searched = self.source_dirs.clone();
+        searched.push("$cdir:$cwd".to_string());
+        println!("Source directories searched: {}", searched.join(":"));
+    }
+
+    /// Implements `step` (`step_into` set) and `next`.
+    fn step_line(&mut self, step_into: bool) {
+        self.selected_frame = None;
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let (break_list, debug_data) = (&mut self.break_list, &self.debug_data);
+        let mut on_breakpoint = |tracee: &Inferior, addr| breakpoint_hit(break_list, debug_data, tracee, addr);
+        match tracee.step_line(debug_data, &self.restore_map, step_into, &mut on_breakpoint) {
+            Ok(status) => self.print_step_status(status),
+            Err(err) => eprintln!("Error stepping: {}", err),
+        }
+    }
+
+    /// Implements `stepi` (with `step_into` set) and `nexti`: executes `count` machine
+    /// instructions, `nexti` running calls to completion, and shows the next instruction.
+    fn step_instruction(&mut self, count: usize, step_into: bool) {
+        self.selected_frame = None;
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        if count == 0 {
+            return;
+        }
+        let (break_list, debug_data) = (&mut self.break_list, &self.debug_data);
+        let mut on_breakpoint = |tracee: &Inferior, addr| breakpoint_hit(break_list, debug_data, tracee, addr);
+        let mut result = Ok(Status::Stopped(Signal::SIGTRAP, 0));
+        for _ in 0..count {
+            result = if step_into {
+                tracee.step_instruction(&self.restore_map)
+            } else {
+                tracee.next_instruction(&self.restore_map, &mut on_breakpoint)
+            };
+            match result {
+                Ok(Status::Stopped(Signal::SIGTRAP, _)) => {}
+                _ => break,
+            }
+        }
+        match result {
+            Ok(status) => {
+                self.print_step_status(status);
+                // Nothing to show if it exited
+                self.print_current_instruction();
+            }
+            Err(err) => eprintln!("Error stepping: {}", err),
+        }
+    }
+
+    /// Prints the instruction at %rip, the way `x/i $pc` would.
+    fn print_current_instruction(&self) {
+        let tracee = match self.inferior.as_ref() {
+            Some(tracee) => tracee,
+            None => return,
+        };
+        let rip = match tracee.get_regs() {
+            Ok(regs) => regs.rip as usize,
+            Err(_) => return,
+        };
+        let read = |addr: usize, len: usize| {
+            let mut bytes = tracee.read_memory(addr, len)?;
+            inferior::restore_original_bytes(&self.restore_map, addr, &mut bytes);
+            Ok::<Vec<u8>, nix::Error>(bytes)
+        };
+        if let Ok(bytes) = read_instruction(&read, rip) {
+            let insn = disassembler::decode(&bytes, rip);
+            println!(
+                "=> {:#x}{}:\t{}",
+                rip,
+                format_symbol(&self.debug_data, rip),
+                format_instruction(&self.debug_data, &insn)
+            );
+        }
+    }
+
+    /// Implements `disassemble`. With no argument it shows the function the selected frame is
+    /// in; with one, the function containing that address or named by it; and with `start,end`
+    /// or `start,+length` that range. A second number smaller than the first is also taken as a
+    /// length. Source lines are shown before the instructions generated for them, and our traps
+    /// show as the bytes they replaced.
+    fn disassemble(&self, spec: Option<&str>) {
+        let tracee = match self.tracee() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        let pc = match &self.selected_frame {
+            Some(frame) => frame.regs.rip,
+            None => tracee.get_regs().map(|regs| regs.rip as usize).unwrap_or(0),
+        };
+        let evaluate = |expr: &str| {
+            let parsed = expression::parse(expr, &self.debug_data)?;
+            let evaluator = Evaluator::for_frame(&self.debug_data, tracee, self.selected_frame.as_ref())?;
+            evaluator.address(&evaluator.evaluate(&parsed)?)
+        };
+        let range = match spec.map(|spec| spec.splitn(2, ',').collect::<Vec<_>>()) {
+            Some(parts) if parts.len() == 2 => {
+                let (first, second) = (parts[0].trim(), parts[1].trim());
+                evaluate(first).and_then(|start| {
+                    let end = if second.starts_with('+') {
+                        start + evaluate(&second[1..])?
+                    } else {
+                        match evaluate(second)? {
+                            end if end < start => start + end,
+                            end => end,
+                        }
+                    };
+                    Ok((start, end, None))
+                })
+            }
+            _ => {
+                let addr = match spec {
+                    Some(expr) => evaluate(expr),
+                    None if self.selected_frame.is_some() => {
+                        Ok(self.selected_frame.as_ref().unwrap().regs.lookup_addr(false))
+                    }
+                    None => Ok(pc),
+                };
+                addr.and_then(|addr| match self.debug_data.get_function_range(addr) {
+                    Some((name, start, end)) => Ok((start, end, Some(name))),
+                    None => Err("No function contains specified address.".to_string()),
+                })
+            }
+        };
+        let (start, end, function) = match range {
+            Ok(range) => range,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+
+        let live = self.inferior.is_some();
+        let read = |addr: usize, len: usize| {
+            let mut bytes = tracee.read_memory(addr, len)?;
+            if live {
+                inferior::restore_original_bytes(&self.restore_map, addr, &mut bytes);
+            }
+            Ok::<Vec<u8>, nix::Error>(bytes)
+        };
+        match function {
+            Some(name) => println!("Dump of assembler code for function {}:", name),
+            None => println!("Dump of assembler code from {:#x} to {:#x}:", start, end),
+        }
+        let mut last_line: Option<Line> = None;
+        let mut sources: HashMap<String, Option<Vec<String>>> = HashMap::new();
+        let mut addr = start;
+        while addr < end {
+            if let Some(line) = self.debug_data.get_line_from_addr(addr) {
+                let (new_file, new_line) = match &last_line {
+                    Some(last) => (last.file != line.file, last.file != line.file || last.number != line.number),
+                    None => (true, true),
+                };
+                if new_line {
+                    if new_file {
+                        println!("{}:", line.file);
+                    }
+                    let text = sources
+                        .entry(line.file.clone())
+                        .or_insert_with(|| self.read_source(&line.file))
+                        .as_ref()
+                        .and_then(|lines| lines.get(line.number.checked_sub(1)?));
+                    match text {
+                        Some(text) => println!("{}\t{}", line.number, text),
+                        None => println!("{}\tin {}", line.number, line.file),
+                    }
+                    last_line = Some(line);
+                }
+            }
+            let bytes = match read_instruction(&read, addr) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    eprintln!("Cannot access memory at address {:#x}", addr);
+                    return;
+                }
+            };
+            let insn = disassembler::decode(&bytes, addr);
+            let label = match function {
+                Some(_) => format!(" <+{}>", addr - start),
+                None => format_symbol(&self.debug_data, addr),
+            };
+            println!(
+                "{}{:#x}{}:\t{}",
+                if addr == pc { "=> " } else { "   " },
+                addr,
+                label,
+                format_instruction(&self.debug_data, &insn)
+            );
+            addr += insn.len;
+        }
+        println!("End of assembler dump.");
+    }
+
+    fn finish(&mut self) {
+        self.selected_frame = None;
+        let tracee = match self.inferior.as_mut() {
+            Some(tracee) => tracee,
+            None => {
+                eprintln!("You need to run a tracee first!");
+                return;
+            }
+        };
+        if let Ok(regs) = tracee.get_regs() {
+            let rip = regs.rip as usize;
+            if let (Some(func), Some(line)) = (
+                self.debug_data.get_function_from_addr(rip),
+                self.debug_data.get_line_from_addr(rip),
+            ) {
+                println!("Run till exit from {} ({})", func, line);
+            }
+        }
+        let (break_list, debug_data) = (&mut self.break_list, &self.debug_data);
+        let mut on_breakpoint = |tracee: &Inferior, addr| breakpoint_hit(break_list, debug_data, tracee, addr);
+        match tracee.finish(debug_data, &self.restore_map, &mut on_breakpoint) {
+            Ok(status) => self.print_step_status(status),
+            Err(err) => eprintln!("Error finishing: {}", err),
+        }
+    }
+
+    /// Reports where the inferior ended up after a step, next or finish.
+    fn print_step_status(&mut self, status: Status) {
+        match status {
+            Status::Exited(exit_code) => {
+                println!("Child exited (status {})", exit_code);
+                self.inferior = None;
+            }
+            Status::Signaled(signal) => {
+                println!("Child got a signal ({})", signal);
+                self.inferior = None;
+            }
+            Status::Forked(_, _, rip) | Status::Execed(rip) => {
+                self.report_catch(&status);
+                self.print_step_status(Status::Stopped(Signal::SIGTRAP, rip));
+            }
+            // A step that ran off into code without line info stopped in the dynamic linker:
+            // carry on from there
+            Status::LibrariesChanged => {
+                self.update_shared_libraries();
+                match self.resume() {
+                    Ok((status, _)) => self.print_step_status(status),
+                    Err(err) => eprintln!("Error continuing: {}", err),
+                }
+            }
+            Status::Stopped(signal, rip) => {
+                // A step may have gone over a dlopen or dlclose
+                self.update_shared_libraries();
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.announce_thread();
+                }
+                if signal != Signal::SIGTRAP {
+                    println!("Child stopped with signal: {}", signal);
+                } else if !self.watch_list.is_empty() {
+                    if let Err(err) = self.report_watchpoints() {
+                        eprintln!("Could not check watchpoints: {}", err);
+                    }
+                }
+                match self.debug_data.get_line_from_addr(rip) {
+                    Some(line) => match self.debug_data.get_function_from_addr(rip) {
+                        Some(func) => println!("Stopped at: {} ({})", func, line),
+                        None => println!("Stopped at: {}", line),
+                    },
+                    None => match self.debug_data.get_function_from_addr(rip) {
+                        Some(func) => println!("Stopped at: {:#x} in {} ()", rip, func),
+                        None => println!("Stopped at: {:#x}", rip),
+                    },
+                }
+            }
+        }
+    }
+
+    /// This function prompts the user to enter a command, and continues re-prompting until the user
+    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
+    ///
+    /// You don't need to read, understand, or modify this function.
+    fn get_next_command(&mut self) -> DebuggerCommand {
+        loop {
+            // Print prompt and get next line of user input
+            match self.readline.readline("(deet) ") {
+                Err(ReadlineError::Interrupted) => {
+                    // User pressed ctrl+c. We're going to ignore it
+                    println!("Type \"quit\" to exit");
+                }
+                Err(ReadlineError::Eof) => {
+                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
+                    return DebuggerCommand::Quit;
+                }
+                Err(err) => {
+                    panic!("Unexpected I/O error: {:?}", err);
+                }
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    self.readline.add_history_entry(line.as_str());
+                    if let Err(err) = self.readline.save_history(&self.history_path) {
+                        println!(
+                            "Warning: failed to save history file at {}: {}",
+                            self.history_path, err
+                        );
+                    }
+                    let tokens: Vec<&str> = line.split_whitespace().collect();
+                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
+                        return cmd;
+                    } else {
+                        println!("Unrecognized command.");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Formats the result of an expression. Like gdb, pointers are labelled with their type, except
+/// for strings.
+fn format_result(debug_data: &DwarfData, tracee: &dyn Tracee, value: &Value) -> String {
+    let (text, is_pointer) = match &value.value_type {
+        ValueType::Type(entity_type) => (
+            format_value(debug_data, tracee, entity_type, &value.bytes),
+            matches!(
+                debug_data.strip_typedefs(entity_type).kind,
+                TypeKind::Pointer(_)
+            ),
+        ),
+        ValueType::PointerTo(target) => {
+            let addr = read_uint(&value.bytes);
+            let text = match &**target {
+                ValueType::Type(target) if addr != 0 && is_char(debug_data, Some(target)) => {
+                    format!("{:#x} {}", addr, read_c_string(tracee, addr as usize))
+                }
+                _ => format!("{:#x}", addr),
+            };
+            (text, true)
+        }
+    };
+    if let (ValueType::Type(entity_type), Some(addr)) = (&value.value_type, value.addr) {
+        if let TypeKind::Function(_) = debug_data.strip_typedefs(entity_type).kind {
+            return format!("{} {:#x}{}", text, addr, format_symbol(debug_data, addr));
+        }
+    }
+    if is_pointer && !text.ends_with('"') {
+        // Like gdb, name whatever the pointer points at if it's a function or global
+        let symbol = format_symbol(debug_data, read_uint(&value.bytes) as usize);
+        format!("({}) {}{}", value.value_type.name(), text, symbol)
+    } else {
+        text
+    }
+}
+
+/// Describes the innermost frame of a thread stopped at `rip`, the way `backtrace` does.
+fn format_frame(debug_data: &DwarfData, rip: usize) -> String {
+    match (debug_data.get_function_from_addr(rip), debug_data.get_line_from_addr(rip)) {
+        (Some(func), Some(line)) => format!("{} ({})", func, line),
+        (Some(func), None) => format!("{:#x} in {} ()", rip, func),
+        _ => format!("{:#x} in ?? ()", rip),
+    }
+}
+
+/// Counts a hit of the breakpoints at `addr` and decides whether the program should stop there.
+/// A breakpoint whose condition is false doesn't count as hit, and one with an ignore count uses
+/// up one crossing instead of stopping. A condition that can't be evaluated stops the program.
+fn breakpoint_hit(break_list: &mut [Breakpoint], debug_data: &DwarfData, tracee: &dyn Tracee, addr: usize) -> bool {
+    let mut stop = false;
+    for bp in break_list.iter_mut().filter(|bp| bp.enabled && !bp.pending && bp.addr == addr) {
+        if let Some(condition) = &bp.condition {
+            let result = expression::parse(condition, debug_data).and_then(|parsed| {
+                let evaluator = Evaluator::new(debug_data, tracee)?;
+                evaluator.truth(&evaluator.evaluate(&parsed)?)
+            });
+            match result {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    println!("Error in testing condition for breakpoint {}:\n{}", bp.num, err);
+                    stop = true;
+                    continue;
+                }
+            }
+        }
+        bp.hits += 1;
+        if bp.ignore_count > 0 {
+            bp.ignore_count -= 1;
+        } else {
+            stop = true;
+        }
+    }
+    stop
+}
+
+fn catch_name(kind: CatchKind) -> &'static str {
+    match kind {
+        CatchKind::Fork => "fork",
+        CatchKind::Vfork => "vfork",
+        CatchKind::Exec => "exec",
+    }
+}
+
+fn print_signal_header() {
+    println!("Signal        Stop\tPrint\tPass to program\tDescription");
+}
+
+fn print_signal_row(signal: Signal, action: &SignalAction) {
+    let yes_no = |flag| if flag { "Yes" } else { "No" };
+    println!(
+        "{:<14}{}\t{}\t{}\t\t{}",
+        signal.as_str(),
+        yes_no(action.stop),
+        yes_no(action.print),
+        yes_no(action.pass),
+        inferior::signal_description(signal)
+    );
+}
+
+/// Parses the value of an on/off setting.
+fn parse_on_off(value: &str) -> Option<bool> {
+    match value {
+        "on" | "1" | "yes" | "enable" => Some(true),
+        "off" | "0" | "no" | "disable" => Some(false),
+        _ => None,
+    }
+}
+
+/// Says where the debug info for `path` came from when it was a separate file, and says so when
+/// there was none and we have only the symbol tables to go by.
+fn report_debug_info(path: &str, debug_data: &DwarfData) {
+    if let Some(debug_file) = debug_data.debug_file() {
+        println!("Reading symbols from {}...", debug_file);
+    }
+    if let Some(err) = debug_data.dwarf_error() {
+        println!("Could not read the debugging information in {}: {:?}", path, err);
+    }
+    if !debug_data.has_debug_info() {
+        println!("(No debugging symbols found in {})", path);
+    }
+}
+
+/// Parses a plain address like `0x401126` or `4198694`.
+fn parse_address(text: &str) -> Option<usize> {
+    let text = text.trim();
+    if text.to_lowercase().starts_with("0x") {
+        usize::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Whether a `break` location that can't be found yet may be in a library loaded later. Plain
+/// line numbers and addresses only make sense in the program itself.
+fn can_be_pending(spec: &str) -> bool {
+    if spec.is_empty() {
+        return false;
+    }
+    match split_linespec(spec) {
+        (Some(_), _) => true,
+        (None, rest) => !spec.starts_with('*') && rest.parse::<usize>().is_err(),
+    }
+}
+
+/// The first line `list` shows to put `number` in the middle of the listing, like gdb.
+fn list_start(number: usize) -> usize {
+    number.max(6) - 5
+}
+
+/// Whether two names for a source file refer to the same file, where one may be relative to the
+/// compilation directory and the other absolute.
+fn same_source(a: &str, b: &str) -> bool {
+    a == b || a.ends_with(&format!("/{}", b)) || b.ends_with(&format!("/{}", a))
+}
+
+/// Returns a label like ` <main+4>` for `addr`, or nothing if no function or global contains it.
+fn format_symbol(debug_data: &DwarfData, addr: usize) -> String {
+    match debug_data.get_symbol_for_addr(addr) {
+        Some((name, 0)) => format!(" <{}>", name),
+        Some((name, offset)) => format!(" <{}+{}>", name, offset),
+        None => String::new(),
+    }
+}
+
+/// Formats one unit of `x` output in the format `letter`.
+fn format_unit(debug_data: &DwarfData, letter: char, bytes: &[u8]) -> String {
+    let raw = read_uint(bytes);
+    match letter {
+        'd' => read_int(bytes).to_string(),
+        'u' => raw.to_string(),
+        'o' if raw == 0 => "0".to_string(),
+        'o' => format!("0{:o}", raw),
+        't' => format!("{:0width$b}", raw, width = bytes.len() * 8),
+        'c' => format!("{} {}", read_int(bytes), format_char(raw as u8)),
+        'a' => format!("{:#x}{}", raw, format_symbol(debug_data, raw as usize)),
+        'f' if bytes.len() == 4 => format!("{}", f32::from_bits(raw as u32)),
+        'f' if bytes.len() == 8 => format!("{}", f64::from_bits(raw)),
+        'f' => read_int(bytes).to_string(),
+        _ => format!("0x{:0width$x}", raw, width = bytes.len() * 2),
+    }
+}
+
+/// Formats a decoded instruction, naming the targets of jumps and calls and the memory that
+/// %rip-relative operands refer to.
+fn format_instruction(debug_data: &DwarfData, insn: &disassembler::Instruction) -> String {
+    match (insn.target, insn.rip_ref) {
+        (Some(target), _) => format!("{}{}", insn.text, format_symbol(debug_data, target)),
+        (None, Some(addr)) => format!(
+            "{}        # {:#x}{}",
+            insn.text,
+            addr,
+            format_symbol(debug_data, addr)
+        ),
+        (None, None) => insn.text.clone(),
+    }
+}
+
+/// Reads enough bytes at `addr` to decode one instruction: up to 15, fewer if the instruction
+/// sits at the end of a mapping.
+fn read_instruction<F>(read: &F, addr: usize) -> Result<Vec<u8>, nix::Error>
+where
+    F: Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+{
+    let mut len = 15;
+    loop {
+        match read(addr, len) {
+            Err(_) if len > 1 => len /= 2,
+            result => return result,
+        }
+    }
+}
+
+/// Reads the NUL-terminated string at `addr` for `x/s`, returning it quoted along with the
+/// number of bytes it occupies (including the NUL).
+fn read_c_string_at<F>(read: &F, addr: usize) -> Result<(String, usize), nix::Error>
+where
+    F: Fn(usize, usize) -> Result<Vec<u8>, nix::Error>,
+{
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_ELEMENTS {
+        let byte = read(addr + bytes.len(), 1)?[0];
+        if byte == 0 {
+            return Ok((format_c_string(&bytes, false), bytes.len() + 1));
+        }
+        bytes.push(byte);
+    }
+    Ok((format_c_string(&bytes, true), bytes.len()))
+}
+
+/// Splits the `len` bytes at `addr` into pieces a debug register can watch: 1, 2, 4 or 8 bytes
+/// long, and aligned to their length.
+fn watch_chunks(mut addr: usize, mut len: usize) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    while len > 0 {
+        let size = [8, 4, 2, 1]
+            .iter()
+            .cloned()
+            .find(|size| addr % size == 0 && *size <= len)
+            .unwrap();
+        chunks.push((addr, size));
+        addr += size;
+        len -= size;
+    }
+    chunks
+}
+
+// Where distributions install separate debug info, searched by default
+const DEFAULT_DEBUG_FILE_DIR: &str = "/usr/lib/debug";
+
+// x86-64 has four debug address registers, DR0-DR3
+const MAX_WATCH_SLOTS: usize = 4;
+
+// Don't print more than this many array elements or string characters
+const MAX_ELEMENTS: usize = 200;
+
+/// Formats a value of type `entity_type` whose bytes are `bytes`. `tracee` is used to read the
+/// strings that char pointers point at.
+fn format_value(debug_data: &DwarfData, tracee: &dyn Tracee, entity_type: &Type, bytes: &[u8]) -> String {
+    let entity_type = debug_data.strip_typedefs(entity_type);
+    let member_type = |offset: Option<usize>| offset.and_then(|offset| debug_data.get_type(offset));
+    match &entity_type.kind {
+        TypeKind::Base(encoding) => format_base(*encoding, bytes),
+        TypeKind::Pointer(target) => {
+            let addr = read_uint(bytes);
+            if addr != 0 && is_char(debug_data, member_type(*target)) {
+                format!("{:#x} {}", addr, read_c_string(tracee, addr as usize))
+            } else {
+                format!("{:#x}", addr)
+            }
+        }
+        TypeKind::Struct(members) | TypeKind::Union(members) => {
+            let fields: Vec<String> = members
+                .iter()
+                .map(|member| {
+                    let value = match member_type(member.type_offset) {
+                        Some(t) if member.offset + t.size <= bytes.len() => format_value(
+                            debug_data,
+                            tracee,
+                            t,
+                            &bytes[member.offset..member.offset + t.size],
+                        ),
+                        _ => "<unavailable>".to_string(),
+                    };
+                    format!("{} = {}", member.name, value)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        TypeKind::Array(elem, dims) => match member_type(*elem) {
+            Some(elem) => format_array(debug_data, tracee, elem, dims, bytes),
+            None => "<unknown element type>".to_string(),
+        },
+        TypeKind::Enum(enumerators) => {
+            let value = read_int(bytes);
+            match enumerators.iter().find(|e| e.value == value) {
+                Some(enumerator) => enumerator.name.clone(),
+                None => value.to_string(),
+            }
+        }
+        TypeKind::Function(_) => format!("{{{}}}", entity_type.name),
+        _ => format!("{:#x}", read_uint(bytes)),
+    }
+}
+
+/// Formats an array whose outermost dimensions are `dims`. Arrays of chars are shown as strings.
+fn format_array(
+    debug_data: &DwarfData,
+    tracee: &dyn Tracee,
+    elem: &Type,
+    dims: &[Option<usize>],
+    bytes: &[u8],
+) -> String {
+    if dims.is_empty() {
+        return format_value(debug_data, tracee, elem, bytes);
+    }
+    let inner_count: usize = dims[1..].iter().map(|dim| dim.unwrap_or(0)).product();
+    let stride = elem.size * inner_count;
+    if stride == 0 {
+        return "{}".to_string();
+    }
+    let count = dims[0].unwrap_or(0).min(bytes.len() / stride);
+    if dims.len() == 1 && is_char(debug_data, Some(elem)) {
+        let len = bytes[..count].iter().position(|&b| b == 0).unwrap_or(count);
+        return format_c_string(&bytes[..len.min(MAX_ELEMENTS)], len > MAX_ELEMENTS);
+    }
+    let mut values: Vec<String> = (0..count.min(MAX_ELEMENTS))
+        .map(|i| {
+            let elem_bytes = &bytes[i * stride..(i + 1) * stride];
+            format_array(debug_data, tracee, elem, &dims[1..], elem_bytes)
+        })
+        .collect();
+    if count > MAX_ELEMENTS {
+        values.push("...".to_string());
+    }
+    format!("{{{}}}", values.join(", "))
+}
+
+fn is_char(debug_data: &DwarfData, entity_type: Option<&Type>) -> bool {
+    matches!(
+        entity_type.map(|t| &debug_data.strip_typedefs(t).kind),
+        Some(TypeKind::Base(BaseEncoding::SignedChar))
+            | Some(TypeKind::Base(BaseEncoding::UnsignedChar))
+    )
+}
+
+/// Reads the NUL-terminated string at `addr` out of the inferior.
+fn read_c_string(tracee: &dyn Tracee, addr: usize) -> String {
+    let mut bytes = Vec::new();
+    while bytes.len() <= MAX_ELEMENTS {
+        match tracee.read_memory(addr + bytes.len(), 8) {
+            Ok(chunk) => match chunk.iter().position(|&b| b == 0) {
+                Some(end) => {
+                    bytes.extend_from_slice(&chunk[..end]);
+                    return format_c_string(&bytes, false);
+                }
+                None => bytes.extend_from_slice(&chunk),
+            },
+            Err(_) => return format!("<error: Cannot access memory at address {:#x}>", addr),
+        }
+    }
+    format_c_string(&bytes[..MAX_ELEMENTS], true)
+}
+
+fn format_c_string(bytes: &[u8], truncated: bool) -> String {
+    let body: String = bytes
+        .iter()
+        .map(|&b| match b {
+            b'"' => "\\\"".to_string(),
+            b'\'' => "'".to_string(),
+            _ => {
+                let quoted = format_char(b);
+                quoted[1..quoted.len() - 1].to_string()
+            }
+        })
+        .collect();
+    format!("\"{}\"{}", body, if truncated { "..." } else { "" })
+}
+
+fn format_base(encoding: BaseEncoding, bytes: &[u8]) -> String {
+    if bytes.len() > 8 {
+        let hex: Vec<String> = bytes.iter().rev().map(|b| format!("{:02x}", b)).collect();
+        return format!("0x{}", hex.join(""));
+    }
+    let raw = read_uint(bytes);
+    match encoding {
+        BaseEncoding::Float if bytes.len() == 4 => format!("{}", f32::from_bits(raw as u32)),
+        BaseEncoding::Float if bytes.len() == 8 => format!("{}", f64::from_bits(raw)),
+        BaseEncoding::Boolean => format!("{}", raw != 0),
+        BaseEncoding::SignedChar => format!("{} {}", read_int(bytes), format_char(raw as u8)),
+        BaseEncoding::UnsignedChar => format!("{} {}", raw, format_char(raw as u8)),
+        BaseEncoding::Signed => format!("{}", read_int(bytes)),
+        _ => format!("{}", raw),
+    }
+}
+
+/// Formats a byte as a C character literal, e.g. `'A'`, `'\n'` or `'\310'`.
+fn format_char(byte: u8) -> String {
+    match byte {
+        b'\n' => "'\\n'".to_string(),
+        b'\t' => "'\\t'".to_string(),
+        0 => "'\\000'".to_string(),
+        b'\'' => "'\\''".to_string(),
+        b'\\' => "'\\\\'".to_string(),
+        0x20..=0x7e => format!("'{}'", byte as char),
+        _ => format!("'\\{:03o}'", byte),
     }
 }
@@ -1,13 +1,61 @@
+use crate::inferior::{CatchKind, SignalAction, WatchKind};
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,       // for milestone2
     Backtrace,      // for milestone3
-    Break(String),  // for milestone5
+    Break(String, Option<String>),  // for milestone5
+    Step,
+    Next,
+    Finish,
+    InfoBreakpoints,
+    InfoRegisters(Vec<String>),
+    InfoAllRegisters(Vec<String>),
+    InfoThreads,
+    InfoSharedLibrary,
+    InfoSymbol(String),
+    Thread(Option<usize>),
+    Delete(Vec<usize>),
+    Disable(Vec<usize>),
+    Enable(Vec<usize>),
+    Print(String),
+    SetVar(String),
+    Watch(WatchKind, String),
+    Attach(usize),
+    Detach,
+    Examine(ExamineFormat, String),
+    List(Option<String>),
+    Directory(Vec<String>),
+    Catch(CatchKind),
+    SetOption(String, String),
+    Show(String),
+    Handle(Vec<String>),
+    InfoSignals(Option<String>),
+    Frame(Option<usize>),
+    Up(usize),
+    Down(usize),
+    InfoLocals,
+    InfoArgs,
+    Condition(usize, Option<String>),
+    Ignore(usize, usize),
+    StepInstruction(usize),
+    NextInstruction(usize),
+    Disassemble(Option<String>),
+}
+
+/// The `/<count><format><size>` of an `x` command. A missing format or size means "the same as
+/// last time".
+pub struct ExamineFormat {
+    pub count: usize,
+    pub format: Option<char>,
+    pub size: Option<char>,
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
@@ -19,10 +67,342 @@ impl DebuggerCommand {
             "c" | "cont" | "continue" => Some( DebuggerCommand::Continue ),
             "bt" | "back" | "backtrace" => Some( DebuggerCommand::Backtrace ),
             "b" | "break" => { 
-                Some( DebuggerCommand::Break( tokens[1].to_string() ) )
+                // `break <location> if <condition>`
+                let args = &tokens[1..];
+                match args.iter().position(|&token| token == "if") {
+                    Some(i) => Some( DebuggerCommand::Break( args[..i].join(" "), Some(args[i + 1..].join(" ")) ) ),
+                    None => Some( DebuggerCommand::Break( args.join(" "), None ) ),
+                }
+            },
+            "s" | "step" => Some( DebuggerCommand::Step ),
+            "n" | "next" => Some( DebuggerCommand::Next ),
+            "fin" | "finish" => Some( DebuggerCommand::Finish ),
+            "si" | "stepi" => Some( DebuggerCommand::StepInstruction( parse_count(tokens.get(1))? ) ),
+            "ni" | "nexti" => Some( DebuggerCommand::NextInstruction( parse_count(tokens.get(1))? ) ),
+            "disas" | "disassemble" => Some( DebuggerCommand::Disassemble(
+                if tokens.len() > 1 { Some(tokens[1..].join(" ")) } else { None }
+            ) ),
+            "i" | "info" => match tokens.get(1) {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some( DebuggerCommand::InfoBreakpoints )
+                },
+                Some(&"r") | Some(&"reg") | Some(&"registers") => {
+                    Some( DebuggerCommand::InfoRegisters( to_strings(&tokens[2..]) ) )
+                },
+                Some(&"all-registers") => {
+                    Some( DebuggerCommand::InfoAllRegisters( to_strings(&tokens[2..]) ) )
+                },
+                Some(&"threads") => Some( DebuggerCommand::InfoThreads ),
+                Some(&"sharedlibrary") | Some(&"shared") | Some(&"dll") => {
+                    Some( DebuggerCommand::InfoSharedLibrary )
+                },
+                Some(&"symbol") if tokens.len() > 2 => {
+                    Some( DebuggerCommand::InfoSymbol( tokens[2..].join(" ") ) )
+                },
+                Some(&"locals") => Some( DebuggerCommand::InfoLocals ),
+                Some(&"args") => Some( DebuggerCommand::InfoArgs ),
+                Some(&"signals") | Some(&"signal") => {
+                    Some( DebuggerCommand::InfoSignals( tokens.get(2).map(|t| t.to_string()) ) )
+                },
+                _ => None,
+            },
+            "p" | "print" if tokens.len() > 1 => {
+                Some( DebuggerCommand::Print( tokens[1..].join(" ") ) )
+            },
+            "set" => match tokens.get(1) {
+                Some(&"var") | Some(&"variable") if tokens.len() > 2 => {
+                    Some( DebuggerCommand::SetVar( tokens[2..].join(" ") ) )
+                },
+                Some(reg) if reg.starts_with('$') => {
+                    Some( DebuggerCommand::SetVar( tokens[1..].join(" ") ) )
+                },
+                Some(name) if tokens.len() > 2 => {
+                    Some( DebuggerCommand::SetOption( name.to_string(), tokens[2..].join(" ") ) )
+                },
+                _ => None,
+            },
+            "show" if tokens.len() > 1 => Some( DebuggerCommand::Show( tokens[1].to_string() ) ),
+            "catch" => match tokens.get(1) {
+                Some(&"fork") => Some( DebuggerCommand::Catch(CatchKind::Fork) ),
+                Some(&"vfork") => Some( DebuggerCommand::Catch(CatchKind::Vfork) ),
+                Some(&"exec") => Some( DebuggerCommand::Catch(CatchKind::Exec) ),
+                _ => None,
+            },
+            "watch" if tokens.len() > 1 => {
+                Some( DebuggerCommand::Watch( WatchKind::Write, tokens[1..].join(" ") ) )
+            },
+            "rwatch" if tokens.len() > 1 => {
+                Some( DebuggerCommand::Watch( WatchKind::Read, tokens[1..].join(" ") ) )
             },
+            "awatch" if tokens.len() > 1 => {
+                Some( DebuggerCommand::Watch( WatchKind::Access, tokens[1..].join(" ") ) )
+            },
+            "thread" => match tokens.get(1) {
+                Some(num) => Some( DebuggerCommand::Thread( Some(num.parse().ok()?) ) ),
+                None => Some( DebuggerCommand::Thread(None) ),
+            },
+            "handle" if tokens.len() > 1 => Some( DebuggerCommand::Handle( to_strings(&tokens[1..]) ) ),
+            "f" | "frame" => match tokens.get(1) {
+                Some(level) => Some( DebuggerCommand::Frame( Some(level.parse().ok()?) ) ),
+                None => Some( DebuggerCommand::Frame(None) ),
+            },
+            "up" => Some( DebuggerCommand::Up( parse_count(tokens.get(1))? ) ),
+            "down" => Some( DebuggerCommand::Down( parse_count(tokens.get(1))? ) ),
+            "attach" => Some( DebuggerCommand::Attach( tokens.get(1)?.parse().ok()? ) ),
+            "detach" => Some( DebuggerCommand::Detach ),
+            t if t == "x" || t.starts_with("x/") => Some( DebuggerCommand::Examine(
+                parse_examine_format(&t[1..])?,
+                tokens[1..].join(" "),
+            ) ),
+            "l" | "list" => Some( DebuggerCommand::List( tokens.get(1).map(|t| t.to_string()) ) ),
+            "dir" | "directory" => Some( DebuggerCommand::Directory( to_strings(&tokens[1..]) ) ),
+            "condition" if tokens.len() > 1 => Some( DebuggerCommand::Condition(
+                tokens[1].parse().ok()?,
+                if tokens.len() > 2 { Some(tokens[2..].join(" ")) } else { None },
+            ) ),
+            "ignore" if tokens.len() == 3 => Some( DebuggerCommand::Ignore(
+                tokens[1].parse().ok()?,
+                tokens[2].parse().ok()?,
+            ) ),
+            "d" | "delete" => Some( DebuggerCommand::Delete( parse_nums(&tokens[1..])? ) ),
+            "disable" => Some( DebuggerCommand::Disable( parse_nums(&tokens[1..])? ) ),
+            "enable" => Some( DebuggerCommand::Enable( parse_nums(&tokens[1..])? ) ),
             // Default case:
             _ => None,
         }
     }
 }
+
+fn to_strings(tokens: &[&str]) -> Vec<String> {
+    tokens.iter().map(|token| token.to_string()).collect()
+}
+
+/// Parses the optional count of `up`, `down`, `stepi` and `nexti`, which defaults to 1.
+fn parse_count(token: Option<&&str>) -> Option<usize> {
+    match token {
+        Some(count) => count.parse().ok(),
+        None => Some(1),
+    }
+}
+
+/// Parses a list of breakpoint numbers, e.g. the `2 3` in `delete 2 3`.
+fn parse_nums(tokens: &[&str]) -> Option<Vec<usize>> {
+    tokens.iter().map(|token| token.parse::<usize>().ok()).collect()
+}
+
+/// Splits a location like `file.c:42` or `file.c:func` into its file and the rest.
+pub fn split_linespec(spec: &str) -> (Option<&str>, &str) {
+    match spec.rfind(':') {
+        Some(index) => (Some(&spec[..index]), &spec[index + 1..]),
+        None => (None, spec),
+    }
+}
+
+/// Parses a signal name like `SIGUSR1`, `usr1` or a number like `10`.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let name = name.to_uppercase();
+    if name.starts_with("SIG") {
+        name.parse().ok()
+    } else {
+        format!("SIG{}", name).parse().ok()
+    }
+}
+
+/// Applies a flag word of `handle SIG [stop|nostop] [print|noprint] [pass|nopass]` to `action`.
+/// Returns false if `word` isn't one.
+pub fn apply_handle_flag(action: &mut SignalAction, word: &str) -> bool {
+    // Stopping means telling the user why, and staying quiet means not stopping
+    match word {
+        "stop" => {
+            action.stop = true;
+            action.print = true;
+        }
+        "nostop" => action.stop = false,
+        "print" => action.print = true,
+        "noprint" => {
+            action.print = false;
+            action.stop = false;
+        }
+        "pass" | "noignore" => action.pass = true,
+        "nopass" | "ignore" => action.pass = false,
+        _ => return false,
+    }
+    true
+}
+
+/// Parses the `/4xw` in `x/4xw`: an optional count, then format and size letters in any order.
+fn parse_examine_format(spec: &str) -> Option<ExamineFormat> {
+    if !spec.starts_with('/') {
+        return Some(ExamineFormat { count: 1, format: None, size: None });
+    }
+    let spec = &spec[1..];
+    let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+    let mut format = ExamineFormat {
+        count: if digits > 0 { spec[..digits].parse().ok()? } else { 1 },
+        format: None,
+        size: None,
+    };
+    for letter in spec[digits..].chars() {
+        match letter {
+            'x' | 'd' | 'u' | 'o' | 't' | 'a' | 'c' | 'f' | 's' | 'i' => format.format = Some(letter),
+            'b' | 'h' | 'w' | 'g' => format.size = Some(letter),
+            _ => return None,
+        }
+    }
+    Some(format)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_format(spec: &str) -> Option<(usize, Option<char>, Option<char>)> {
+        parse_examine_format(spec).map(|format| (format.count, format.format, format.size))
+    }
+
+    #[test]
+    fn test_parse_examine_format() {
+        // A plain `x`
+        assert_eq!(parse_format(""), Some((1, None, None)));
+        assert_eq!(parse_format("/4xw"), Some((4, Some('x'), Some('w'))));
+        assert_eq!(parse_format("/wx"), Some((1, Some('x'), Some('w'))));
+        assert_eq!(parse_format("/s"), Some((1, Some('s'), None)));
+        assert_eq!(parse_format("/10i"), Some((10, Some('i'), None)));
+        assert_eq!(parse_format("/0x"), Some((0, Some('x'), None)));
+        assert_eq!(parse_format("/g"), Some((1, None, Some('g'))));
+        // The count has to come first
+        assert_eq!(parse_format("/wx4"), None);
+        assert_eq!(parse_format("/4q"), None);
+        assert_eq!(parse_format("/-1x"), None);
+    }
+
+    #[test]
+    fn test_examine_command() {
+        match DebuggerCommand::from_tokens(&["x"]) {
+            Some(DebuggerCommand::Examine(format, expr)) => {
+                assert_eq!((format.count, format.format, format.size), (1, None, None));
+                assert_eq!(expr, "");
+            }
+            _ => panic!("x didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["x/2dh", "arr", "+", "1"]) {
+            Some(DebuggerCommand::Examine(format, expr)) => {
+                assert_eq!(
+                    (format.count, format.format, format.size),
+                    (2, Some('d'), Some('h'))
+                );
+                assert_eq!(expr, "arr + 1");
+            }
+            _ => panic!("x/2dh didn't parse"),
+        }
+        assert!(DebuggerCommand::from_tokens(&["x/wx4", "&n"]).is_none());
+    }
+
+    #[test]
+    fn test_register_commands() {
+        match DebuggerCommand::from_tokens(&["info", "registers"]) {
+            Some(DebuggerCommand::InfoRegisters(names)) => assert!(names.is_empty()),
+            _ => panic!("info registers didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["i", "r", "rip", "eflags"]) {
+            Some(DebuggerCommand::InfoRegisters(names)) => assert_eq!(names, vec!["rip", "eflags"]),
+            _ => panic!("i r didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["info", "all-registers", "xmm0"]) {
+            Some(DebuggerCommand::InfoAllRegisters(names)) => assert_eq!(names, vec!["xmm0"]),
+            _ => panic!("info all-registers didn't parse"),
+        }
+        // `set $reg` needs no `var`, unlike a setting
+        match DebuggerCommand::from_tokens(&["set", "$eax", "=", "0x10"]) {
+            Some(DebuggerCommand::SetVar(assignment)) => assert_eq!(assignment, "$eax = 0x10"),
+            _ => panic!("set $eax didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["set", "var", "$pc", "=", "main"]) {
+            Some(DebuggerCommand::SetVar(assignment)) => assert_eq!(assignment, "$pc = main"),
+            _ => panic!("set var $pc didn't parse"),
+        }
+    }
+
+    #[test]
+    fn test_split_linespec() {
+        assert_eq!(split_linespec("42"), (None, "42"));
+        assert_eq!(split_linespec("main"), (None, "main"));
+        assert_eq!(split_linespec("main.c:42"), (Some("main.c"), "42"));
+        assert_eq!(split_linespec("src/util.c:helper"), (Some("src/util.c"), "helper"));
+        // Only the last colon separates the file
+        assert_eq!(split_linespec("dir:x/a.c:7"), (Some("dir:x/a.c"), "7"));
+        assert_eq!(split_linespec("a.c:"), (Some("a.c"), ""));
+    }
+
+    #[test]
+    fn test_break_command() {
+        match DebuggerCommand::from_tokens(&["break", "util.c:12"]) {
+            Some(DebuggerCommand::Break(spec, condition)) => {
+                assert_eq!(spec, "util.c:12");
+                assert_eq!(condition, None);
+            }
+            _ => panic!("break util.c:12 didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["b", "util.c:helper", "if", "n", ">", "3"]) {
+            Some(DebuggerCommand::Break(spec, condition)) => {
+                assert_eq!(spec, "util.c:helper");
+                assert_eq!(condition.as_deref(), Some("n > 3"));
+            }
+            _ => panic!("b util.c:helper if didn't parse"),
+        }
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGUSR1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("usr1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("SigAlrm"), Some(Signal::SIGALRM));
+        assert_eq!(parse_signal("10"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("SIGFOO"), None);
+        assert_eq!(parse_signal("SIG"), None);
+        assert_eq!(parse_signal(""), None);
+    }
+
+    #[test]
+    fn test_apply_handle_flag() {
+        let mut action = SignalAction { stop: true, print: true, pass: true };
+        // Not printing implies not stopping, and stopping implies printing
+        assert!(apply_handle_flag(&mut action, "noprint"));
+        assert_eq!(action, SignalAction { stop: false, print: false, pass: true });
+        assert!(apply_handle_flag(&mut action, "stop"));
+        assert_eq!(action, SignalAction { stop: true, print: true, pass: true });
+        assert!(apply_handle_flag(&mut action, "nostop"));
+        assert_eq!(action, SignalAction { stop: false, print: true, pass: true });
+        assert!(apply_handle_flag(&mut action, "ignore"));
+        assert!(!action.pass);
+        assert!(apply_handle_flag(&mut action, "noignore"));
+        assert!(action.pass);
+        assert!(apply_handle_flag(&mut action, "nopass"));
+        assert!(!action.pass);
+        assert!(!apply_handle_flag(&mut action, "pass_it"));
+        assert_eq!(action, SignalAction { stop: false, print: true, pass: false });
+    }
+
+    #[test]
+    fn test_signal_commands() {
+        match DebuggerCommand::from_tokens(&["handle", "SIGUSR1", "nostop", "noprint"]) {
+            Some(DebuggerCommand::Handle(args)) => {
+                assert_eq!(args, vec!["SIGUSR1", "nostop", "noprint"])
+            }
+            _ => panic!("handle didn't parse"),
+        }
+        assert!(DebuggerCommand::from_tokens(&["handle"]).is_none());
+        match DebuggerCommand::from_tokens(&["info", "signals", "usr2"]) {
+            Some(DebuggerCommand::InfoSignals(name)) => assert_eq!(name.as_deref(), Some("usr2")),
+            _ => panic!("info signals usr2 didn't parse"),
+        }
+        match DebuggerCommand::from_tokens(&["info", "signal"]) {
+            Some(DebuggerCommand::InfoSignals(name)) => assert_eq!(name, None),
+            _ => panic!("info signal didn't parse"),
+        }
+    }
+}
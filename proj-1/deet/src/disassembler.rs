@@ -0,0 +1,1353 @@
+//! A small x86-64 instruction decoder, printing AT&T syntax like objdump and gdb. It covers the
+//! general purpose instructions compilers emit plus common SSE moves and arithmetic; anything
+//! else decodes as a one byte `(bad)`.
+
+/// A decoded instruction.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub addr: usize,
+    pub len: usize,
+    pub text: String,
+    /// The destination of a direct jump or call
+    pub target: Option<usize>,
+    /// The address of a %rip-relative memory operand
+    pub rip_ref: Option<usize>,
+}
+
+const REGS64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGS32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGS16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGS8_REX: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const REGS8: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ALU_OPS: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFT_OPS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+/// x87 instructions with a memory operand, by opcode (0xd8-0xdf) and ModRM reg field.
+const X87_MEMORY_OPS: [[&str; 8]; 8] = [
+    ["fadds", "fmuls", "fcoms", "fcomps", "fsubs", "fsubrs", "fdivs", "fdivrs"],
+    ["flds", "", "fsts", "fstps", "fldenv", "fldcw", "fnstenv", "fnstcw"],
+    ["fiaddl", "fimull", "ficoml", "ficompl", "fisubl", "fisubrl", "fidivl", "fidivrl"],
+    ["fildl", "fisttpl", "fistl", "fistpl", "", "fldt", "", "fstpt"],
+    ["faddl", "fmull", "fcoml", "fcompl", "fsubl", "fsubrl", "fdivl", "fdivrl"],
+    ["fldl", "fisttpll", "fstl", "fstpl", "frstor", "", "fnsave", "fnstsw"],
+    ["fiadds", "fimuls", "ficoms", "ficomps", "fisubs", "fisubrs", "fidivs", "fidivrs"],
+    ["filds", "fisttps", "fists", "fistps", "fbld", "fildll", "fbstp", "fistpll"],
+];
+
+const BIT_OPS: [&str; 4] = ["bt", "bts", "btr", "btc"];
+
+/// SSE2 integer instructions of the form `op xmm/m128, xmm`, all with a 0x66 prefix.
+const SSE2_INT_OPS: [(u8, &str); 42] = [
+    (0x60, "punpcklbw"), (0x61, "punpcklwd"), (0x62, "punpckldq"), (0x63, "packsswb"),
+    (0x64, "pcmpgtb"), (0x65, "pcmpgtw"), (0x66, "pcmpgtd"), (0x67, "packuswb"),
+    (0x68, "punpckhbw"), (0x69, "punpckhwd"), (0x6a, "punpckhdq"), (0x6b, "packssdw"),
+    (0x6c, "punpcklqdq"), (0x6d, "punpckhqdq"), (0x74, "pcmpeqb"), (0x75, "pcmpeqw"),
+    (0x76, "pcmpeqd"), (0xd4, "paddq"), (0xd5, "pmullw"), (0xd8, "psubusb"),
+    (0xd9, "psubusw"), (0xda, "pminub"), (0xdb, "pand"), (0xdc, "paddusb"),
+    (0xdd, "paddusw"), (0xde, "pmaxub"), (0xdf, "pandn"), (0xe0, "pavgb"),
+    (0xe4, "pmulhuw"), (0xe5, "pmulhw"), (0xea, "pminsw"), (0xeb, "por"),
+    (0xee, "pmaxsw"), (0xef, "pxor"), (0xf4, "pmuludq"), (0xf8, "psubb"),
+    (0xf9, "psubw"), (0xfa, "psubd"), (0xfb, "psubq"), (0xfc, "paddb"), (0xfd, "paddw"),
+    (0xfe, "paddd"),
+];
+
+/// The second operand of a ModRM byte: a register or a memory reference.
+#[derive(Clone)]
+enum Rm {
+    Reg(u8),
+    Mem(String),
+}
+
+#[derive(Clone)]
+enum Operand {
+    Reg(String),
+    Mem(String),
+    Imm(i64),
+    /// A jump or call destination
+    Addr(usize),
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    addr: usize,
+    rex: u8,
+    opsize16: bool,
+    rep: Option<u8>,
+    lock: bool,
+    addr32: bool,
+    segment: Option<&'static str>,
+    rip_disp: Option<i64>,
+}
+
+/// Decodes the instruction at the start of `bytes`, which live at address `addr`.
+pub fn decode(bytes: &[u8], addr: usize) -> Instruction {
+    let mut decoder = Decoder {
+        bytes,
+        pos: 0,
+        addr,
+        rex: 0,
+        opsize16: false,
+        rep: None,
+        lock: false,
+        addr32: false,
+        segment: None,
+        rip_disp: None,
+    };
+    match decoder.decode() {
+        Some((text, target)) => Instruction {
+            addr,
+            len: decoder.pos,
+            text,
+            target,
+            rip_ref: decoder
+                .rip_disp
+                .map(|disp| (addr as i64 + decoder.pos as i64 + disp) as usize),
+        },
+        None => Instruction {
+            addr,
+            len: 1,
+            text: "(bad)".to_string(),
+            target: None,
+            rip_ref: None,
+        },
+    }
+}
+
+fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", -(value as i128))
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+/// Immediates are shown as unsigned numbers of the operand's size, e.g. `$0xfffffff0`.
+fn truncate(value: i64, size: u8) -> u64 {
+    match size {
+        1 => value as u8 as u64,
+        2 => value as u16 as u64,
+        4 => value as u32 as u64,
+        _ => value as u64,
+    }
+}
+
+fn suffix(size: u8) -> &'static str {
+    match size {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q",
+    }
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn imm(&mut self, size: u8) -> Option<i64> {
+        let len = size as usize;
+        let bytes = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(bytes);
+        let shift = 64 - 8 * len as u32;
+        Some(((u64::from_le_bytes(buf) << shift) as i64) >> shift)
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 8 != 0
+    }
+
+    /// The operand size of an instruction with the default 32 bit size.
+    fn opsize(&self) -> u8 {
+        if self.rex_w() {
+            8
+        } else if self.opsize16 {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn reg_name(&self, reg: u8, size: u8) -> String {
+        let name = match size {
+            1 if self.rex != 0 => REGS8_REX[reg as usize],
+            1 => REGS8[reg as usize & 7],
+            2 => REGS16[reg as usize],
+            4 => REGS32[reg as usize],
+            16 => return format!("%xmm{}", reg),
+            32 => return format!("%ymm{}", reg),
+            _ => REGS64[reg as usize],
+        };
+        format!("%{}", name)
+    }
+
+    fn reg(&self, reg: u8, size: u8) -> Operand {
+        Operand::Reg(self.reg_name(reg, size))
+    }
+
+    fn rm(&self, rm: &Rm, size: u8) -> Operand {
+        match rm {
+            Rm::Reg(reg) => self.reg(*reg, size),
+            Rm::Mem(mem) => Operand::Mem(mem.clone()),
+        }
+    }
+
+    /// Decodes a ModRM byte (and any SIB byte and displacement), returning the reg field and the
+    /// r/m operand.
+    fn modrm(&mut self) -> Option<(u8, Rm)> {
+        let modrm = self.byte()?;
+        let md = modrm >> 6;
+        let reg = ((modrm >> 3) & 7) | ((self.rex & 4) << 1);
+        let rm = modrm & 7;
+        if md == 3 {
+            return Some((reg, Rm::Reg(rm | ((self.rex & 1) << 3))));
+        }
+        let mut base = None;
+        let mut index = None;
+        let mut scale = 1;
+        let mut disp_size = match md {
+            1 => 1,
+            2 => 4,
+            _ => 0,
+        };
+        if rm == 4 {
+            let sib = self.byte()?;
+            scale = 1 << (sib >> 6);
+            let sib_index = ((sib >> 3) & 7) | ((self.rex & 2) << 2);
+            if sib_index != 4 {
+                index = Some(sib_index);
+            }
+            let sib_base = sib & 7;
+            if sib_base == 5 && md == 0 {
+                disp_size = 4;
+            } else {
+                base = Some(sib_base | ((self.rex & 1) << 3));
+            }
+        } else if rm == 5 && md == 0 {
+            let disp = self.imm(4)?;
+            self.rip_disp = Some(disp);
+            return Some((reg, Rm::Mem(self.segmented(format!("{}(%rip)", hex(disp))))));
+        } else {
+            base = Some(rm | ((self.rex & 1) << 3));
+        }
+        let disp = if disp_size > 0 { self.imm(disp_size)? } else { 0 };
+        let mut mem = if base.is_none() && index.is_none() {
+            format!("{:#x}", disp as u64)
+        } else if disp_size > 0 {
+            hex(disp)
+        } else {
+            String::new()
+        };
+        if base.is_some() || index.is_some() {
+            mem.push('(');
+            if let Some(base) = base {
+                mem.push_str(&self.reg_name(base, 8));
+            }
+            if let Some(index) = index {
+                mem.push_str(&format!(",{},{}", self.reg_name(index, 8), scale));
+            }
+            mem.push(')');
+        }
+        Some((reg, Rm::Mem(self.segmented(mem))))
+    }
+
+    fn segmented(&self, mem: String) -> String {
+        match self.segment {
+            Some(segment) => format!("%{}:{}", segment, mem),
+            None => mem,
+        }
+    }
+
+    /// Formats `mnemonic` with its operands, given in Intel order (destination first). AT&T
+    /// syntax lists them the other way round, and needs a size suffix when no register operand
+    /// pins the size down.
+    fn format(&self, mnemonic: &str, operands: &[Operand], size: u8) -> String {
+        let has_reg = operands.iter().any(|op| matches!(op, Operand::Reg(_)));
+        let has_mem = operands.iter().any(|op| matches!(op, Operand::Mem(_)));
+        let mnemonic = if has_mem && !has_reg {
+            format!("{}{}", mnemonic, suffix(size))
+        } else {
+            mnemonic.to_string()
+        };
+        let operands: Vec<String> = operands
+            .iter()
+            .rev()
+            .map(|op| match op {
+                Operand::Reg(reg) => reg.clone(),
+                Operand::Mem(mem) => mem.clone(),
+                Operand::Imm(imm) => format!("${:#x}", truncate(*imm, size)),
+                Operand::Addr(addr) => format!("{:#x}", addr),
+            })
+            .collect();
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{:<6} {}", mnemonic, operands.join(","))
+        }
+    }
+
+    fn decode(&mut self) -> Option<(String, Option<usize>)> {
+        let (mut text, target) = self.decode_opcode()?;
+        if self.addr32 {
+            text = format!("addr32 {}", text);
+        }
+        if self.lock {
+            text = format!("lock {}", text);
+        }
+        Some((text, target))
+    }
+
+    fn decode_opcode(&mut self) -> Option<(String, Option<usize>)> {
+        // Prefixes
+        let mut opcode = loop {
+            let byte = self.byte()?;
+            match byte {
+                0x66 => self.opsize16 = true,
+                0xf2 | 0xf3 => self.rep = Some(byte),
+                0xf0 => self.lock = true,
+                0x2e | 0x3e | 0x26 | 0x36 => {}
+                0x67 => self.addr32 = true,
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                _ => break byte,
+            }
+        };
+        if opcode & 0xf0 == 0x40 {
+            self.rex = opcode;
+            opcode = self.byte()?;
+        }
+        if opcode == 0x0f {
+            return self.decode_0f();
+        }
+
+        let size = self.opsize();
+        let text = match opcode {
+            // add/or/adc/sbb/and/sub/xor/cmp in their six encodings
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let op = ALU_OPS[(opcode >> 3) as usize];
+                match opcode & 7 {
+                    0..=3 => {
+                        let size = if opcode & 1 == 0 { 1 } else { size };
+                        let (reg, rm) = self.modrm()?;
+                        let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                        if opcode & 2 == 0 {
+                            self.format(op, &[rm, reg], size)
+                        } else {
+                            self.format(op, &[reg, rm], size)
+                        }
+                    }
+                    4 => {
+                        let imm = self.imm(1)?;
+                        self.format(op, &[self.reg(0, 1), Operand::Imm(imm)], 1)
+                    }
+                    _ => {
+                        let imm = self.imm(size.min(4))?;
+                        self.format(op, &[self.reg(0, size), Operand::Imm(imm)], size)
+                    }
+                }
+            }
+            0x50..=0x57 => self.format("push", &[self.reg((opcode & 7) | ((self.rex & 1) << 3), 8)], 8),
+            0x58..=0x5f => self.format("pop", &[self.reg((opcode & 7) | ((self.rex & 1) << 3), 8)], 8),
+            0x63 => {
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, 4);
+                format!("{:<6} {},{}", "movslq", self.operand_text(&rm), self.reg_name(reg, 8))
+            }
+            0x68 => {
+                let imm = self.imm(4)?;
+                self.format("push", &[Operand::Imm(imm)], 8)
+            }
+            0x6a => {
+                let imm = self.imm(1)?;
+                self.format("push", &[Operand::Imm(imm)], 8)
+            }
+            0x69 | 0x6b => {
+                let (reg, rm) = self.modrm()?;
+                let imm = self.imm(if opcode == 0x69 { size.min(4) } else { 1 })?;
+                let rm = self.rm(&rm, size);
+                self.format("imul", &[self.reg(reg, size), rm, Operand::Imm(imm)], size)
+            }
+            0x70..=0x7f => {
+                let rel = self.imm(1)?;
+                let target = (self.addr as i64 + self.pos as i64 + rel) as usize;
+                let text = self.format(&format!("j{}", CONDITIONS[(opcode & 0xf) as usize]), &[Operand::Addr(target)], 8);
+                return Some((text, Some(target)));
+            }
+            0x80 | 0x81 | 0x83 => {
+                let (reg, rm) = self.modrm()?;
+                let size = if opcode == 0x80 { 1 } else { size };
+                let imm = self.imm(if opcode == 0x81 { size.min(4) } else { 1 })?;
+                let rm = self.rm(&rm, size);
+                self.format(ALU_OPS[(reg & 7) as usize], &[rm, Operand::Imm(imm)], size)
+            }
+            0x84..=0x8b => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                let op = match opcode {
+                    0x84 | 0x85 => "test",
+                    0x86 | 0x87 => "xchg",
+                    _ => "mov",
+                };
+                if opcode < 0x8a {
+                    self.format(op, &[rm, reg], size)
+                } else {
+                    self.format(op, &[reg, rm], size)
+                }
+            }
+            0x8d => {
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, size);
+                self.format("lea", &[self.reg(reg, size), rm], size)
+            }
+            0x8f => {
+                let (_, rm) = self.modrm()?;
+                let rm = self.rm(&rm, 8);
+                // push and pop are always 64 bit, so objdump leaves the suffix off
+                format!("{:<6} {}", "pop", self.operand_text(&rm))
+            }
+            0x90 if self.rep == Some(0xf3) => "pause".to_string(),
+            0x90 if self.rex & 1 == 0 && !self.opsize16 => "nop".to_string(),
+            0x90..=0x97 => {
+                let reg = (opcode & 7) | ((self.rex & 1) << 3);
+                self.format("xchg", &[self.reg(reg, size), self.reg(0, size)], size)
+            }
+            0x98 => match size {
+                8 => "cltq",
+                2 => "cbtw",
+                _ => "cwtl",
+            }
+            .to_string(),
+            0x99 => match size {
+                8 => "cqto",
+                2 => "cwtd",
+                _ => "cltd",
+            }
+            .to_string(),
+            0xa4 | 0xa5 | 0xaa | 0xab | 0xa6 | 0xa7 | 0xae | 0xaf | 0xac | 0xad => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (op, operands) = match opcode & 0xfe {
+                    0xa4 => ("movs", "%ds:(%rsi),%es:(%rdi)".to_string()),
+                    0xa6 => ("cmps", "%es:(%rdi),%ds:(%rsi)".to_string()),
+                    0xaa => ("stos", format!("{},%es:(%rdi)", self.reg_name(0, size))),
+                    0xac => ("lods", format!("%ds:(%rsi),{}", self.reg_name(0, size))),
+                    _ => ("scas", format!("%es:(%rdi),{}", self.reg_name(0, size))),
+                };
+                let prefix = match (self.rep, opcode & 0xfe) {
+                    (Some(0xf3), 0xa6) | (Some(0xf3), 0xae) => "repz ",
+                    (Some(0xf2), _) => "repnz ",
+                    (Some(_), _) => "rep ",
+                    (None, _) => "",
+                };
+                // The register operand gives the size away, except for movs and cmps
+                let suffix = match op {
+                    "movs" | "cmps" => suffix(size),
+                    _ => "",
+                };
+                format!("{}{}{} {}", prefix, op, suffix, operands)
+            }
+            0xa8 => {
+                let imm = self.imm(1)?;
+                self.format("test", &[self.reg(0, 1), Operand::Imm(imm)], 1)
+            }
+            0xa9 => {
+                let imm = self.imm(size.min(4))?;
+                self.format("test", &[self.reg(0, size), Operand::Imm(imm)], size)
+            }
+            0xb0..=0xb7 => {
+                let imm = self.imm(1)?;
+                let reg = (opcode & 7) | ((self.rex & 1) << 3);
+                self.format("mov", &[self.reg(reg, 1), Operand::Imm(imm)], 1)
+            }
+            0xb8..=0xbf => {
+                let imm = self.imm(if size == 8 { 8 } else { size })?;
+                let reg = (opcode & 7) | ((self.rex & 1) << 3);
+                let op = if size == 8 { "movabs" } else { "mov" };
+                self.format(op, &[self.reg(reg, size), Operand::Imm(imm)], size)
+            }
+            0xc0 | 0xc1 | 0xd0 | 0xd1 | 0xd2 | 0xd3 => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, size);
+                let op = SHIFT_OPS[(reg & 7) as usize];
+                match opcode {
+                    0xc0 | 0xc1 => {
+                        let imm = self.imm(1)?;
+                        self.format(op, &[rm, Operand::Imm(imm)], size)
+                    }
+                    0xd2 | 0xd3 => self.format(op, &[rm, self.reg(1, 1)], size),
+                    _ => self.format(op, &[rm], size),
+                }
+            }
+            0xc2 => {
+                let imm = self.imm(2)?;
+                self.format("ret", &[Operand::Imm(imm & 0xffff)], 8)
+            }
+            0xc3 if self.rep.is_some() => "repz ret".to_string(),
+            0xc3 => "ret".to_string(),
+            0xc6 | 0xc7 => {
+                let size = if opcode == 0xc6 { 1 } else { size };
+                let (_, rm) = self.modrm()?;
+                let imm = self.imm(size.min(4))?;
+                let rm = self.rm(&rm, size);
+                self.format("mov", &[rm, Operand::Imm(imm)], size)
+            }
+            0xc9 => "leave".to_string(),
+            0xcc => "int3".to_string(),
+            0xcd => format!("{:<6} ${:#x}", "int", self.byte()?),
+            0xe8 | 0xe9 | 0xeb => {
+                let rel = self.imm(if opcode == 0xeb { 1 } else { 4 })?;
+                let target = (self.addr as i64 + self.pos as i64 + rel) as usize;
+                let op = if opcode == 0xe8 { "call" } else { "jmp" };
+                return Some((self.format(op, &[Operand::Addr(target)], 8), Some(target)));
+            }
+            0xd8..=0xdf => return Some((self.decode_x87(opcode)?, None)),
+            0xc4 | 0xc5 => return Some((self.decode_vex(opcode)?, None)),
+            0xf4 => "hlt".to_string(),
+            0xf5 => "cmc".to_string(),
+            0xf8 => "clc".to_string(),
+            0xf9 => "stc".to_string(),
+            0xfc => "cld".to_string(),
+            0xfd => "std".to_string(),
+            0xf6 | 0xf7 => {
+                let size = if opcode == 0xf6 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, size);
+                match reg & 7 {
+                    0 | 1 => {
+                        let imm = self.imm(size.min(4))?;
+                        self.format("test", &[rm, Operand::Imm(imm)], size)
+                    }
+                    op => {
+                        let op = ["", "", "not", "neg", "mul", "imul", "div", "idiv"][op as usize];
+                        self.format(op, &[rm], size)
+                    }
+                }
+            }
+            0xfe | 0xff => {
+                let (reg, rm) = self.modrm()?;
+                match (opcode, reg & 7) {
+                    (_, 0) | (_, 1) => {
+                        let size = if opcode == 0xfe { 1 } else { size };
+                        let rm = self.rm(&rm, size);
+                        self.format(if reg & 7 == 0 { "inc" } else { "dec" }, &[rm], size)
+                    }
+                    (0xff, 2) | (0xff, 4) => {
+                        let op = if reg & 7 == 2 { "call" } else { "jmp" };
+                        let rm = self.rm(&rm, 8);
+                        format!("{:<6} *{}", op, self.operand_text(&rm))
+                    }
+                    (0xff, 6) => {
+                        let rm = self.rm(&rm, 8);
+                        format!("{:<6} {}", "push", self.operand_text(&rm))
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some((text, None))
+    }
+
+    fn operand_text(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Reg(text) | Operand::Mem(text) => text.clone(),
+            Operand::Imm(imm) => format!("${:#x}", imm),
+            Operand::Addr(addr) => format!("{:#x}", addr),
+        }
+    }
+
+    /// Decodes the x87 floating point instructions, 0xd8-0xdf. Only the commonly used register
+    /// forms are known.
+    fn decode_x87(&mut self, opcode: u8) -> Option<String> {
+        let modrm = *self.bytes.get(self.pos)?;
+        if modrm < 0xc0 {
+            let (reg, rm) = self.modrm()?;
+            let op = X87_MEMORY_OPS[(opcode - 0xd8) as usize][(reg & 7) as usize];
+            return match (op, rm) {
+                ("", _) | (_, Rm::Reg(_)) => None,
+                (op, Rm::Mem(mem)) => Some(format!("{:<6} {}", op, mem)),
+            };
+        }
+        self.pos += 1;
+        let st = modrm & 7;
+        let text = match (opcode, modrm & 0xf8) {
+            (0xd8, 0xc0) => format!("{:<6} %st({}),%st", "fadd", st),
+            (0xd8, 0xc8) => format!("{:<6} %st({}),%st", "fmul", st),
+            (0xd8, 0xe0) => format!("{:<6} %st({}),%st", "fsub", st),
+            (0xd8, 0xf0) => format!("{:<6} %st({}),%st", "fdiv", st),
+            (0xd9, 0xc0) => format!("{:<6} %st({})", "fld", st),
+            (0xd9, 0xc8) => format!("{:<6} %st({})", "fxch", st),
+            (0xdd, 0xd0) => format!("{:<6} %st({})", "fst", st),
+            (0xdd, 0xd8) => format!("{:<6} %st({})", "fstp", st),
+            (0xdb, 0xe8) => format!("{:<6} %st({}),%st", "fucomi", st),
+            (0xdb, 0xf0) => format!("{:<6} %st({}),%st", "fcomi", st),
+            (0xdf, 0xe8) => format!("{:<6} %st({}),%st", "fucomip", st),
+            (0xdf, 0xf0) => format!("{:<6} %st({}),%st", "fcomip", st),
+            (0xda, 0xc0) => format!("{:<6} %st({}),%st", "fcmovb", st),
+            (0xda, 0xc8) => format!("{:<6} %st({}),%st", "fcmove", st),
+            (0xda, 0xd0) => format!("{:<6} %st({}),%st", "fcmovbe", st),
+            (0xdb, 0xc0) => format!("{:<6} %st({}),%st", "fcmovnb", st),
+            (0xdb, 0xc8) => format!("{:<6} %st({}),%st", "fcmovne", st),
+            (0xdb, 0xd0) => format!("{:<6} %st({}),%st", "fcmovnbe", st),
+            (0xdc, 0xc0) => format!("{:<6} %st,%st({})", "fadd", st),
+            (0xdc, 0xc8) => format!("{:<6} %st,%st({})", "fmul", st),
+            (0xde, 0xc0) => format!("{:<6} %st,%st({})", "faddp", st),
+            (0xde, 0xc8) => format!("{:<6} %st,%st({})", "fmulp", st),
+            // AT&T swaps the names of the reversed and plain forms here
+            (0xde, 0xe0) => format!("{:<6} %st,%st({})", "fsubp", st),
+            (0xde, 0xe8) => format!("{:<6} %st,%st({})", "fsubrp", st),
+            (0xde, 0xf0) => format!("{:<6} %st,%st({})", "fdivp", st),
+            (0xde, 0xf8) => format!("{:<6} %st,%st({})", "fdivrp", st),
+            _ => match (opcode, modrm) {
+                (0xd9, 0xe0) => "fchs",
+                (0xd9, 0xe1) => "fabs",
+                (0xd9, 0xe5) => "fxam",
+                (0xd9, 0xe8) => "fld1",
+                (0xd9, 0xee) => "fldz",
+                (0xd9, 0xc9) => "fxch",
+                (0xdb, 0xe2) => "fnclex",
+                (0xdb, 0xe3) => "fninit",
+                (0xde, 0xd9) => "fcompp",
+                (0xdf, 0xe0) => "fnstsw %ax",
+                _ => return None,
+            }
+            .to_string(),
+        };
+        Some(text)
+    }
+
+    /// Decodes the AVX instructions with a VEX prefix (0xc4 or 0xc5) that show up in compiled
+    /// code and libc's string functions. AVX-512 (EVEX) isn't supported.
+    fn decode_vex(&mut self, prefix: u8) -> Option<String> {
+        let byte1 = self.byte()?;
+        let (map, byte2) = if prefix == 0xc5 {
+            self.rex = 0x40 | ((!byte1 & 0x80) >> 5);
+            (1, byte1)
+        } else {
+            let byte2 = self.byte()?;
+            self.rex = 0x40 | ((!byte1 & 0xe0) >> 5) | ((byte2 & 0x80) >> 4);
+            (byte1 & 0x1f, byte2)
+        };
+        let vvvv = (!byte2 >> 3) & 0xf;
+        let size = if byte2 & 4 != 0 { 32 } else { 16 };
+        let pp = byte2 & 3;
+        let opcode = self.byte()?;
+        let gp_size = if self.rex_w() { 8 } else { 4 };
+
+        let text = match (map, pp, opcode) {
+            (1, 0, 0x77) => if size == 32 { "vzeroall" } else { "vzeroupper" }.to_string(),
+            (1, _, 0x10) | (1, _, 0x11) | (1, _, 0x28) | (1, _, 0x29) | (1, 1, 0x6f)
+            | (1, 1, 0x7f) | (1, 2, 0x6f) | (1, 2, 0x7f) | (1, 1, 0xe7) => {
+                let (reg, rm) = self.modrm()?;
+                let op = match (opcode, pp) {
+                    (0x10, 0) | (0x11, 0) => "vmovups",
+                    (0x10, 1) | (0x11, 1) => "vmovupd",
+                    (0x10, 2) | (0x11, 2) => "vmovss",
+                    (0x10, _) | (0x11, _) => "vmovsd",
+                    (0x28, 1) | (0x29, 1) => "vmovapd",
+                    (0x28, _) | (0x29, _) => "vmovaps",
+                    (0x6f, 1) | (0x7f, 1) => "vmovdqa",
+                    (0xe7, _) => "vmovntdq",
+                    _ => "vmovdqu",
+                };
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                match opcode {
+                    0x10 | 0x28 | 0x6f => self.format(op, &[reg, rm], size),
+                    _ => self.format(op, &[rm, reg], size),
+                }
+            }
+            (1, 1, 0x6e) | (1, 1, 0x7e) => {
+                let (reg, rm) = self.modrm()?;
+                let op = if self.rex_w() { "vmovq" } else { "vmovd" };
+                let (xmm, gp) = (self.reg(reg, 16), self.rm(&rm, gp_size));
+                if opcode == 0x6e {
+                    self.format(op, &[xmm, gp], gp_size)
+                } else {
+                    self.format(op, &[gp, xmm], gp_size)
+                }
+            }
+            (1, 2, 0x7e) | (1, 1, 0xd6) => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode == 0x7e {
+                    self.format("vmovq", &[reg, rm], 16)
+                } else {
+                    self.format("vmovq", &[rm, reg], 16)
+                }
+            }
+            (1, 1, 0xd7) => {
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, size);
+                format!("{:<6} {},{}", "vpmovmskb", self.operand_text(&rm), self.reg_name(reg, 4))
+            }
+            (1, 0, 0x54) | (1, 1, 0x54) | (1, 0, 0x56) | (1, 1, 0x56) | (1, 0, 0x57)
+            | (1, 1, 0x57) | (1, _, 0x58) | (1, _, 0x59) | (1, _, 0x5c) | (1, _, 0x5e) => {
+                let (reg, rm) = self.modrm()?;
+                let base = match opcode {
+                    0x54 => "vand",
+                    0x56 => "vor",
+                    0x57 => "vxor",
+                    0x58 => "vadd",
+                    0x59 => "vmul",
+                    0x5c => "vsub",
+                    _ => "vdiv",
+                };
+                let op = format!("{}{}", base, ["ps", "pd", "ss", "sd"][pp as usize]);
+                let size = if pp >= 2 { 16 } else { size };
+                let (reg, src, rm) = (self.reg(reg, size), self.reg(vvvv, size), self.rm(&rm, size));
+                self.format(&op, &[reg, src, rm], size)
+            }
+            (1, 1, _) if SSE2_INT_OPS.iter().any(|(code, _)| *code == opcode) => {
+                let op = SSE2_INT_OPS.iter().find(|(code, _)| *code == opcode).unwrap().1;
+                let (reg, rm) = self.modrm()?;
+                let (reg, src, rm) = (self.reg(reg, size), self.reg(vvvv, size), self.rm(&rm, size));
+                self.format(&format!("v{}", op), &[reg, src, rm], size)
+            }
+            (2, 1, 0x00) | (2, 1, 0x3b) => {
+                let (reg, rm) = self.modrm()?;
+                let op = if opcode == 0 { "vpshufb" } else { "vpminud" };
+                let (reg, src, rm) = (self.reg(reg, size), self.reg(vvvv, size), self.rm(&rm, size));
+                self.format(op, &[reg, src, rm], size)
+            }
+            (2, 1, 0x17) => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format("vptest", &[reg, rm], size)
+            }
+            (2, 1, 0x18) | (2, 1, 0x58) | (2, 1, 0x78) | (2, 1, 0x79) => {
+                let (reg, rm) = self.modrm()?;
+                let op = match opcode {
+                    0x18 => "vbroadcastss",
+                    0x58 => "vpbroadcastd",
+                    0x78 => "vpbroadcastb",
+                    _ => "vpbroadcastw",
+                };
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, 16));
+                self.format(op, &[reg, rm], size)
+            }
+            (2, _, 0xf7) | (2, 0, 0xf5) => {
+                let (reg, rm) = self.modrm()?;
+                let op = match (opcode, pp) {
+                    (0xf5, _) => "bzhi",
+                    (_, 0) => "bextr",
+                    (_, 1) => "shlx",
+                    (_, 2) => "sarx",
+                    _ => "shrx",
+                };
+                let (reg, rm, src) = (self.reg(reg, gp_size), self.rm(&rm, gp_size), self.reg(vvvv, gp_size));
+                self.format(op, &[reg, rm, src], gp_size)
+            }
+            (2, 0, 0xf2) => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, src, rm) = (self.reg(reg, gp_size), self.reg(vvvv, gp_size), self.rm(&rm, gp_size));
+                self.format("andn", &[reg, src, rm], gp_size)
+            }
+            (2, 0, 0xf3) => {
+                let (reg, rm) = self.modrm()?;
+                let op = match reg & 7 {
+                    1 => "blsr",
+                    2 => "blsmsk",
+                    3 => "blsi",
+                    _ => return None,
+                };
+                let (dest, rm) = (self.reg(vvvv, gp_size), self.rm(&rm, gp_size));
+                self.format(op, &[dest, rm], gp_size)
+            }
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    /// Decodes the two byte opcodes, 0x0f xx.
+    fn decode_0f(&mut self) -> Option<(String, Option<usize>)> {
+        let opcode = self.byte()?;
+        let size = self.opsize();
+        // SSE instructions pick their variant with the 0x66/0xf3/0xf2 prefix
+        let sse = |ps: &str, pd: &str, ss: &str, sd: &str, decoder: &Decoder| match decoder.rep {
+            Some(0xf3) => ss.to_string(),
+            Some(_) => sd.to_string(),
+            None if decoder.opsize16 => pd.to_string(),
+            None => ps.to_string(),
+        };
+        let text = match opcode {
+            0x05 => "syscall".to_string(),
+            0x38 | 0x3a if self.opsize16 => {
+                let escape = self.byte()?;
+                let op = match (opcode, escape) {
+                    (0x38, 0x00) => "pshufb",
+                    (0x38, 0x17) => "ptest",
+                    (0x38, 0x38) => "pminsb",
+                    (0x38, 0x3a) => "pminuw",
+                    (0x38, 0x3b) => "pminud",
+                    (0x38, 0x3f) => "pmaxud",
+                    (0x3a, 0x0f) => "palignr",
+                    (0x3a, 0x60) => "pcmpestrm",
+                    (0x3a, 0x61) => "pcmpestri",
+                    (0x3a, 0x62) => "pcmpistrm",
+                    (0x3a, 0x63) => "pcmpistri",
+                    _ => return None,
+                };
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode == 0x3a {
+                    let imm = self.imm(1)? & 0xff;
+                    self.format(op, &[reg, rm, Operand::Imm(imm)], 16)
+                } else {
+                    self.format(op, &[reg, rm], 16)
+                }
+            }
+            0x2b if self.rep.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let op = if self.opsize16 { "movntpd" } else { "movntps" };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format(op, &[rm, reg], 16)
+            }
+            0x50 if self.rep.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let op = if self.opsize16 { "movmskpd" } else { "movmskps" };
+                let rm = self.rm(&rm, 16);
+                format!("{:<6} {},{}", op, self.operand_text(&rm), self.reg_name(reg, 4))
+            }
+            0x0b => "ud2".to_string(),
+            0x1e if self.rep == Some(0xf3) && self.bytes.get(self.pos) == Some(&0xfa) => {
+                self.pos += 1;
+                "endbr64".to_string()
+            }
+            0x1f => {
+                let (_, rm) = self.modrm()?;
+                let rm = self.rm(&rm, size);
+                self.format("nop", &[rm], size)
+            }
+            0x10 | 0x11 | 0x28 | 0x29 => {
+                let (reg, rm) = self.modrm()?;
+                let op = if opcode < 0x28 {
+                    sse("movups", "movupd", "movss", "movsd", self)
+                } else {
+                    sse("movaps", "movapd", "movaps", "movapd", self)
+                };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode & 1 == 0 {
+                    self.format(&op, &[reg, rm], 16)
+                } else {
+                    self.format(&op, &[rm, reg], 16)
+                }
+            }
+            0x2a => {
+                let (reg, rm) = self.modrm()?;
+                let op = sse("cvtpi2ps", "cvtpi2pd", "cvtsi2ss", "cvtsi2sd", self);
+                let rm = self.rm(&rm, size);
+                let op = match rm {
+                    Operand::Mem(_) => format!("{}{}", op, suffix(size)),
+                    _ => op,
+                };
+                format!("{:<6} {},{}", op, self.operand_text(&rm), self.reg_name(reg, 16))
+            }
+            0x2c | 0x2d => {
+                let (reg, rm) = self.modrm()?;
+                let op = if opcode == 0x2c {
+                    sse("cvttps2pi", "cvttpd2pi", "cvttss2si", "cvttsd2si", self)
+                } else {
+                    sse("cvtps2pi", "cvtpd2pi", "cvtss2si", "cvtsd2si", self)
+                };
+                let rm = self.rm(&rm, 16);
+                format!("{:<6} {},{}", op, self.operand_text(&rm), self.reg_name(reg, size))
+            }
+            0x2e | 0x2f | 0x51 | 0x54 | 0x55 | 0x56 | 0x57 | 0x58 | 0x59 | 0x5a | 0x5c | 0x5d
+            | 0x5e | 0x5f => {
+                let (reg, rm) = self.modrm()?;
+                let op = match opcode {
+                    0x2e => sse("ucomiss", "ucomisd", "ucomiss", "ucomisd", self),
+                    0x2f => sse("comiss", "comisd", "comiss", "comisd", self),
+                    0x51 => sse("sqrtps", "sqrtpd", "sqrtss", "sqrtsd", self),
+                    0x54 => sse("andps", "andpd", "andps", "andpd", self),
+                    0x55 => sse("andnps", "andnpd", "andnps", "andnpd", self),
+                    0x56 => sse("orps", "orpd", "orps", "orpd", self),
+                    0x57 => sse("xorps", "xorpd", "xorps", "xorpd", self),
+                    0x58 => sse("addps", "addpd", "addss", "addsd", self),
+                    0x59 => sse("mulps", "mulpd", "mulss", "mulsd", self),
+                    0x5a => sse("cvtps2pd", "cvtpd2ps", "cvtss2sd", "cvtsd2ss", self),
+                    0x5c => sse("subps", "subpd", "subss", "subsd", self),
+                    0x5d => sse("minps", "minpd", "minss", "minsd", self),
+                    0x5e => sse("divps", "divpd", "divss", "divsd", self),
+                    _ => sse("maxps", "maxpd", "maxss", "maxsd", self),
+                };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format(&op, &[reg, rm], 16)
+            }
+            0x6e | 0x7e if self.opsize16 || self.rep.is_some() => {
+                let (reg, rm) = self.modrm()?;
+                if opcode == 0x7e && self.rep == Some(0xf3) {
+                    let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                    self.format("movq", &[reg, rm], 16)
+                } else {
+                    let gp_size = if self.rex_w() { 8 } else { 4 };
+                    let op = if self.rex_w() { "movq" } else { "movd" };
+                    let (xmm, gp) = (self.reg(reg, 16), self.rm(&rm, gp_size));
+                    if opcode == 0x6e {
+                        self.format(op, &[xmm, gp], gp_size)
+                    } else {
+                        self.format(op, &[gp, xmm], gp_size)
+                    }
+                }
+            }
+            0x6f | 0x7f if self.opsize16 || self.rep == Some(0xf3) => {
+                let (reg, rm) = self.modrm()?;
+                let op = if self.opsize16 { "movdqa" } else { "movdqu" };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode == 0x6f {
+                    self.format(op, &[reg, rm], 16)
+                } else {
+                    self.format(op, &[rm, reg], 16)
+                }
+            }
+            0x70 if self.opsize16 || self.rep.is_some() => {
+                let (reg, rm) = self.modrm()?;
+                let op = sse("", "pshufd", "pshufhw", "pshuflw", self);
+                let imm = self.imm(1)? & 0xff;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format(&op, &[reg, rm, Operand::Imm(imm)], 16)
+            }
+            0x71..=0x73 if self.opsize16 => {
+                let (reg, rm) = self.modrm()?;
+                let width = ["w", "d", "q"][(opcode - 0x71) as usize];
+                let op = match (opcode, reg & 7) {
+                    (0x73, 3) => "psrldq".to_string(),
+                    (0x73, 7) => "pslldq".to_string(),
+                    (_, 2) => format!("psrl{}", width),
+                    (_, 4) => format!("psra{}", width),
+                    (_, 6) => format!("psll{}", width),
+                    _ => return None,
+                };
+                let imm = self.imm(1)? & 0xff;
+                let rm = self.rm(&rm, 16);
+                self.format(&op, &[rm, Operand::Imm(imm)], 16)
+            }
+            0xd7 if self.opsize16 => {
+                let (reg, rm) = self.modrm()?;
+                let rm = self.rm(&rm, 16);
+                format!("{:<6} {},{}", "pmovmskb", self.operand_text(&rm), self.reg_name(reg, 4))
+            }
+            0xe7 if self.opsize16 => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format("movntdq", &[rm, reg], 16)
+            }
+            _ if self.opsize16 && SSE2_INT_OPS.iter().any(|(code, _)| *code == opcode) => {
+                let op = SSE2_INT_OPS.iter().find(|(code, _)| *code == opcode).unwrap().1;
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format(op, &[reg, rm], 16)
+            }
+            0x12 | 0x13 | 0x16 | 0x17 if self.rep.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let op = match (opcode, &rm, self.opsize16) {
+                    (0x12, Rm::Reg(_), false) => "movhlps",
+                    (0x16, Rm::Reg(_), false) => "movlhps",
+                    (0x12, _, false) | (0x13, _, false) => "movlps",
+                    (0x12, _, true) | (0x13, _, true) => "movlpd",
+                    (_, _, false) => "movhps",
+                    (_, _, true) => "movhpd",
+                };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode & 1 == 0 {
+                    self.format(op, &[reg, rm], 16)
+                } else {
+                    self.format(op, &[rm, reg], 16)
+                }
+            }
+            0x14 | 0x15 | 0xc6 if self.rep.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let op = match opcode {
+                    0x14 => sse("unpcklps", "unpcklpd", "", "", self),
+                    0x15 => sse("unpckhps", "unpckhpd", "", "", self),
+                    _ => sse("shufps", "shufpd", "", "", self),
+                };
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                if opcode == 0xc6 {
+                    let imm = self.imm(1)? & 0xff;
+                    self.format(&op, &[reg, rm, Operand::Imm(imm)], 16)
+                } else {
+                    self.format(&op, &[reg, rm], 16)
+                }
+            }
+            0xd6 if self.opsize16 => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format("movq", &[rm, reg], 16)
+            }
+            0xef if self.opsize16 => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, 16), self.rm(&rm, 16));
+                self.format("pxor", &[reg, rm], 16)
+            }
+            0x40..=0x4f => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format(&format!("cmov{}", CONDITIONS[(opcode & 0xf) as usize]), &[reg, rm], size)
+            }
+            0x80..=0x8f => {
+                let rel = self.imm(4)?;
+                let target = (self.addr as i64 + self.pos as i64 + rel) as usize;
+                let op = format!("j{}", CONDITIONS[(opcode & 0xf) as usize]);
+                return Some((self.format(&op, &[Operand::Addr(target)], 8), Some(target)));
+            }
+            0x90..=0x9f => {
+                let (_, rm) = self.modrm()?;
+                let rm = self.rm(&rm, 1);
+                format!("{:<6} {}", format!("set{}", CONDITIONS[(opcode & 0xf) as usize]), self.operand_text(&rm))
+            }
+            0xa2 => "cpuid".to_string(),
+            0x31 => "rdtsc".to_string(),
+            0x01 if self.bytes.get(self.pos) == Some(&0xd0) => {
+                self.pos += 1;
+                "xgetbv".to_string()
+            }
+            0x01 if self.bytes.get(self.pos) == Some(&0xd5) => {
+                self.pos += 1;
+                "xend".to_string()
+            }
+            0x01 if self.bytes.get(self.pos) == Some(&0xd6) => {
+                self.pos += 1;
+                "xtest".to_string()
+            }
+            0xae if self.bytes.get(self.pos).map_or(false, |&modrm| modrm >= 0xe8) => {
+                let modrm = self.byte()?;
+                match modrm & 0xf8 {
+                    0xe8 => "lfence",
+                    0xf0 => "mfence",
+                    _ => "sfence",
+                }
+                .to_string()
+            }
+            0x0d | 0x18 => {
+                let (reg, rm) = self.modrm()?;
+                let op = match (opcode, reg & 7) {
+                    (0x0d, 1) => "prefetchw",
+                    (0x0d, _) => "prefetch",
+                    (_, 0) => "prefetchnta",
+                    (_, 1) => "prefetcht0",
+                    (_, 2) => "prefetcht1",
+                    (_, 3) => "prefetcht2",
+                    _ => return None,
+                };
+                let rm = self.rm(&rm, 8);
+                format!("{:<6} {}", op, self.operand_text(&rm))
+            }
+            0xa3 | 0xab | 0xb3 | 0xbb => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format(BIT_OPS[((opcode >> 3) & 3) as usize], &[rm, reg], size)
+            }
+            0xba => {
+                let (reg, rm) = self.modrm()?;
+                if reg & 7 < 4 {
+                    return None;
+                }
+                let imm = self.imm(1)? & 0xff;
+                let rm = self.rm(&rm, size);
+                self.format(BIT_OPS[(reg & 3) as usize], &[rm, Operand::Imm(imm)], size)
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => {
+                let (reg, rm) = self.modrm()?;
+                let op = if opcode < 0xac { "shld" } else { "shrd" };
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                if opcode & 1 == 0 {
+                    let imm = self.imm(1)? & 0xff;
+                    self.format(op, &[rm, reg, Operand::Imm(imm)], size)
+                } else {
+                    self.format(op, &[rm, reg, self.reg(1, 1)], size)
+                }
+            }
+            0xbc | 0xbd => {
+                let (reg, rm) = self.modrm()?;
+                let op = match (opcode, self.rep) {
+                    (0xbc, Some(0xf3)) => "tzcnt",
+                    (0xbd, Some(0xf3)) => "lzcnt",
+                    (0xbc, _) => "bsf",
+                    _ => "bsr",
+                };
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format(op, &[reg, rm], size)
+            }
+            0xb0 | 0xb1 | 0xc0 | 0xc1 => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let op = if opcode < 0xc0 { "cmpxchg" } else { "xadd" };
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format(op, &[rm, reg], size)
+            }
+            0xc7 => {
+                let (reg, rm) = self.modrm()?;
+                match (reg & 7, &rm) {
+                    (1, Rm::Mem(mem)) => {
+                        let op = if self.rex_w() { "cmpxchg16b" } else { "cmpxchg8b" };
+                        format!("{:<6} {}", op, mem)
+                    }
+                    _ => return None,
+                }
+            }
+            0xaf => {
+                let (reg, rm) = self.modrm()?;
+                let (reg, rm) = (self.reg(reg, size), self.rm(&rm, size));
+                self.format("imul", &[reg, rm], size)
+            }
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let (reg, rm) = self.modrm()?;
+                let from = if opcode & 1 == 0 { 1 } else { 2 };
+                let op = format!(
+                    "mov{}{}{}",
+                    if opcode < 0xbe { "z" } else { "s" },
+                    suffix(from),
+                    suffix(size)
+                );
+                let rm = self.rm(&rm, from);
+                format!("{:<6} {},{}", op, self.operand_text(&rm), self.reg_name(reg, size))
+            }
+            0xc8..=0xcf => {
+                let reg = (opcode & 7) | ((self.rex & 1) << 3);
+                self.format("bswap", &[self.reg(reg, size)], size)
+            }
+            _ => return None,
+        };
+        Some((text, None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Decodes each `(bytes, text)` at 0x401000, checking the text against objdump's and that the
+    /// whole instruction was consumed.
+    fn check(table: &[(&[u8], &str)]) {
+        for (bytes, text) in table {
+            let instruction = decode(bytes, 0x401000);
+            assert_eq!(instruction.text, *text, "decoding {:x?}", bytes);
+            assert_eq!(instruction.len, bytes.len(), "length of {}", text);
+            assert_eq!(instruction.target, None, "target of {}", text);
+            assert_eq!(instruction.rip_ref, None, "rip_ref of {}", text);
+        }
+    }
+
+    #[test]
+    fn test_modrm_sib_and_rex() {
+        check(&[
+            (&[0x55], "push   %rbp"),
+            (&[0x48, 0x89, 0xe5], "mov    %rsp,%rbp"),
+            // REX.R and REX.B
+            (&[0x4d, 0x89, 0xc7], "mov    %r8,%r15"),
+            (&[0x4c, 0x89, 0x18], "mov    %r11,(%rax)"),
+            (&[0x8b, 0x45, 0xec], "mov    -0x14(%rbp),%eax"),
+            (&[0x89, 0x45, 0xec], "mov    %eax,-0x14(%rbp)"),
+            (&[0x49, 0x8b, 0x45, 0x10], "mov    0x10(%r13),%rax"),
+            // %r13 as a base always needs a displacement, like %rbp
+            (&[0x41, 0x8b, 0x45, 0x00], "mov    0x0(%r13),%eax"),
+            // %rsp and %r12 as a base always need a SIB byte
+            (&[0x4d, 0x8b, 0x0c, 0x24], "mov    (%r12),%r9"),
+            (&[0x48, 0x8b, 0x3c, 0x24], "mov    (%rsp),%rdi"),
+            (&[0x4c, 0x89, 0x64, 0x24, 0x18], "mov    %r12,0x18(%rsp)"),
+            (&[0x8b, 0x4c, 0x98, 0x08], "mov    0x8(%rax,%rbx,4),%ecx"),
+            // REX.X extends the index
+            (&[0x4a, 0x8d, 0x14, 0x50], "lea    (%rax,%r10,2),%rdx"),
+            (
+                &[0x4f, 0x8b, 0x5c, 0xf4, 0x40],
+                "mov    0x40(%r12,%r14,8),%r11",
+            ),
+            (
+                &[0x46, 0x8b, 0x84, 0x3b, 0x00, 0xf0, 0xff, 0xff],
+                "mov    -0x1000(%rbx,%r15,1),%r8d",
+            ),
+            // No base register
+            (
+                &[0x4a, 0x8b, 0x04, 0xcd, 0x00, 0x00, 0x00, 0x00],
+                "mov    0x0(,%r9,8),%rax",
+            ),
+            // Any REX prefix makes the byte registers %spl-%dil rather than %ah-%bh
+            (&[0x40, 0x88, 0xf0], "mov    %sil,%al"),
+            (&[0x44, 0x38, 0xd1], "cmp    %r10b,%cl"),
+            (&[0x0f, 0xb6, 0x00], "movzbl (%rax),%eax"),
+            (&[0x48, 0x0f, 0xbe, 0x75, 0xff], "movsbq -0x1(%rbp),%rsi"),
+            (&[0x48, 0x63, 0xff], "movslq %edi,%rdi"),
+            (&[0x31, 0xc0], "xor    %eax,%eax"),
+            (&[0x48, 0x6b, 0xcb, 0x64], "imul   $0x64,%rbx,%rcx"),
+            (&[0x90], "nop"),
+            (&[0xc3], "ret"),
+            (&[0xc9], "leave"),
+        ]);
+    }
+
+    #[test]
+    fn test_operand_sizes() {
+        check(&[
+            (
+                &[0xc7, 0x45, 0xfc, 0x05, 0x00, 0x00, 0x00],
+                "movl   $0x5,-0x4(%rbp)",
+            ),
+            // 0x66 makes the operand 16 bits, immediates included
+            (&[0x66, 0xc7, 0x00, 0x07, 0x00], "movw   $0x7,(%rax)"),
+            (&[0x66, 0x89, 0xc3], "mov    %ax,%bx"),
+            (&[0x66, 0x44, 0x89, 0x0a], "mov    %r9w,(%rdx)"),
+            (&[0x66, 0x05, 0x34, 0x12], "add    $0x1234,%ax"),
+            (&[0x66, 0x83, 0x00, 0x01], "addw   $0x1,(%rax)"),
+            (&[0x66, 0x41, 0xba, 0x10, 0x00], "mov    $0x10,%r10w"),
+            // REX.W makes it 64 bits, with a sign-extended 32 bit immediate
+            (&[0x48, 0x83, 0xc4, 0x10], "add    $0x10,%rsp"),
+            (&[0x41, 0x83, 0xec, 0x08], "sub    $0x8,%r12d"),
+            (
+                &[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff],
+                "mov    $0xffffffffffffffff,%rax",
+            ),
+            (&[0xb8, 0xff, 0xff, 0xff, 0xff], "mov    $0xffffffff,%eax"),
+            (
+                &[0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+                "movabs $0x1122334455667788,%rax",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_rip_relative() {
+        // (address, bytes, text, the address referred to)
+        let table: &[(usize, &[u8], &str, usize)] = &[
+            (
+                0x401026,
+                &[0x8b, 0x05, 0xe5, 0x2e, 0x00, 0x00],
+                "mov    0x2ee5(%rip),%eax",
+                0x403f11,
+            ),
+            (
+                0x40102c,
+                &[0x48, 0x8d, 0x3d, 0x00, 0x01, 0x00, 0x00],
+                "lea    0x100(%rip),%rdi",
+                0x401133,
+            ),
+            (
+                0x40107c,
+                &[0xff, 0x25, 0x08, 0x00, 0x00, 0x00],
+                "jmp    *0x8(%rip)",
+                0x40108a,
+            ),
+            // An immediate after the displacement still counts towards the end of the instruction
+            (
+                0x40101c,
+                &[0x83, 0x3d, 0x10, 0x00, 0x00, 0x00, 0x00],
+                "cmpl   $0x0,0x10(%rip)",
+                0x401033,
+            ),
+            (
+                0x401023,
+                &[0xc7, 0x05, 0x20, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+                "movl   $0x1,0x20(%rip)",
+                0x40104d,
+            ),
+            (
+                0x40102d,
+                &[0xf2, 0x0f, 0x10, 0x05, 0x30, 0x00, 0x00, 0x00],
+                "movsd  0x30(%rip),%xmm0",
+                0x401065,
+            ),
+        ];
+        for (addr, bytes, text, rip_ref) in table {
+            let instruction = decode(bytes, *addr);
+            assert_eq!(instruction.text, *text);
+            assert_eq!(instruction.len, bytes.len(), "length of {}", text);
+            assert_eq!(instruction.rip_ref, Some(*rip_ref), "rip_ref of {}", text);
+            assert_eq!(instruction.target, None, "target of {}", text);
+        }
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        // (address, bytes, text, target)
+        let table: &[(usize, &[u8], &str, usize)] = &[
+            (
+                0x40106b,
+                &[0xe8, 0x90, 0xff, 0xff, 0xff],
+                "call   0x401000",
+                0x401000,
+            ),
+            (0x401070, &[0xeb, 0x8e], "jmp    0x401000", 0x401000),
+            (
+                0x401058,
+                &[0xe9, 0x00, 0xfe, 0xff, 0xff],
+                "jmp    0x400e5d",
+                0x400e5d,
+            ),
+            (0x401072, &[0x74, 0x8c], "je     0x401000", 0x401000),
+            (0x401074, &[0x75, 0x0d], "jne    0x401083", 0x401083),
+            (0x401076, &[0x7c, 0x0b], "jl     0x401083", 0x401083),
+            (0x401078, &[0x7f, 0x86], "jg     0x401000", 0x401000),
+            (
+                0x401052,
+                &[0x0f, 0x84, 0x00, 0x01, 0x00, 0x00],
+                "je     0x401158",
+                0x401158,
+            ),
+        ];
+        for (addr, bytes, text, target) in table {
+            let instruction = decode(bytes, *addr);
+            assert_eq!(instruction.text, *text);
+            assert_eq!(instruction.len, bytes.len(), "length of {}", text);
+            assert_eq!(instruction.target, Some(*target), "target of {}", text);
+            assert_eq!(instruction.rip_ref, None, "rip_ref of {}", text);
+        }
+
+        // Indirect calls have no target we can know ahead of time
+        let instruction = decode(&[0xff, 0xd0], 0x40107a);
+        assert_eq!(instruction.text, "call   *%rax");
+        assert_eq!(instruction.target, None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // mov 0x40302010(%rsp),%rax, cut short at every length
+        let bytes = [0x48, 0x8b, 0x84, 0x24, 0x10, 0x20, 0x30, 0x40];
+        assert_eq!(
+            decode(&bytes, 0x401000).text,
+            "mov    0x40302010(%rsp),%rax"
+        );
+        for len in 0..bytes.len() {
+            let instruction = decode(&bytes[..len], 0x401000);
+            assert_eq!(instruction.text, "(bad)", "{} bytes", len);
+            assert_eq!(instruction.len, 1, "{} bytes", len);
+            assert_eq!(instruction.target, None);
+            assert_eq!(instruction.rip_ref, None);
+        }
+        for bytes in [
+            &[0xe8, 0x90, 0xff][..],
+            &[0x0f, 0x84, 0x00],
+            &[0x66],
+            &[0x0f],
+        ]
+        .iter()
+        {
+            assert_eq!(
+                decode(bytes, 0x401000).text,
+                "(bad)",
+                "decoding {:x?}",
+                bytes
+            );
+        }
+    }
+}
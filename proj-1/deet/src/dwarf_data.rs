@@ -1,9 +1,21 @@
 use crate::gimli_wrapper;
+use crate::gimli_wrapper::SectionDataByName;
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection, SymbolKind};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::path::Path;
 use std::{fmt, fs};
 
+// The note type of .note.gnu.build-id
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// The sections `info symbol` can name.
+const SECTION_NAMES: [&str; 12] = [
+    ".init", ".plt", ".plt.got", ".plt.sec", ".text", ".fini", ".rodata", ".data.rel.ro", ".got",
+    ".got.plt", ".data", ".bss",
+];
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
@@ -12,7 +24,38 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    types: HashMap<usize, Type>,
+    call_frames: gimli_wrapper::CallFrameInfo,
+    /// None when there is no usable DWARF, in which case we go by the symbol tables
+    addr2line: Option<Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>>,
+    /// Why the DWARF couldn't be read, if it couldn't
+    dwarf_error: Option<gimli_wrapper::Error>,
+    /// The separate file the DWARF came from, for a stripped binary
+    debug_file: Option<String>,
+    /// The entry point recorded in the ELF header
+    entry: usize,
+    /// How far the program was loaded from the addresses it was linked at, which is nonzero for
+    /// position-independent executables. The addresses in `files` already include it; the
+    /// CFI and addr2line lookups have it taken off
+    load_bias: usize,
+    /// The functions and objects in the ELF symbol tables, at their link-time addresses
+    symbols: Vec<ElfSymbol>,
+    /// The link-time address range of each of the `SECTION_NAMES` the file has
+    sections: Vec<(String, usize, usize)>,
+    /// The link-time address range of everything the file maps into memory
+    extent: (usize, usize),
+    /// The dynamic linker named in .interp, if the program has one
+    interpreter: Option<String>,
+    /// The shared libraries the program has loaded, each with its own debug info. Lookups by
+    /// name fall back to them, and lookups by address go to whichever one holds the address
+    libraries: Vec<SharedLibrary>,
+    libraries_loaded: usize,
+}
+
+/// A shared library loaded into the program, found through the dynamic linker's `link_map`.
+pub struct SharedLibrary {
+    pub name: String,
+    pub debug_data: DwarfData,
 }
 
 impl fmt::Debug for DwarfData {
@@ -28,34 +71,337 @@ impl From<gimli_wrapper::Error> for Error {
 }
 
 impl DwarfData {
-    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
-        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
-        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+    /// Loads the debug info of the program at `path`. If it has been stripped of its DWARF, the
+    /// separate debug file it points to is looked for in `debug_file_dirs` (like
+    /// `/usr/lib/debug`) and next to it, and its DWARF and symbols are used alongside the
+    /// program's own sections and call frame information.
+    pub fn from_file(path: &str, debug_file_dirs: &[String]) -> Result<DwarfData, Error> {
+        let mmap = map_file(path)?;
+        let object = object::File::parse(&mmap)
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let debug_file = match object.section_by_name(".debug_info") {
+            Some(_) => None,
+            None => find_debug_file(path, &object, debug_file_dirs),
+        };
+        let debug_mmap = debug_file.as_ref().and_then(|debug_file| map_file(debug_file).ok());
+        let debug_object = debug_mmap.as_ref().and_then(|mmap| object::File::parse(mmap).ok());
+        let dwarf_object = debug_object.as_ref().unwrap_or(&object);
+        let dwarf_path = match &debug_object {
+            Some(_) => debug_file.as_deref().unwrap_or(path),
+            None => path,
+        };
+        // dwz's file of shared DWARF, and the package `dwp` makes of -gsplit-dwarf's .dwo files
+        let sup_mmap = find_sup_file(dwarf_path, dwarf_object, debug_file_dirs)
+            .and_then(|sup_file| map_file(&sup_file).ok());
+        let sup_object = sup_mmap.as_ref().and_then(|mmap| object::File::parse(mmap).ok());
+        let dwp_mmap = map_file(&format!("{}.dwp", path)).ok();
+        let dwp_object = dwp_mmap.as_ref().and_then(|mmap| object::File::parse(mmap).ok());
+        // A stripped or half-built binary still has its symbol tables to go by
+        let (files, types, dwarf_error) = match gimli_wrapper::load_file(
+            dwarf_object,
+            sup_object.as_ref(),
+            dwp_object.as_ref(),
+            endian,
+        ) {
+            Ok((files, types)) => (files, types, None),
+            Err(err) => (Vec::new(), HashMap::new(), Some(err)),
+        };
+        // addr2line can't follow skeleton units to their split units. When it comes up empty,
+        // we fall back on our own line tables and function ranges.
+        let addr2line = match dwarf_error {
+            Some(_) => None,
+            None => gimli_wrapper::load_rc_dwarf(dwarf_object, endian)
+                .ok()
+                .and_then(|dwarf| Context::from_dwarf(dwarf).ok()),
+        };
+        let mut symbols = elf_symbols(&object);
+        if let Some(debug_object) = &debug_object {
+            symbols.extend(elf_symbols(debug_object));
+        }
+        let sections = SECTION_NAMES
+            .iter()
+            .filter_map(|&name| {
+                let section = object.section_by_name(name)?;
+                let start = section.address() as usize;
+                Some((name.to_string(), start, start + section.size() as usize))
+            })
+            .collect();
+        let mapped = || object.sections().filter(|section| section.address() != 0);
+        let extent = (
+            mapped().map(|section| section.address() as usize).min().unwrap_or(0),
+            mapped().map(|section| (section.address() + section.size()) as usize).max().unwrap_or(0),
+        );
+        let interpreter = object.section_data_by_name(".interp").map(|data| {
+            String::from_utf8_lossy(&data).trim_end_matches('\0').to_string()
+        });
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files,
+            types,
+            call_frames: gimli_wrapper::load_call_frame_info(&object, endian),
+            addr2line,
+            dwarf_error,
+            debug_file: debug_object.and(debug_file),
+            entry: object.entry() as usize,
+            load_bias: 0,
+            symbols,
+            sections,
+            extent,
+            interpreter,
+            libraries: Vec::new(),
+            libraries_loaded: 0,
+        })
+    }
+
+    /// Debug info for no program at all, for when the target can't be read.
+    pub fn empty() -> DwarfData {
+        DwarfData {
+            files: Vec::new(),
+            types: HashMap::new(),
+            call_frames: Default::default(),
+            addr2line: None,
+            dwarf_error: None,
+            debug_file: None,
+            entry: 0,
+            load_bias: 0,
+            symbols: Vec::new(),
+            sections: Vec::new(),
+            extent: (0, 0),
+            interpreter: None,
+            libraries: Vec::new(),
+            libraries_loaded: 0,
+        }
+    }
+
+    /// The separate debug file the DWARF was read from, if it wasn't in the program itself.
+    pub fn debug_file(&self) -> Option<&str> {
+        self.debug_file.as_deref()
+    }
+
+    /// Why the DWARF couldn't be used, when we have fallen back to the symbol tables.
+    pub fn dwarf_error(&self) -> Option<&gimli_wrapper::Error> {
+        self.dwarf_error.as_ref()
+    }
+
+    /// The dynamic linker the program asks for, e.g. `/lib64/ld-linux-x86-64.so.2`.
+    pub fn interpreter(&self) -> Option<&str> {
+        self.interpreter.as_deref()
+    }
+
+    /// Where .text ended up in memory.
+    pub fn text_range(&self) -> (usize, usize) {
+        match self.sections.iter().find(|section| section.0 == ".text") {
+            Some(&(_, start, end)) => (start.wrapping_add(self.load_bias), end.wrapping_add(self.load_bias)),
+            None => (0, 0),
+        }
+    }
+
+    /// Returns the name of the section `addr` is in, such as `.text` or `.data`.
+    pub fn get_section_for_addr(&self, addr: usize) -> Option<&str> {
+        if let Some(library) = self.get_library_for_addr(addr) {
+            return library.debug_data.get_section_for_addr(addr);
+        }
+        let addr = addr.wrapping_sub(self.load_bias);
+        self.sections
+            .iter()
+            .find(|&&(_, start, end)| start <= addr && addr < end)
+            .map(|section| &section.0[..])
+    }
+
+    /// Finds the symbol table entry containing `addr`, in the program or whichever library it
+    /// is in, and how far into it `addr` is.
+    fn get_elf_symbol_for_addr(&self, addr: usize) -> Option<(&ElfSymbol, usize)> {
+        if let Some(library) = self.get_library_for_addr(addr) {
+            return library.debug_data.get_elf_symbol_for_addr(addr);
+        }
+        let addr = addr.wrapping_sub(self.load_bias);
+        let symbol = self.symbols.iter().find(|symbol| {
+            symbol.address <= addr && addr < symbol.address + symbol.size.max(1)
+        })?;
+        Some((symbol, addr - symbol.address))
+    }
+
+    /// Looks up a function by name in the symbol tables of the program and then its
+    /// libraries, for when there is no DWARF for it.
+    fn get_elf_function_address(&self, name: &str) -> Option<usize> {
+        let symbol = self.symbols.iter().find(|symbol| symbol.is_function && symbol.name == name);
+        match symbol {
+            Some(symbol) => Some(symbol.address.wrapping_add(self.load_bias)),
+            None => self
+                .libraries
+                .iter()
+                .find_map(|library| library.debug_data.get_elf_function_address(name)),
+        }
+    }
+
+    /// Whether there is any DWARF at all, as opposed to just symbol tables.
+    pub fn has_debug_info(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    /// Looks up a symbol table entry by name and returns its address in memory.
+    pub fn get_symbol_address(&self, name: &str) -> Option<usize> {
+        let symbol = self.symbols.iter().find(|symbol| symbol.name == name)?;
+        Some(symbol.address.wrapping_add(self.load_bias))
+    }
+
+    pub fn libraries(&self) -> &[SharedLibrary] {
+        &self.libraries
+    }
+
+    /// Loads the debug info of the shared library at `path`, which was loaded `base` bytes
+    /// above its link-time addresses.
+    pub fn add_library(
+        &mut self,
+        path: &str,
+        base: usize,
+        debug_file_dirs: &[String],
+    ) -> Result<(), Error> {
+        let mut debug_data = DwarfData::from_file(path, debug_file_dirs)?;
+        debug_data.set_load_bias(base);
+        // Keep the library's type offsets apart from ours and every other library's
+        self.libraries_loaded += 1;
+        debug_data.rebase_types(self.libraries_loaded << 40);
+        self.libraries.push(SharedLibrary {
+            name: path.to_string(),
+            debug_data,
+        });
+        Ok(())
+    }
+
+    pub fn remove_library(&mut self, path: &str) {
+        self.libraries.retain(|library| library.name != path);
+    }
+
+    pub fn clear_libraries(&mut self) {
+        self.libraries.clear();
+    }
+
+    /// Returns the shared library mapped at `addr`, if any.
+    pub fn get_library_for_addr(&self, addr: usize) -> Option<&SharedLibrary> {
+        self.libraries.iter().find(|library| {
+            let debug_data = &library.debug_data;
+            let start = debug_data.extent.0.wrapping_add(debug_data.load_bias);
+            let end = debug_data.extent.1.wrapping_add(debug_data.load_bias);
+            start <= addr && addr < end
+        })
+    }
+
+    /// Our compilation units followed by those of every loaded library.
+    fn all_files(&self) -> impl Iterator<Item = &File> + '_ {
+        self.files.iter().chain(
+            self.libraries.iter().flat_map(|library| library.debug_data.files.iter()),
+        )
+    }
+
+    fn all_types(&self) -> impl Iterator<Item = &Type> + '_ {
+        self.types.values().chain(
+            self.libraries.iter().flat_map(|library| library.debug_data.types.values()),
+        )
+    }
+
+    /// Adds `tag` to every type offset, both where types are stored and where they are referred
+    /// to.
+    fn rebase_types(&mut self, tag: usize) {
+        let rebase = |offset: &mut Option<usize>| {
+            if let Some(offset) = offset {
+                *offset += tag;
+            }
+        };
+        let rebase_kind = |kind: &mut TypeKind| match kind {
+            TypeKind::Pointer(target)
+            | TypeKind::Const(target)
+            | TypeKind::Volatile(target)
+            | TypeKind::Restrict(target)
+            | TypeKind::Typedef(target)
+            | TypeKind::Array(target, _)
+            | TypeKind::Function(target) => rebase(target),
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                members.iter_mut().for_each(|member| rebase(&mut member.type_offset))
+            }
+            TypeKind::Base(_) | TypeKind::Enum(_) | TypeKind::Unknown => {}
+        };
+        self.types = self
+            .types
+            .drain()
+            .map(|(offset, mut entity_type)| {
+                rebase_kind(&mut entity_type.kind);
+                (offset + tag, entity_type)
+            })
+            .collect();
+        for file in self.files.iter_mut() {
+            let variables = file
+                .functions
+                .iter_mut()
+                .flat_map(|func| func.variables.iter_mut())
+                .chain(file.global_variables.iter_mut());
+            for var in variables {
+                rebase_kind(&mut var.entity_type.kind);
+            }
+        }
+    }
+
+    /// The entry point the program was linked with.
+    pub fn entry_point(&self) -> usize {
+        self.entry
+    }
+
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Records that the program was loaded `bias` bytes above its link-time addresses, and
+    /// moves every address we hand out accordingly.
+    pub fn set_load_bias(&mut self, bias: usize) {
+        let delta = bias.wrapping_sub(self.load_bias);
+        let relocate = |var: &mut Variable| {
+            if let Location::Address(addr) = &mut var.location {
+                *addr = addr.wrapping_add(delta);
+            }
+        };
+        for file in self.files.iter_mut() {
+            file.global_variables.iter_mut().for_each(relocate);
+            for func in file.functions.iter_mut() {
+                func.address = func.address.wrapping_add(delta);
+                // Static locals live at fixed addresses too
+                func.variables.iter_mut().for_each(relocate);
+            }
+            for line in file.lines.iter_mut() {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+        // The symbol tables and section ranges keep their link-time addresses
+        self.load_bias = bias;
+    }
+
+    /// Returns every compilation unit called `file`, or whose basename is `file`.
+    fn get_target_files<'a>(&'a self, file: &str) -> impl Iterator<Item = &'a File> {
+        let file = file.to_string();
+        let suffix = format!("/{}", file);
+        self.all_files().filter(move |f| {
+            f.name == file || (!file.contains("/") && f.name.ends_with(&suffix))
         })
     }
 
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+        self.get_target_files(file).next()
+    }
+
+    /// Returns the name of the compilation unit `file` refers to, which may be just its basename.
+    pub fn get_file_name(&self, file: &str) -> Option<&str> {
+        Some(&self.get_target_file(file)?.name)
     }
 
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+            None => self.files.first()?,
         };
         Some(
             target_file
@@ -73,26 +419,81 @@ impl DwarfData {
                 self.get_target_file(filename)?
                     .functions
                     .iter()
-                    .find(|func| func.name == func_name)?
+                    .find(|func| func.name == func_name && func.text_length > 0)?
                     .address,
             ),
             None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
+                for file in self.all_files() {
+                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name && func.text_length > 0) {
                         return Some(func.address);
                     }
                 }
-                None
+                self.get_elf_function_address(func_name)
             }
         }
     }
 
+    /// Returns where a breakpoint on `line_number` belongs in each compilation unit matching
+    /// `file` (or the first unit if there is no file): the lowest address of the first line at or
+    /// after `line_number` that has code.
+    pub fn get_lines_for_line(&self, file: Option<&str>, line_number: usize) -> Vec<Line> {
+        let target_files: Vec<&File> = match file {
+            Some(filename) => self.get_target_files(filename).collect(),
+            None => self.files.iter().take(1).collect(),
+        };
+        target_files
+            .into_iter()
+            .filter_map(|target_file| {
+                let number = target_file
+                    .lines
+                    .iter()
+                    .map(|line| line.number)
+                    .filter(|&number| number >= line_number)
+                    .min()?;
+                target_file
+                    .lines
+                    .iter()
+                    .filter(|line| line.number == number)
+                    .min_by_key(|line| line.address)
+                    .cloned()
+            })
+            .collect()
+    }
+
+    /// Returns the address of every function called `func_name` in the compilation units
+    /// matching `file`, or in all of them. Static functions may share a name across units.
+    pub fn get_addrs_for_function(&self, file: Option<&str>, func_name: &str) -> Vec<usize> {
+        let target_files: Vec<&File> = match file {
+            Some(filename) => self.get_target_files(filename).collect(),
+            None => self.all_files().collect(),
+        };
+        let addrs: Vec<usize> = target_files
+            .into_iter()
+            .flat_map(|target_file| target_file.functions.iter())
+            // Declarations of functions defined elsewhere, such as in a library, have no code
+            .filter(|func| func.name == func_name && func.text_length > 0)
+            .map(|func| func.address)
+            .collect();
+        if addrs.is_empty() && file.is_none() {
+            return self.get_elf_function_address(func_name).into_iter().collect();
+        }
+        addrs
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
-            .addr2line
-            .find_location(curr_addr.try_into().unwrap())
-            .ok()??;
+        if let Some(library) = self.get_library_for_addr(curr_addr) {
+            return library.debug_data.get_line_from_addr(curr_addr);
+        }
+        let location = self.addr2line.as_ref().and_then(|addr2line| {
+            addr2line
+                .find_location(curr_addr.wrapping_sub(self.load_bias).try_into().unwrap())
+                .ok()?
+        });
+        let location = match location {
+            Some(location) => location,
+            None => return self.get_line_from_tables(curr_addr),
+        };
         Some(Line {
             file: location.file?.to_string(),
             number: location.line?.try_into().unwrap(),
@@ -102,13 +503,152 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+        if let Some(library) = self.get_library_for_addr(curr_addr) {
+            return library.debug_data.get_function_from_addr(curr_addr);
+        }
+        let from_dwarf = self.addr2line.as_ref().and_then(|addr2line| {
+            let frame = addr2line
+                .find_frames(curr_addr.wrapping_sub(self.load_bias).try_into().unwrap())
+                .ok()?
+                .next()
+                .ok()??;
+            Some(frame.function?.raw_name().ok()?.to_string())
+        });
+        from_dwarf
+            .or_else(|| Some(self.get_function_for_addr(curr_addr)?.name.clone()))
+            .or_else(|| match self.get_elf_symbol_for_addr(curr_addr) {
+                Some((symbol, _)) if symbol.is_function => Some(symbol.name.clone()),
+                _ => None,
+            })
+    }
+
+    /// Finds the line containing `curr_addr` in the line tables we loaded ourselves, for when
+    /// addr2line can't make sense of the DWARF.
+    fn get_line_from_tables(&self, curr_addr: usize) -> Option<Line> {
+        let func = self.get_function_for_addr(curr_addr)?;
+        let line = self
+            .files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .filter(|line| func.address <= line.address && line.address <= curr_addr)
+            .max_by_key(|line| line.address)?;
+        Some(Line {
+            file: line.file.clone(),
+            number: line.number,
+            address: curr_addr,
+        })
+    }
+
+    /// Returns the function whose text contains `curr_addr`, using the `address` and
+    /// `text_length` recorded for each function.
+    pub fn get_function_for_addr(&self, curr_addr: usize) -> Option<&Function> {
+        self.all_files()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= curr_addr && curr_addr < func.address + func.text_length)
+    }
+
+    /// Returns the name, start and end of the function containing `curr_addr`, from the DWARF
+    /// or failing that the symbol tables.
+    pub fn get_function_range(&self, curr_addr: usize) -> Option<(&str, usize, usize)> {
+        if let Some(func) = self.get_function_for_addr(curr_addr) {
+            return Some((&func.name, func.address, func.address + func.text_length));
+        }
+        match self.get_elf_symbol_for_addr(curr_addr)? {
+            (symbol, offset) if symbol.is_function && symbol.size > 0 => {
+                let start = curr_addr - offset;
+                Some((&symbol.name, start, start + symbol.size))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the global variable called `name`, searching every compilation unit.
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.all_files()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns the function or global variable containing `curr_addr`, and how far into it
+    /// `curr_addr` is, for labels like `<main+4>`.
+    pub fn get_symbol_for_addr(&self, curr_addr: usize) -> Option<(&str, usize)> {
+        if let Some(func) = self.get_function_for_addr(curr_addr) {
+            return Some((&func.name, curr_addr - func.address));
+        }
+        self.all_files()
+            .flat_map(|file| file.global_variables.iter())
+            .find_map(|var| match var.location {
+                Location::Address(addr)
+                    if addr <= curr_addr && curr_addr < addr + var.entity_type.size.max(1) =>
+                {
+                    Some((&var.name[..], curr_addr - addr))
+                }
+                _ => None,
+            })
+            .or_else(|| {
+                let (symbol, offset) = self.get_elf_symbol_for_addr(curr_addr)?;
+                Some((&symbol.name[..], offset))
+            })
+    }
+
+    /// Looks up a type by its offset in .debug_info.
+    pub fn get_type(&self, offset: usize) -> Option<&Type> {
+        self.types.get(&offset).or_else(|| {
+            self.libraries
+                .iter()
+                .find_map(|library| library.debug_data.get_type(offset))
+        })
+    }
+
+    /// Looks up a named type such as `struct node`, `point_t` or `long int`.
+    pub fn get_type_by_name(&self, name: &str) -> Option<&Type> {
+        self.all_types()
+            .find(|entity_type| entity_type.name == name && entity_type.size > 0)
+            .or_else(|| self.all_types().find(|entity_type| entity_type.name == name))
+    }
+
+    /// Returns the value of the enumeration constant called `name`, e.g. `RED`.
+    pub fn get_enumerator_value(&self, name: &str) -> Option<i64> {
+        self.all_types().find_map(|entity_type| match &entity_type.kind {
+            TypeKind::Enum(enumerators) => enumerators
+                .iter()
+                .find(|enumerator| enumerator.name == name)
+                .map(|enumerator| enumerator.value),
+            _ => None,
+        })
+    }
+
+    /// Follows typedefs and const/volatile/restrict qualifiers down to the type underneath.
+    pub fn strip_typedefs<'a>(&'a self, entity_type: &'a Type) -> &'a Type {
+        let mut curr = entity_type;
+        loop {
+            let target = match curr.kind {
+                TypeKind::Typedef(target)
+                | TypeKind::Const(target)
+                | TypeKind::Volatile(target)
+                | TypeKind::Restrict(target) => target,
+                _ => return curr,
+            };
+            match target.and_then(|offset| self.get_type(offset)) {
+                Some(next) => curr = next,
+                None => return curr,
+            }
+        }
+    }
+
+    /// Returns the CFI rule for unwinding the frame that is executing `curr_addr`.
+    pub fn get_frame_rule(&self, curr_addr: usize) -> Option<FrameRule> {
+        if let Some(library) = self.get_library_for_addr(curr_addr) {
+            return library.debug_data.get_frame_rule(curr_addr);
+        }
+        self.call_frames.find_rule(curr_addr.wrapping_sub(self.load_bias))
+    }
+
+    /// Returns true if `curr_addr` is the first instruction of some row in the line table, i.e.
+    /// the beginning of a source line.
+    pub fn is_line_start(&self, curr_addr: usize) -> bool {
+        self.all_files()
+            .any(|file| file.lines.iter().any(|line| line.address == curr_addr))
     }
 
     #[allow(dead_code)]
@@ -148,27 +688,275 @@ impl DwarfData {
     }
 }
 
+fn map_file(path: &str) -> Result<memmap::Mmap, Error> {
+    let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+    unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile)) }
+}
+
+/// Collects the functions and objects an ELF file defines in .symtab and .dynsym.
+fn elf_symbols(object: &object::File) -> Vec<ElfSymbol> {
+    object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter_map(|(_, symbol)| match symbol.kind() {
+            SymbolKind::Text | SymbolKind::Data if !symbol.is_undefined() => Some(ElfSymbol {
+                name: symbol.name()?.to_string(),
+                address: symbol.address() as usize,
+                size: symbol.size() as usize,
+                is_function: symbol.kind() == SymbolKind::Text,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Finds the separate debug file for the program at `path`, the way gdb does: by build ID as
+/// `<dir>/.build-id/xx/yyyy.debug` under each of `debug_file_dirs`, and then by the name in
+/// .gnu_debuglink, next to the program, in a `.debug` directory beside it, or under each of
+/// `debug_file_dirs` followed by the program's directory. A debuglink candidate only counts
+/// if its CRC matches the one recorded.
+fn find_debug_file(
+    path: &str,
+    object: &object::File,
+    debug_file_dirs: &[String],
+) -> Option<String> {
+    if let Some(note) = object.section_data_by_name(".note.gnu.build-id") {
+        if let Some(build_id) = parse_build_id(&note) {
+            let hex: String = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+            for dir in debug_file_dirs {
+                let candidate = format!("{}/.build-id/{}/{}.debug", dir, &hex[..2], &hex[2..]);
+                if Path::new(&candidate).is_file() {
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+
+    let debuglink = object.section_data_by_name(".gnu_debuglink")?;
+    let name_len = debuglink.iter().position(|&byte| byte == 0)?;
+    let name = String::from_utf8_lossy(&debuglink[..name_len]).to_string();
+    // The CRC comes after the name, 4-byte aligned
+    let crc_offset = (name_len + 4) & !3;
+    let mut crc = [0u8; 4];
+    crc.copy_from_slice(debuglink.get(crc_offset..crc_offset + 4)?);
+    let crc = if object.is_little_endian() {
+        u32::from_le_bytes(crc)
+    } else {
+        u32::from_be_bytes(crc)
+    };
+    let real_path = fs::canonicalize(path).ok()?;
+    let dir = real_path.parent()?.to_string_lossy().to_string();
+    let mut candidates = vec![format!("{}/{}", dir, name), format!("{}/.debug/{}", dir, name)];
+    candidates.extend(
+        debug_file_dirs
+            .iter()
+            .map(|debug_dir| format!("{}{}/{}", debug_dir, dir, name)),
+    );
+    candidates.into_iter().find(|candidate| {
+        // The link may name the program itself
+        if fs::canonicalize(candidate).ok().as_ref() == Some(&real_path) {
+            return false;
+        }
+        match map_file(candidate) {
+            Ok(mmap) => crc32(&mmap) == crc,
+            Err(_) => false,
+        }
+    })
+}
+
+/// Finds the supplementary file that dwz moved the DWARF shared between programs into. It is
+/// named by .gnu_debugaltlink, along with its build ID, or by DWARF 5's .debug_sup, relative to
+/// the directory of `path` unless absolute. Failing that, it is looked for by build ID under
+/// each of `debug_file_dirs`.
+fn find_sup_file(path: &str, object: &object::File, debug_file_dirs: &[String]) -> Option<String> {
+    let (name, build_id) = match object.section_data_by_name(".gnu_debugaltlink") {
+        Some(altlink) => {
+            let name_len = altlink.iter().position(|&byte| byte == 0)?;
+            let name = String::from_utf8_lossy(&altlink[..name_len]).to_string();
+            (name, Some(altlink[name_len + 1..].to_vec()))
+        }
+        None => {
+            // A 2-byte version and a flag come before the name
+            let sup = object.section_data_by_name(".debug_sup")?;
+            let name = sup.get(3..)?;
+            let name_len = name.iter().position(|&byte| byte == 0)?;
+            (String::from_utf8_lossy(&name[..name_len]).to_string(), None)
+        }
+    };
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut candidates = vec![dir.join(&name).to_string_lossy().to_string()];
+    if let Some(build_id) = &build_id {
+        let hex: String = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        if hex.len() > 2 {
+            candidates.extend(debug_file_dirs.iter().map(|debug_dir| {
+                format!("{}/.build-id/{}/{}.debug", debug_dir, &hex[..2], &hex[2..])
+            }));
+        }
+    }
+    candidates.into_iter().find(|candidate| {
+        let mmap = match map_file(candidate) {
+            Ok(mmap) => mmap,
+            Err(_) => return false,
+        };
+        let build_id = match &build_id {
+            Some(build_id) => build_id,
+            None => return true,
+        };
+        let sup = match object::File::parse(&mmap) {
+            Ok(sup) => sup,
+            Err(_) => return false,
+        };
+        match sup.section_data_by_name(".note.gnu.build-id") {
+            Some(note) => parse_build_id(&note) == Some(&build_id[..]),
+            None => false,
+        }
+    })
+}
+
+/// Pulls the build ID out of a .note.gnu.build-id section.
+fn parse_build_id(note: &[u8]) -> Option<&[u8]> {
+    let word = |offset: usize| -> Option<usize> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(note.get(offset..offset + 4)?);
+        Some(u32::from_le_bytes(bytes) as usize)
+    };
+    // namesz, descsz and type, then the name ("GNU") padded to 4 bytes, then the ID
+    let (name_size, desc_size) = (word(0)?, word(4)?);
+    if word(8)? != NT_GNU_BUILD_ID as usize || desc_size < 2 {
+        return None;
+    }
+    let desc = 12 + ((name_size + 3) & !3);
+    note.get(desc..desc + desc_size)
+}
+
+/// The CRC-32 (as in zlib) that .gnu_debuglink records of the debug file.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A function or object from .symtab or .dynsym.
+#[derive(Debug, Clone)]
+pub struct ElfSymbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+    pub is_function: bool,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            kind,
         }
     }
 }
 
+/// The shape of a type. Types refer to each other by their offset in .debug_info, which can be
+/// looked up with `DwarfData::get_type`; `None` stands for `void`.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base(BaseEncoding),
+    Pointer(Option<usize>),
+    Const(Option<usize>),
+    Volatile(Option<usize>),
+    Restrict(Option<usize>),
+    Typedef(Option<usize>),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// Element type, and the length of each dimension (outermost first) if known
+    Array(Option<usize>, Vec<Option<usize>>),
+    Enum(Vec<Enumerator>),
+    /// Return type
+    Function(Option<usize>),
+    Unknown,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Unknown
+    }
+}
+
+/// How the bits of a base type should be interpreted (DW_AT_encoding).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BaseEncoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+    Other,
+}
+
+/// A field of a struct or union, `offset` bytes from the start of it.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub type_offset: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Enumerator {
+    pub name: String,
+    pub value: i64,
+}
+
+/// How to find the caller's registers from a frame at some address, according to the call frame
+/// information. Registers are DWARF register numbers (6 is %rbp, 7 is %rsp).
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRule {
+    /// The CFA (the caller's %rsp before the call) is this register plus an offset
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    /// Where the return address is saved, relative to the CFA. None marks the outermost frame
+    pub return_address: Option<i64>,
+    /// Where the caller's %rbp is saved, relative to the CFA. None means %rbp is unchanged
+    pub rbp: Option<i64>,
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
+    /// An offset from the function's frame base (DW_OP_fbreg)
     FramePointerOffset(isize),
 }
 
+/// What a function's DW_OP_fbreg offsets are relative to, from its DW_AT_frame_base.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBase {
+    /// DW_OP_call_frame_cfa, as gcc emits
+    Cfa,
+    /// A register plus an offset: DW_OP_reg6 (%rbp) from clang, or DW_OP_bregN
+    Register(u16, i64),
+}
+
+impl Default for FrameBase {
+    fn default() -> Self {
+        FrameBase::Cfa
+    }
+}
+
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -191,6 +979,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -200,6 +989,7 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub frame_base: FrameBase,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -224,3 +1014,36 @@ impl fmt::Display for Line {
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_build_id() {
+        // The .note.gnu.build-id of a binary linked with --build-id
+        let note: &[u8] = &[
+            0x04, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, b'G', b'N',
+            b'U', 0x00, 0x31, 0x86, 0xe6, 0xed, 0x13, 0x1a, 0x5a, 0xc4, 0xed, 0x6b, 0x5c, 0x9c,
+            0x81, 0x4a, 0xe9, 0x67, 0xcb, 0xaa, 0x07, 0x58,
+        ];
+        let id: String = parse_build_id(note)
+            .unwrap()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(id, "3186e6ed131a5ac4ed6b5c9c814ae967cbaa0758");
+        // Truncated, and a note of another type
+        assert_eq!(parse_build_id(&note[..30]), None);
+        let mut other = note.to_vec();
+        other[8] = 1;
+        assert_eq!(parse_build_id(&other), None);
+    }
+
+    #[test]
+    fn test_crc32() {
+        // The check value of the CRC-32 that .gnu_debuglink uses
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
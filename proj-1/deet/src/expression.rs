@@ -0,0 +1,1196 @@
+//! Parsing and evaluation of C expressions such as `p->next->value`, `arr[i + 1]`, `*ptr`, `&x`
+//! and `(long)y`, for `print` and `set var`. Variables and types come from the DWARF data, and
+//! memory is read out of the stopped inferior or core dump.
+
+use crate::dwarf_data::{BaseEncoding, DwarfData, FrameBase, Location, Type, TypeKind, Variable};
+use crate::inferior::{Frame, Tracee, DWARF_RBP, DWARF_RSP};
+use crate::registers;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(u64),
+    Float(f64),
+    Char(u8),
+    Punct(&'static str),
+}
+
+// Longest operators first, so that e.g. "->" isn't read as "-" followed by ">"
+const PUNCTUATION: [&str; 28] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^",
+    "!", "~", "<", ">", "(", ")", "[", "]", ".", "=", ",",
+];
+
+// Words that can only start a type name, as in `(unsigned long)x`
+const TYPE_KEYWORDS: [&str; 15] = [
+    "struct", "union", "enum", "unsigned", "signed", "const", "volatile", "void", "char", "short",
+    "int", "long", "float", "double", "_Bool",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(parse_number(&text)?);
+        } else if c == '\'' {
+            // Find the closing quote, skipping over escaped characters
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] != '\'' {
+                end += if chars[end] == '\\' { 2 } else { 1 };
+            }
+            if end >= chars.len() {
+                return Err("Unmatched single quote.".to_string());
+            }
+            let text: String = chars[i + 1..end].iter().collect();
+            tokens.push(Token::Char(parse_char(&text)?));
+            i = end + 1;
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => {
+                    tokens.push(Token::Punct(p));
+                    i += p.len();
+                }
+                None => return Err(format!("Invalid character '{}' in expression.", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<Token, String> {
+    let invalid = || format!("Invalid number \"{}\".", text);
+    let lower = text.to_lowercase();
+    if lower.starts_with("0x") {
+        let digits = lower[2..].trim_end_matches(|c| c == 'u' || c == 'l');
+        return u64::from_str_radix(digits, 16)
+            .map(Token::Int)
+            .map_err(|_| invalid());
+    }
+    if lower.contains('.') || lower.contains('e') {
+        let digits = lower.trim_end_matches(|c| c == 'f' || c == 'l');
+        return digits
+            .parse::<f64>()
+            .map(Token::Float)
+            .map_err(|_| invalid());
+    }
+    let digits = lower.trim_end_matches(|c| c == 'u' || c == 'l');
+    if digits.len() > 1 && digits.starts_with('0') {
+        return u64::from_str_radix(&digits[1..], 8)
+            .map(Token::Int)
+            .map_err(|_| invalid());
+    }
+    digits.parse::<u64>().map(Token::Int).map_err(|_| invalid())
+}
+
+/// Parses the inside of a character literal, e.g. `a`, `\n` or `\101`.
+fn parse_char(text: &str) -> Result<u8, String> {
+    let invalid = || format!("Invalid character constant '{}'.", text);
+    let bytes = text.as_bytes();
+    match bytes {
+        [c] => Ok(*c),
+        [b'\\', b'n'] => Ok(b'\n'),
+        [b'\\', b't'] => Ok(b'\t'),
+        [b'\\', b'r'] => Ok(b'\r'),
+        [b'\\', b'\\'] => Ok(b'\\'),
+        [b'\\', b'\''] => Ok(b'\''),
+        [b'\\', b'"'] => Ok(b'"'),
+        [b'\\', b'x', ..] => u8::from_str_radix(&text[2..], 16).map_err(|_| invalid()),
+        [b'\\', ..] => u8::from_str_radix(&text[1..], 8).map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+/// A type written in a cast, e.g. `struct node *`.
+#[derive(Debug, Clone)]
+pub struct TypeName {
+    name: String,
+    pointers: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Int(u64),
+    Float(f64),
+    Char(u8),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    /// `a.b`
+    Member(Box<Expr>, String),
+    /// `a->b`
+    PtrMember(Box<Expr>, String),
+    Cast(TypeName, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+}
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    debug_data: &'a DwarfData,
+}
+
+/// Parses `input` into an expression tree. `debug_data` is needed to tell casts such as
+/// `(point_t)x` apart from parenthesized expressions.
+pub fn parse(input: &str, debug_data: &DwarfData) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        debug_data,
+    };
+    let expr = parser.parse_assign()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.syntax_error());
+    }
+    Ok(expr)
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_punct(&self, punct: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(p)) => *p == punct,
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if !self.peek_punct(punct) {
+            return Err(self.syntax_error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn syntax_error(&self) -> String {
+        let near: Vec<String> = self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .map(|token| match token {
+                Token::Ident(name) => name.clone(),
+                Token::Int(value) => value.to_string(),
+                Token::Float(value) => value.to_string(),
+                Token::Char(value) => format!("'{}'", *value as char),
+                Token::Punct(punct) => punct.to_string(),
+            })
+            .collect();
+        format!("A syntax error in expression, near `{}'.", near.join(" "))
+    }
+
+    fn parse_assign(&mut self) -> Result<Expr, String> {
+        let lhs = self.parse_binary(1)?;
+        if self.peek_punct("=") {
+            self.pos += 1;
+            let rhs = self.parse_assign()?;
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Punct(op)) = self.peek() {
+            let op = *op;
+            let prec = match binary_precedence(op) {
+                Some(prec) if prec >= min_prec => prec,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.parse_binary(prec + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Punct(op)) if ["-", "+", "!", "~", "*", "&"].contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::Punct("(")) if self.starts_type_name(self.pos + 1) => {
+                self.pos += 1;
+                let type_name = self.parse_type_name()?;
+                self.expect(")")?;
+                Ok(Expr::Cast(type_name, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn starts_type_name(&self, pos: usize) -> bool {
+        match self.tokens.get(pos) {
+            Some(Token::Ident(word)) => {
+                TYPE_KEYWORDS.contains(&word.as_str())
+                    || self.debug_data.get_type_by_name(word).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<TypeName, String> {
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            if word != "const" && word != "volatile" {
+                words.push(word.clone());
+            }
+            self.pos += 1;
+        }
+        let mut pointers = 0;
+        while self.peek_punct("*") {
+            pointers += 1;
+            self.pos += 1;
+        }
+        Ok(TypeName {
+            name: words.join(" "),
+            pointers,
+        })
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            if self.peek_punct("[") {
+                self.pos += 1;
+                let index = self.parse_assign()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.peek_punct(".") || self.peek_punct("->") {
+                let arrow = self.peek_punct("->");
+                self.pos += 1;
+                let field = match self.peek() {
+                    Some(Token::Ident(field)) => field.clone(),
+                    _ => return Err(self.syntax_error()),
+                };
+                self.pos += 1;
+                expr = if arrow {
+                    Expr::PtrMember(Box::new(expr), field)
+                } else {
+                    Expr::Member(Box::new(expr), field)
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let expr = match self.peek() {
+            Some(Token::Int(value)) => Expr::Int(*value),
+            Some(Token::Float(value)) => Expr::Float(*value),
+            Some(Token::Char(value)) => Expr::Char(*value),
+            Some(Token::Ident(name)) => Expr::Var(name.clone()),
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let expr = self.parse_assign()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(self.syntax_error()),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+/// The type of a value. Most come straight from DWARF, but taking the address of something can
+/// produce pointer types that the program itself never mentions.
+#[derive(Debug, Clone)]
+pub enum ValueType {
+    Type(Type),
+    PointerTo(Box<ValueType>),
+}
+
+impl ValueType {
+    pub fn name(&self) -> String {
+        match self {
+            ValueType::Type(entity_type) => entity_type.name.clone(),
+            ValueType::PointerTo(inner) => {
+                let name = inner.name();
+                if name.ends_with(" ()") {
+                    // A pointer to a function, e.g. `void (*)()`
+                    format!("{} (*)()", &name[..name.len() - 3])
+                } else if name.ends_with('*') {
+                    format!("{}*", name)
+                } else {
+                    format!("{} *", name)
+                }
+            }
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            ValueType::Type(entity_type) => entity_type.size,
+            ValueType::PointerTo(_) => 8,
+        }
+    }
+}
+
+/// The result of evaluating an expression: its bytes, and where it lives in the inferior if it
+/// is an lvalue.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub value_type: ValueType,
+    pub bytes: Vec<u8>,
+    pub addr: Option<usize>,
+}
+
+/// A value converted for arithmetic.
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+/// Returns the type gcc would give a C base type name such as `unsigned long`, e.g.
+/// `long unsigned int`.
+fn canonical_base_name(name: &str) -> Option<String> {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let unsigned = words.contains(&"unsigned");
+    let signed = words.contains(&"signed");
+    let longs = words.iter().filter(|word| **word == "long").count();
+    let short = words.contains(&"short");
+    let known = ["unsigned", "signed", "long", "short", "int", "char", "float", "double", "_Bool"];
+    if words.is_empty() || !words.iter().all(|word| known.contains(word)) {
+        return None;
+    }
+    let name = if words.contains(&"char") {
+        match (unsigned, signed) {
+            (true, _) => "unsigned char",
+            (_, true) => "signed char",
+            _ => "char",
+        }
+    } else if words.contains(&"double") {
+        if longs > 0 { "long double" } else { "double" }
+    } else if words.contains(&"float") {
+        "float"
+    } else if words.contains(&"_Bool") {
+        "_Bool"
+    } else if short {
+        if unsigned { "short unsigned int" } else { "short int" }
+    } else if longs == 1 {
+        if unsigned { "long unsigned int" } else { "long int" }
+    } else if longs > 1 {
+        if unsigned { "long long unsigned int" } else { "long long int" }
+    } else if unsigned {
+        "unsigned int"
+    } else {
+        "int"
+    };
+    Some(name.to_string())
+}
+
+/// C base types we can use even if the program's DWARF doesn't mention them.
+fn builtin_type(canonical_name: &str) -> Option<Type> {
+    let (size, encoding) = match canonical_name {
+        "char" | "signed char" => (1, BaseEncoding::SignedChar),
+        "unsigned char" => (1, BaseEncoding::UnsignedChar),
+        "_Bool" => (1, BaseEncoding::Boolean),
+        "short int" => (2, BaseEncoding::Signed),
+        "short unsigned int" => (2, BaseEncoding::Unsigned),
+        "int" => (4, BaseEncoding::Signed),
+        "unsigned int" => (4, BaseEncoding::Unsigned),
+        "long int" | "long long int" => (8, BaseEncoding::Signed),
+        "long unsigned int" | "long long unsigned int" => (8, BaseEncoding::Unsigned),
+        "float" => (4, BaseEncoding::Float),
+        "double" => (8, BaseEncoding::Float),
+        _ => return None,
+    };
+    Some(Type::new(canonical_name.to_string(), size, TypeKind::Base(encoding)))
+}
+
+/// The type of an integer arithmetic result, following C's usual arithmetic conversions closely
+/// enough for a debugger: everything is at least an int.
+fn int_result_type(size: usize, unsigned: bool) -> Type {
+    let name = match (size > 4, unsigned) {
+        (false, false) => "int",
+        (false, true) => "unsigned int",
+        (true, false) => "long int",
+        (true, true) => "long unsigned int",
+    };
+    builtin_type(name).unwrap()
+}
+
+pub fn read_uint(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// Reads a little-endian integer, sign-extending it from its actual width.
+pub fn read_int(bytes: &[u8]) -> i64 {
+    let len = bytes.len().min(8) as u32;
+    if len == 0 {
+        return 0;
+    }
+    let shift = 64 - 8 * len;
+    ((read_uint(bytes) << shift) as i64) >> shift
+}
+
+/// Evaluates expressions in the context of a stopped inferior: variables are looked up in the
+/// function containing `rip`, and locals are found relative to its frame base, which is usually
+/// the frame's `cfa`.
+pub struct Evaluator<'a> {
+    debug_data: &'a DwarfData,
+    tracee: &'a dyn Tracee,
+    regs: libc::user_regs_struct,
+    rip: usize,
+    cfa: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(debug_data: &'a DwarfData, tracee: &'a dyn Tracee) -> Result<Evaluator<'a>, String> {
+        let regs = tracee.get_regs().map_err(|err| err.to_string())?;
+        let cfa = tracee.get_cfa(debug_data).map_err(|err| err.to_string())?;
+        Ok(Evaluator {
+            debug_data,
+            tracee,
+            regs,
+            rip: regs.rip as usize,
+            cfa,
+        })
+    }
+
+    /// Like `new`, but evaluates in `frame` when it is given: its function's variables are the
+    /// ones in scope, and `$pc`, `$sp` and `$fp` are the frame's.
+    pub fn for_frame(
+        debug_data: &'a DwarfData,
+        tracee: &'a dyn Tracee,
+        frame: Option<&Frame>,
+    ) -> Result<Evaluator<'a>, String> {
+        let mut evaluator = Evaluator::new(debug_data, tracee)?;
+        if let Some(frame) = frame {
+            evaluator.regs.rip = frame.regs.rip as u64;
+            evaluator.regs.rsp = frame.regs.rsp as u64;
+            evaluator.regs.rbp = frame.regs.rbp as u64;
+            evaluator.rip = frame.regs.lookup_addr(frame.level == 0);
+            evaluator.cfa = frame.cfa;
+        }
+        Ok(evaluator)
+    }
+
+    pub fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Int(value) => {
+                let entity_type = if *value > i32::MAX as u64 {
+                    builtin_type("long int").unwrap()
+                } else {
+                    builtin_type("int").unwrap()
+                };
+                Ok(self.int_value(*value as i64, entity_type))
+            }
+            Expr::Float(value) => Ok(Value {
+                value_type: ValueType::Type(builtin_type("double").unwrap()),
+                bytes: value.to_bits().to_le_bytes().to_vec(),
+                addr: None,
+            }),
+            Expr::Char(value) => Ok(self.int_value(*value as i64, builtin_type("char").unwrap())),
+            Expr::Var(name) => self.variable(name),
+            Expr::Unary(op, operand) => self.unary(op, self.evaluate(operand)?),
+            Expr::Binary(op, lhs, rhs) if *op == "&&" || *op == "||" => {
+                // The right operand is only evaluated if the left one doesn't decide the result,
+                // so that `p && p->x` is safe when p is null
+                let lhs = self.truth(&self.evaluate(lhs)?)?;
+                let result = if lhs == (*op == "||") {
+                    lhs
+                } else {
+                    self.truth(&self.evaluate(rhs)?)?
+                };
+                Ok(self.int_value(result as i64, builtin_type("int").unwrap()))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                self.binary(op, self.evaluate(lhs)?, self.evaluate(rhs)?)
+            }
+            Expr::Index(base, index) => {
+                let sum = self.binary("+", self.evaluate(base)?, self.evaluate(index)?)?;
+                self.unary("*", sum)
+            }
+            Expr::Member(base, field) => self.member(self.evaluate(base)?, field),
+            Expr::PtrMember(base, field) => {
+                let target = self.unary("*", self.evaluate(base)?)?;
+                self.member(target, field)
+            }
+            Expr::Cast(type_name, operand) => {
+                let value_type = self.lookup_type(type_name)?;
+                let value = self.evaluate(operand)?;
+                Ok(Value {
+                    bytes: self.convert(&value, &value_type)?,
+                    value_type,
+                    addr: None,
+                })
+            }
+            Expr::Assign(..) => Err("Assignments are only supported by \"set var\".".to_string()),
+        }
+    }
+
+    fn int_value(&self, value: i64, entity_type: Type) -> Value {
+        let size = entity_type.size;
+        Value {
+            value_type: ValueType::Type(entity_type),
+            bytes: value.to_le_bytes()[..size].to_vec(),
+            addr: None,
+        }
+    }
+
+    /// Reads a value of type `value_type` out of the inferior's memory.
+    fn load(&self, value_type: ValueType, addr: usize) -> Result<Value, String> {
+        let bytes = self
+            .tracee
+            .read_memory(addr, value_type.size())
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+        Ok(Value {
+            value_type,
+            bytes,
+            addr: Some(addr),
+        })
+    }
+
+    fn variable(&self, name: &str) -> Result<Value, String> {
+        if name.starts_with('$') {
+            return self.register(&name[1..]);
+        }
+        let var = self
+            .debug_data
+            .get_function_for_addr(self.rip)
+            .and_then(|func| func.variables.iter().find(|var| var.name == name))
+            .or_else(|| self.debug_data.get_global_variable(name));
+        if let Some(var) = var {
+            return self.variable_value(var);
+        }
+        if let Some(value) = self.debug_data.get_enumerator_value(name) {
+            return Ok(self.int_value(value, builtin_type("int").unwrap()));
+        }
+        if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            // We don't record function types, so like gdb without them call it `void ()`
+            return Ok(Value {
+                value_type: ValueType::Type(Type::new("void ()".to_string(), 1, TypeKind::Function(None))),
+                bytes: Vec::new(),
+                addr: Some(addr),
+            });
+        }
+        Err(format!("No symbol \"{}\" in current context.", name))
+    }
+
+    /// Reads the value of `var`, one of the current function's variables or a global.
+    pub fn variable_value(&self, var: &Variable) -> Result<Value, String> {
+        let addr = match var.location {
+            Location::Address(addr) => addr,
+            Location::FramePointerOffset(offset) => (self.frame_base()? as isize + offset) as usize,
+        };
+        self.load(ValueType::Type(var.entity_type.clone()), addr)
+    }
+
+    /// The address the current function's locals are at offsets from.
+    fn frame_base(&self) -> Result<usize, String> {
+        let frame_base = self
+            .debug_data
+            .get_function_for_addr(self.rip)
+            .map(|func| func.frame_base)
+            .unwrap_or_default();
+        match frame_base {
+            FrameBase::Cfa => Ok(self.cfa),
+            FrameBase::Register(register, offset) => {
+                let value = match register {
+                    DWARF_RBP => self.regs.rbp,
+                    DWARF_RSP => self.regs.rsp,
+                    _ => return Err(format!("Unsupported frame base register {}.", register)),
+                };
+                Ok((value as i64 + offset) as usize)
+            }
+        }
+    }
+
+    /// Reads the register `$name`. Like gdb, the stack and frame pointers are `void *` and the
+    /// program counter is a code pointer; everything else is an integer of the register's size.
+    fn register(&self, name: &str) -> Result<Value, String> {
+        let (value, size) = registers::get_register(&self.regs, name)
+            .ok_or(format!("Invalid register `{}'", name))?;
+        let void = || ValueType::Type(Type::new("void".to_string(), 1, TypeKind::Unknown));
+        let value_type = match name {
+            "rsp" | "rbp" | "sp" | "fp" => ValueType::PointerTo(Box::new(void())),
+            "rip" | "pc" => ValueType::PointerTo(Box::new(ValueType::Type(Type::new(
+                "void ()".to_string(),
+                1,
+                TypeKind::Function(None),
+            )))),
+            _ => ValueType::Type(match size {
+                8 => builtin_type("long int").unwrap(),
+                4 => builtin_type("int").unwrap(),
+                2 => builtin_type("short int").unwrap(),
+                // gdb's int8_t, which unlike char prints as a plain number
+                _ => Type::new("int8_t".to_string(), 1, TypeKind::Base(BaseEncoding::Signed)),
+            }),
+        };
+        Ok(Value {
+            value_type,
+            bytes: value.to_le_bytes()[..size].to_vec(),
+            addr: None,
+        })
+    }
+
+    fn lookup_type(&self, type_name: &TypeName) -> Result<ValueType, String> {
+        let canonical = canonical_base_name(&type_name.name);
+        let base = if type_name.name == "void" {
+            Some(Type::new("void".to_string(), 1, TypeKind::Unknown))
+        } else {
+            self.debug_data
+                .get_type_by_name(&type_name.name)
+                .or_else(|| self.debug_data.get_type_by_name(canonical.as_ref()?))
+                .cloned()
+                .or_else(|| builtin_type(canonical.as_ref()?))
+        };
+        let mut value_type = match base {
+            Some(base) => ValueType::Type(base),
+            None => return Err(format!("No symbol \"{}\" in current context.", type_name.name)),
+        };
+        for _ in 0..type_name.pointers {
+            value_type = ValueType::PointerTo(Box::new(value_type));
+        }
+        Ok(value_type)
+    }
+
+    fn kind<'t>(&'t self, value_type: &'t ValueType) -> Option<&'t TypeKind> {
+        match value_type {
+            ValueType::Type(entity_type) => Some(&self.debug_data.strip_typedefs(entity_type).kind),
+            ValueType::PointerTo(_) => None,
+        }
+    }
+
+    /// For pointers and arrays, returns the type of the thing pointed to (or of the elements).
+    fn pointee(&self, value_type: &ValueType) -> Option<ValueType> {
+        let get_type = |offset: &Option<usize>| {
+            offset
+                .and_then(|offset| self.debug_data.get_type(offset))
+                .cloned()
+                .unwrap_or_else(|| Type::new("void".to_string(), 1, TypeKind::Unknown))
+        };
+        match value_type {
+            ValueType::PointerTo(inner) => Some((**inner).clone()),
+            ValueType::Type(entity_type) => match &self.debug_data.strip_typedefs(entity_type).kind {
+                TypeKind::Pointer(target) => Some(ValueType::Type(get_type(target))),
+                TypeKind::Array(elem, dims) if dims.len() > 1 => {
+                    // An element of a multi-dimensional array is itself an array
+                    let elem_type = get_type(elem);
+                    let dims = dims[1..].to_vec();
+                    let count: usize = dims.iter().map(|dim| dim.unwrap_or(0)).product();
+                    let name = format!("{} [{}]", elem_type.name, dims.iter()
+                        .map(|dim| dim.map_or(String::new(), |count| count.to_string()))
+                        .collect::<Vec<String>>()
+                        .join("]["));
+                    Some(ValueType::Type(Type::new(
+                        name,
+                        count * elem_type.size,
+                        TypeKind::Array(*elem, dims),
+                    )))
+                }
+                TypeKind::Array(elem, _) => Some(ValueType::Type(get_type(elem))),
+                _ => None,
+            },
+        }
+    }
+
+    fn scalar(&self, value: &Value) -> Result<Scalar, String> {
+        let bytes = &value.bytes;
+        match self.kind(&value.value_type) {
+            None | Some(TypeKind::Pointer(_)) => Ok(Scalar::UInt(read_uint(bytes))),
+            Some(TypeKind::Array(..)) | Some(TypeKind::Function(_)) => match value.addr {
+                // Arrays decay to a pointer to their first element, functions to a pointer to
+                // their code
+                Some(addr) => Ok(Scalar::UInt(addr as u64)),
+                None => Err("Attempt to take address of value not located in memory.".to_string()),
+            },
+            Some(TypeKind::Base(BaseEncoding::Float)) => match bytes.len() {
+                4 => Ok(Scalar::Float(f32::from_bits(read_uint(bytes) as u32) as f64)),
+                8 => Ok(Scalar::Float(f64::from_bits(read_uint(bytes)))),
+                _ => Err(format!("Unsupported floating point type {}.", value.value_type.name())),
+            },
+            Some(TypeKind::Base(BaseEncoding::Signed))
+            | Some(TypeKind::Base(BaseEncoding::SignedChar))
+            | Some(TypeKind::Enum(_)) => Ok(Scalar::Int(read_int(bytes))),
+            Some(TypeKind::Base(_)) => Ok(Scalar::UInt(read_uint(bytes))),
+            _ => Err(format!("Invalid operand of type {}.", value.value_type.name())),
+        }
+    }
+
+    /// Interprets `value` as an address, as `x` does with its argument: pointers, integers and
+    /// arrays or functions (by their location) all qualify.
+    pub fn address(&self, value: &Value) -> Result<usize, String> {
+        match self.scalar(value)? {
+            Scalar::Int(addr) => Ok(addr as usize),
+            Scalar::UInt(addr) => Ok(addr as usize),
+            Scalar::Float(_) => Err("Invalid number of type double as an address.".to_string()),
+        }
+    }
+
+    fn is_unsigned(&self, value: &Value) -> bool {
+        matches!(self.scalar(value), Ok(Scalar::UInt(_)))
+    }
+
+    /// Converts `value` to `value_type` as a C cast or assignment would, returning the bytes.
+    pub fn convert(&self, value: &Value, value_type: &ValueType) -> Result<Vec<u8>, String> {
+        let size = value_type.size();
+        match self.kind(value_type) {
+            Some(TypeKind::Struct(_)) | Some(TypeKind::Union(_)) | Some(TypeKind::Array(..)) => {
+                if value.value_type.name() == value_type.name() {
+                    Ok(value.bytes.clone())
+                } else {
+                    Err(format!("Invalid cast to {}.", value_type.name()))
+                }
+            }
+            Some(TypeKind::Base(BaseEncoding::Float)) => {
+                let value = match self.scalar(value)? {
+                    Scalar::Int(i) => i as f64,
+                    Scalar::UInt(u) => u as f64,
+                    Scalar::Float(f) => f,
+                };
+                match size {
+                    4 => Ok((value as f32).to_bits().to_le_bytes().to_vec()),
+                    8 => Ok(value.to_bits().to_le_bytes().to_vec()),
+                    _ => Err(format!("Unsupported floating point type {}.", value_type.name())),
+                }
+            }
+            Some(TypeKind::Base(BaseEncoding::Boolean)) => {
+                let truth = self.truth(value)?;
+                Ok(vec![truth as u8])
+            }
+            _ => {
+                let value = match self.scalar(value)? {
+                    Scalar::Int(i) => i as u64,
+                    Scalar::UInt(u) => u,
+                    Scalar::Float(f) => f as i64 as u64,
+                };
+                Ok(value.to_le_bytes()[..size.min(8)].to_vec())
+            }
+        }
+    }
+
+    /// Whether `value` counts as true in C, i.e. is nonzero.
+    pub fn truth(&self, value: &Value) -> Result<bool, String> {
+        Ok(match self.scalar(value)? {
+            Scalar::Int(i) => i != 0,
+            Scalar::UInt(u) => u != 0,
+            Scalar::Float(f) => f != 0.0,
+        })
+    }
+
+    fn unary(&self, op: &str, operand: Value) -> Result<Value, String> {
+        match op {
+            "*" => {
+                let target = match self.pointee(&operand.value_type) {
+                    Some(target) => target,
+                    None => {
+                        return Err("Attempt to take contents of a non-pointer value.".to_string())
+                    }
+                };
+                if let ValueType::Type(Type { kind: TypeKind::Unknown, .. }) = target {
+                    return Err("Attempt to take contents of a non-pointer value.".to_string());
+                }
+                let addr = match self.scalar(&operand)? {
+                    Scalar::UInt(addr) => addr as usize,
+                    _ => unreachable!(),
+                };
+                self.load(target, addr)
+            }
+            "&" => match operand.addr {
+                Some(addr) => Ok(Value {
+                    value_type: ValueType::PointerTo(Box::new(operand.value_type)),
+                    bytes: (addr as u64).to_le_bytes().to_vec(),
+                    addr: None,
+                }),
+                None => Err("Attempt to take address of value not located in memory.".to_string()),
+            },
+            "!" => {
+                let truth = self.truth(&operand)?;
+                Ok(self.int_value(!truth as i64, builtin_type("int").unwrap()))
+            }
+            _ => {
+                // Unary minus, plus and ~ behave like 0 - x, 0 + x and -1 ^ x
+                let zero = self.int_value(if op == "~" { -1 } else { 0 }, builtin_type("int").unwrap());
+                let op = match op {
+                    "-" => "-",
+                    "+" => "+",
+                    _ => "^",
+                };
+                self.binary(op, zero, operand)
+            }
+        }
+    }
+
+    fn binary(&self, op: &'static str, lhs: Value, rhs: Value) -> Result<Value, String> {
+        // Pointer arithmetic is scaled by the size of the thing pointed to
+        let lhs_pointee = self.pointee(&lhs.value_type);
+        let rhs_pointee = self.pointee(&rhs.value_type);
+        match (op, &lhs_pointee, &rhs_pointee) {
+            ("+", Some(target), None) | ("-", Some(target), None) => {
+                let addr = self.pointer_value(&lhs)?;
+                let offset = self.int_operand(&rhs)? * target.size() as i64;
+                let addr = if op == "+" {
+                    addr.wrapping_add(offset as u64)
+                } else {
+                    addr.wrapping_sub(offset as u64)
+                };
+                return Ok(self.pointer(lhs, addr));
+            }
+            ("+", None, Some(target)) => {
+                let addr = self.pointer_value(&rhs)?;
+                let offset = self.int_operand(&lhs)? * target.size() as i64;
+                return Ok(self.pointer(rhs, addr.wrapping_add(offset as u64)));
+            }
+            ("-", Some(target), Some(_)) => {
+                let diff = self.pointer_value(&lhs)?.wrapping_sub(self.pointer_value(&rhs)?) as i64;
+                let size = target.size().max(1) as i64;
+                return Ok(self.int_value(diff / size, builtin_type("long int").unwrap()));
+            }
+            _ => {}
+        }
+
+        let size = lhs.value_type.size().max(rhs.value_type.size());
+        let unsigned = (self.is_unsigned(&lhs) && lhs.value_type.size() >= size.max(4))
+            || (self.is_unsigned(&rhs) && rhs.value_type.size() >= size.max(4));
+        let result_type = int_result_type(size, unsigned);
+        let comparison = ["==", "!=", "<", ">", "<=", ">="].contains(&op);
+        let int_type = || builtin_type("int").unwrap();
+
+        match (self.scalar(&lhs)?, self.scalar(&rhs)?) {
+            (Scalar::Float(_), _) | (_, Scalar::Float(_)) => {
+                let a = self.float_operand(&lhs)?;
+                let b = self.float_operand(&rhs)?;
+                if comparison {
+                    return Ok(self.int_value(compare(op, a, b) as i64, int_type()));
+                }
+                let result = match op {
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" => a / b,
+                    _ => return Err(format!("Integer only operation {}.", op)),
+                };
+                Ok(Value {
+                    value_type: ValueType::Type(builtin_type("double").unwrap()),
+                    bytes: result.to_bits().to_le_bytes().to_vec(),
+                    addr: None,
+                })
+            }
+            (a, b) if unsigned => {
+                let a = scalar_bits(a);
+                let b = scalar_bits(b);
+                if comparison {
+                    return Ok(self.int_value(compare(op, a, b) as i64, int_type()));
+                }
+                let result = match op {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" | "%" if b == 0 => return Err("Division by zero".to_string()),
+                    "/" => a / b,
+                    "%" => a % b,
+                    "&" => a & b,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    _ => return Err(format!("Unsupported operator {}.", op)),
+                };
+                Ok(self.int_value(result as i64, result_type))
+            }
+            (a, b) => {
+                let a = scalar_bits(a) as i64;
+                let b = scalar_bits(b) as i64;
+                if comparison {
+                    return Ok(self.int_value(compare(op, a, b) as i64, int_type()));
+                }
+                let result = match op {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" | "%" if b == 0 => return Err("Division by zero".to_string()),
+                    "/" => a.wrapping_div(b),
+                    "%" => a.wrapping_rem(b),
+                    "&" => a & b,
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    _ => return Err(format!("Unsupported operator {}.", op)),
+                };
+                Ok(self.int_value(result, result_type))
+            }
+        }
+    }
+
+    fn pointer_value(&self, value: &Value) -> Result<u64, String> {
+        match self.scalar(value)? {
+            Scalar::UInt(addr) => Ok(addr),
+            _ => Err("Invalid pointer operand.".to_string()),
+        }
+    }
+
+    /// Builds a pointer with the type of `like` (or, for arrays, a pointer to their elements).
+    fn pointer(&self, like: Value, addr: u64) -> Value {
+        let value_type = match self.kind(&like.value_type) {
+            Some(TypeKind::Array(..)) => {
+                ValueType::PointerTo(Box::new(self.pointee(&like.value_type).unwrap()))
+            }
+            _ => like.value_type,
+        };
+        Value {
+            value_type,
+            bytes: addr.to_le_bytes().to_vec(),
+            addr: None,
+        }
+    }
+
+    fn int_operand(&self, value: &Value) -> Result<i64, String> {
+        match self.scalar(value)? {
+            Scalar::Int(i) => Ok(i),
+            Scalar::UInt(u) => Ok(u as i64),
+            Scalar::Float(_) => Err("Integer only operation.".to_string()),
+        }
+    }
+
+    fn float_operand(&self, value: &Value) -> Result<f64, String> {
+        Ok(match self.scalar(value)? {
+            Scalar::Int(i) => i as f64,
+            Scalar::UInt(u) => u as f64,
+            Scalar::Float(f) => f,
+        })
+    }
+
+    fn member(&self, base: Value, field: &str) -> Result<Value, String> {
+        let members = match self.kind(&base.value_type) {
+            Some(TypeKind::Struct(members)) | Some(TypeKind::Union(members)) => members,
+            _ => {
+                return Err(
+                    "Attempt to extract a component of a value that is not a structure."
+                        .to_string(),
+                )
+            }
+        };
+        let member = match members.iter().find(|member| member.name == field) {
+            Some(member) => member,
+            None => return Err(format!("There is no member named {}.", field)),
+        };
+        let member_type = member
+            .type_offset
+            .and_then(|offset| self.debug_data.get_type(offset))
+            .ok_or(format!("Unknown type for member {}.", field))?;
+        let end = member.offset + member_type.size;
+        if end > base.bytes.len() {
+            return Err(format!("Member {} lies outside its structure.", field));
+        }
+        Ok(Value {
+            value_type: ValueType::Type(member_type.clone()),
+            bytes: base.bytes[member.offset..end].to_vec(),
+            addr: base.addr.map(|addr| addr + member.offset),
+        })
+    }
+}
+
+fn scalar_bits(scalar: Scalar) -> u64 {
+    match scalar {
+        Scalar::Int(i) => i as u64,
+        Scalar::UInt(u) => u,
+        Scalar::Float(f) => f as i64 as u64,
+    }
+}
+
+fn compare<T: PartialOrd>(op: &str, a: T, b: T) -> bool {
+    match op {
+        "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        "<=" => a <= b,
+        _ => a >= b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::FpRegisters;
+    use nix::errno::Errno;
+
+    /// A stopped program whose memory is `memory`, starting at address 0x1000.
+    struct FakeTracee {
+        memory: Vec<u8>,
+    }
+
+    const MEMORY_START: usize = 0x1000;
+
+    impl Tracee for FakeTracee {
+        fn get_regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+            Ok(unsafe { std::mem::zeroed() })
+        }
+
+        fn get_fpregs(&self) -> Result<FpRegisters, nix::Error> {
+            Err(nix::Error::Sys(Errno::ENODATA))
+        }
+
+        fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+            if addr < MEMORY_START || addr + len > MEMORY_START + self.memory.len() {
+                return Err(nix::Error::Sys(Errno::EIO));
+            }
+            Ok(self.memory[addr - MEMORY_START..addr - MEMORY_START + len].to_vec())
+        }
+
+        fn auxv(&self) -> Option<Vec<(u64, u64)>> {
+            None
+        }
+    }
+
+    fn evaluate(input: &str) -> Result<Value, String> {
+        // An int holding 42 at 0x1000, and a pointer to it at 0x1008
+        let mut memory = vec![0u8; 16];
+        memory[..4].copy_from_slice(&42i32.to_le_bytes());
+        memory[8..].copy_from_slice(&(MEMORY_START as u64).to_le_bytes());
+        let tracee = FakeTracee { memory };
+        let debug_data = DwarfData::empty();
+        let evaluator = Evaluator::new(&debug_data, &tracee)?;
+        evaluator.evaluate(&parse(input, &debug_data)?)
+    }
+
+    fn evaluate_int(input: &str) -> i64 {
+        let value = evaluate(input).expect(input);
+        read_int(&value.bytes)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate_int("1 + 2 * 3"), 7);
+        assert_eq!(evaluate_int("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate_int("10 - 4 - 3"), 3);
+        assert_eq!(evaluate_int("1 << 2 + 1"), 8);
+        assert_eq!(evaluate_int("6 | 1 ^ 3 & 1"), 6);
+        assert_eq!(evaluate_int("1 < 2 == 1"), 1);
+        assert_eq!(evaluate_int("-2 * 3"), -6);
+        assert_eq!(evaluate_int("!0 + ~0"), 0);
+        assert_eq!(evaluate_int("7 % 4 * 2"), 6);
+    }
+
+    #[test]
+    fn test_casts() {
+        let value = evaluate("(char)300").unwrap();
+        assert_eq!(value.value_type.name(), "char");
+        assert_eq!(read_int(&value.bytes), 44);
+        assert_eq!(
+            read_uint(&evaluate("(unsigned char)-1").unwrap().bytes),
+            255
+        );
+        assert_eq!(evaluate_int("(long)1 << 40"), 1 << 40);
+        assert_eq!(evaluate_int("(int)3.7"), 3);
+        assert_eq!(evaluate_int("(unsigned int)-1 > 0"), 1);
+        assert_eq!(evaluate_int("-1 > 0"), 0);
+
+        let value = evaluate("(double)1 / 2").unwrap();
+        assert_eq!(value.value_type.name(), "double");
+        assert_eq!(f64::from_bits(read_uint(&value.bytes)), 0.5);
+
+        assert!(evaluate("(struct nothing)1").is_err());
+    }
+
+    #[test]
+    fn test_dereference_and_address() {
+        assert_eq!(evaluate_int("*(int *)0x1000"), 42);
+        assert_eq!(evaluate_int("**(int **)0x1008"), 42);
+        assert_eq!(evaluate_int("((int *)0x1000)[0] + 1"), 43);
+        assert_eq!(evaluate_int("*((int *)0x1004 - 1)"), 42);
+
+        let value = evaluate("&*(int *)0x1000").unwrap();
+        assert_eq!(value.value_type.name(), "int *");
+        assert_eq!(read_uint(&value.bytes), 0x1000);
+        assert_eq!(
+            read_uint(&evaluate("*&*(int **)0x1008").unwrap().bytes),
+            0x1000
+        );
+
+        assert_eq!(
+            evaluate("*(int *)0").unwrap_err(),
+            "Cannot access memory at address 0x0"
+        );
+        assert!(evaluate("*1").is_err());
+        assert!(evaluate("&1").is_err());
+    }
+
+    #[test]
+    fn test_assignment_parsing() {
+        let debug_data = DwarfData::empty();
+        match parse("x = y = 3", &debug_data).unwrap() {
+            Expr::Assign(lhs, rhs) => {
+                assert!(matches!(*lhs, Expr::Var(ref name) if name == "x"));
+                match *rhs {
+                    Expr::Assign(lhs, rhs) => {
+                        assert!(matches!(*lhs, Expr::Var(ref name) if name == "y"));
+                        assert!(matches!(*rhs, Expr::Int(3)));
+                    }
+                    other => panic!("Expected an assignment, got {:?}", other),
+                }
+            }
+            other => panic!("Expected an assignment, got {:?}", other),
+        }
+        assert!(matches!(
+            parse("a[1] = 2 + 3", &debug_data).unwrap(),
+            Expr::Assign(ref lhs, ref rhs)
+                if matches!(**lhs, Expr::Index(..)) && matches!(**rhs, Expr::Binary("+", ..))
+        ));
+        assert!(matches!(
+            parse("a == 2", &debug_data).unwrap(),
+            Expr::Binary("==", ..)
+        ));
+        assert!(parse("a = ", &debug_data).is_err());
+        assert!(parse("1 2", &debug_data).is_err());
+        assert!(evaluate("1 = 2").is_err());
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        assert_eq!(evaluate_int("0 && *(int *)0"), 0);
+        assert_eq!(evaluate_int("1 || *(int *)0"), 1);
+        assert_eq!(evaluate_int("(int *)0 && *(int *)0"), 0);
+        assert_eq!(evaluate_int("(int *)0x1000 && *(int *)0x1000 == 42"), 1);
+        assert!(evaluate("1 && *(int *)0").is_err());
+        assert!(evaluate("0 || *(int *)0").is_err());
+        assert_eq!(evaluate_int("2 && 3"), 1);
+        assert_eq!(evaluate_int("0 || 0.5"), 1);
+        assert_eq!(evaluate_int("0 || 0"), 0);
+    }
+}
@@ -5,27 +5,65 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
-use gimli::{UnitOffset, UnitSectionOffset};
-use object::Object;
+use gimli::{Section, UnitOffset, UnitSectionOffset, UnwindSection};
+use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{BaseEncoding, Enumerator, FrameRule, Member, Type, TypeKind};
+use crate::dwarf_data::{File, FrameBase, Function, Line, Location, Variable};
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::rc::Rc;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+/// object 0.19 no longer has `Object::section_data_by_name`. This puts it back on top of
+/// `section_by_name`, decompressing the section if it is compressed.
+pub trait SectionDataByName<'data> {
+    fn section_data_by_name(&self, name: &str) -> Option<borrow::Cow<'data, [u8]>>;
+}
+
+impl<'data> SectionDataByName<'data> for object::File<'data> {
+    fn section_data_by_name(&self, name: &str) -> Option<borrow::Cow<'data, [u8]>> {
+        self.section_by_name(name)?.uncompressed_data().ok()
+    }
+}
+
+/// Loads the DWARF sections of `object` into reference-counted slices, for building an
+/// `addr2line::Context`.
+pub fn load_rc_dwarf(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<gimli::Dwarf<gimli::EndianRcSlice<gimli::RunTimeEndian>>, Error> {
+    let load_section = |id: gimli::SectionId| -> Result<_, gimli::Error> {
+        let data = object
+            .section_data_by_name(id.name())
+            .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+        Ok(gimli::EndianRcSlice::new(Rc::from(&*data), endian))
+    };
+    let load_section_sup = |_| Ok(gimli::EndianRcSlice::new(Rc::from(&[][..]), endian));
+    Ok(gimli::Dwarf::load(&load_section, &load_section_sup)?)
+}
+
+pub fn load_file(
+    object: &object::File,
+    supplementary: Option<&object::File>,
+    dwp: Option<&object::File>,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
             .section_data_by_name(id.name())
             .unwrap_or(borrow::Cow::Borrowed(&[][..])))
     };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+    // Load a supplementary section. dwz moves strings that several programs share into a
+    // supplementary object file (DW_FORM_GNU_strp_alt), if we have found one.
+    let load_section_sup = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+        Ok(supplementary
+            .and_then(|sup| sup.section_data_by_name(id.name()))
+            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+    };
 
     // Load all of the sections.
     let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
@@ -34,228 +72,959 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
+    // The supplementary file's own DWARF, whose partial units our DW_FORM_GNU_ref_alt
+    // references point into
+    let sup_cow = match supplementary {
+        Some(sup) => {
+            let load_sup_section = |id: gimli::SectionId| -> Result<_, gimli::Error> {
+                Ok(sup
+                    .section_data_by_name(id.name())
+                    .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+            };
+            let load_none = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+            Some(gimli::Dwarf::load(&load_sup_section, &load_none)?)
+        }
+        None => None,
+    };
+    let sup_dwarf = sup_cow.as_ref().map(|cow| cow.borrow(&borrow_section));
+
     // Define a mapping from type offsets to type structs
     let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
 
     let mut compilation_units: Vec<File> = Vec::new();
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
+    // Types may be referenced from other units, so every unit's types are loaded before any
+    // variables, which take a copy of their type
+    let mut all_types: Vec<usize> = Vec::new();
+    let mut partial_units: PartialUnits<_> = HashMap::new();
+    if let Some(sup_dwarf) = &sup_dwarf {
+        let mut units = sup_dwarf.units();
+        while let Some(header) = units.next()? {
+            let mut unit = sup_dwarf.unit(header)?;
+            unit.offset = UnitSectionOffset::DebugInfoOffset(gimli::DebugInfoOffset(
+                SUP_OFFSET + header.offset().0,
+            ));
+            all_types.extend(load_types(sup_dwarf, &unit, &mut offset_to_type)?);
+            if let Some(root) = partial_unit_root(&unit)? {
+                partial_units.insert(root, (sup_dwarf, unit));
+            }
+        }
+    }
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
+    // Split units are numbered on from the end of .debug_info, so that the type offsets of one
+    // .dwo file don't collide with another's
+    let size = dwarf.debug_info.reader().len();
+    let mut split_base = size;
+    let split_dwarf = SplitDwarf {
+        object,
+        dwp,
+        endian,
+    };
+
+    // Iterate over the compilation units. gimli only knows the DW_UT_compile header, so we read
+    // the headers ourselves to get at the skeleton units that -gsplit-dwarf leaves behind.
+    let mut units = Vec::new();
+    let mut input = *dwarf.debug_info.reader();
+    while !input.is_empty() {
+        let offset = size - input.len();
+        let (header, dwo_id) = match parse_unit_header(&mut input, offset)? {
+            Some(header) => header,
+            None => continue,
+        };
+        let mut unit = dwarf.unit(header)?;
+        let skeleton = read_unit_root(&mut unit, &dwarf, dwo_id)?;
+        if skeleton.is_none() {
+            all_types.extend(load_types(&dwarf, &unit, &mut offset_to_type)?);
+        }
+        // dwz moves what compile units have in common into partial units, which are only
+        // loaded as part of the units that import them
+        match partial_unit_root(&unit)? {
+            Some(root) => {
+                partial_units.insert(root, (&dwarf, unit));
+            }
+            None => units.push((offset, unit, skeleton)),
+        }
+    }
+    resolve_types(&mut offset_to_type, &all_types);
+
+    for (offset, unit, skeleton) in units {
+        match skeleton {
+            Some(skeleton) => {
+                let found = split_dwarf.load_unit(
+                    &skeleton,
+                    &mut split_base,
+                    &mut offset_to_type,
+                    &mut compilation_units,
+                )?;
+                if !found {
+                    eprintln!(
+                        "Could not find DWO CU {} (0x{:x}) referenced by CU at offset 0x{:x}",
+                        skeleton.dwo_path.display(),
+                        skeleton.dwo_id.unwrap_or(0),
+                        offset
+                    );
+                }
+            }
+            None => load_entries(
+                &dwarf,
+                &unit,
+                &offset_to_type,
+                &mut compilation_units,
+                &partial_units,
+                0,
+            )?,
+        }
+        // A skeleton unit keeps the line table
+        load_lines(&dwarf, &unit, &mut compilation_units)?;
+    }
+    Ok((compilation_units, offset_to_type))
+}
+
+/// Loads the types, functions and variables of a compilation unit.
+fn load_unit<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+    compilation_units: &mut Vec<File>,
+) -> Result<(), Error> {
+    let unit_types = load_types(dwarf, unit, offset_to_type)?;
+    resolve_types(offset_to_type, &unit_types);
+    load_entries(dwarf, unit, offset_to_type, compilation_units, &HashMap::new(), 0)
+}
+
+/// Loads the types of a unit into `offset_to_type`, and returns their offsets. Their names and
+/// sizes are left for `resolve_types` to fill in.
+fn load_types<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<Vec<usize>, Error> {
+    // Types may be referenced before they are declared, so collect them in a first pass.
+    // Struct members, array bounds and enumerators are children of the type they belong to.
+    let mut depth = 0;
+    let mut parents: Vec<(isize, usize)> = Vec::new();
+    let mut unit_types: Vec<usize> = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while parents.last().map_or(false, |&(d, _)| d >= depth) {
+            parents.pop();
+        }
+        let type_offset = section_offset(entry.offset(), unit);
+        let name = get_attr_string(entry, gimli::DW_AT_name, unit, dwarf);
+        let target = get_attr_ref(entry, gimli::DW_AT_type, unit, dwarf);
+        let kind = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                    Ok(Some(gimli::AttributeValue::Encoding(encoding))) => encoding,
+                    _ => gimli::DwAte(0),
+                };
+                TypeKind::Base(match encoding {
+                    gimli::DW_ATE_signed => BaseEncoding::Signed,
+                    gimli::DW_ATE_unsigned => BaseEncoding::Unsigned,
+                    gimli::DW_ATE_signed_char => BaseEncoding::SignedChar,
+                    gimli::DW_ATE_unsigned_char => BaseEncoding::UnsignedChar,
+                    gimli::DW_ATE_float => BaseEncoding::Float,
+                    gimli::DW_ATE_boolean => BaseEncoding::Boolean,
+                    _ => BaseEncoding::Other,
+                })
+            }
+            gimli::DW_TAG_pointer_type => TypeKind::Pointer(target),
+            gimli::DW_TAG_const_type => TypeKind::Const(target),
+            gimli::DW_TAG_volatile_type => TypeKind::Volatile(target),
+            gimli::DW_TAG_restrict_type => TypeKind::Restrict(target),
+            gimli::DW_TAG_typedef => TypeKind::Typedef(target),
+            gimli::DW_TAG_structure_type => TypeKind::Struct(Vec::new()),
+            gimli::DW_TAG_union_type => TypeKind::Union(Vec::new()),
+            gimli::DW_TAG_enumeration_type => TypeKind::Enum(Vec::new()),
+            gimli::DW_TAG_array_type => TypeKind::Array(target, Vec::new()),
+            gimli::DW_TAG_subroutine_type => TypeKind::Function(target),
+            gimli::DW_TAG_member | gimli::DW_TAG_subrange_type | gimli::DW_TAG_enumerator => {
+                let parent = match parents.last() {
+                    Some(&(d, parent)) if d == depth - 1 => offset_to_type.get_mut(&parent),
+                    _ => None,
+                };
+                let parent = match parent {
+                    Some(parent) => parent,
+                    None => continue,
+                };
+                match (entry.tag(), &mut parent.kind) {
+                    (gimli::DW_TAG_member, TypeKind::Struct(members))
+                    | (gimli::DW_TAG_member, TypeKind::Union(members)) => {
+                        members.push(Member {
+                            name: name.unwrap_or_default(),
+                            type_offset: target,
+                            offset: get_attr_udata(entry, gimli::DW_AT_data_member_location)
+                                .unwrap_or(0) as usize,
+                        });
+                    }
+                    (gimli::DW_TAG_subrange_type, TypeKind::Array(_, dims)) => {
+                        let count = get_attr_udata(entry, gimli::DW_AT_count).or_else(|| {
+                            get_attr_udata(entry, gimli::DW_AT_upper_bound).map(|ub| ub + 1)
+                        });
+                        dims.push(count.map(|count| count as usize));
+                    }
+                    (gimli::DW_TAG_enumerator, TypeKind::Enum(enumerators)) => {
+                        let value = match entry.attr_value(gimli::DW_AT_const_value) {
+                            Ok(Some(gimli::AttributeValue::Sdata(value))) => value,
+                            Ok(Some(value)) => value.udata_value().unwrap_or(0) as i64,
+                            _ => 0,
+                        };
+                        enumerators.push(Enumerator {
+                            name: name.unwrap_or_default(),
+                            value,
+                        });
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let name = match (&kind, name) {
+            (TypeKind::Struct(_), name) => format!("struct {}", name.unwrap_or("{...}".to_string())),
+            (TypeKind::Union(_), name) => format!("union {}", name.unwrap_or("{...}".to_string())),
+            (TypeKind::Enum(_), name) => format!("enum {}", name.unwrap_or("{...}".to_string())),
+            (_, name) => name.unwrap_or_default(),
+        };
+        let byte_size = get_attr_udata(entry, gimli::DW_AT_byte_size).unwrap_or(0);
+        offset_to_type.insert(
+            type_offset,
+            Type::new(name, byte_size.try_into().unwrap(), kind),
+        );
+        parents.push((depth, type_offset));
+        unit_types.push(type_offset);
+    }
+    Ok(unit_types)
+}
+
+/// Partial units by the offset of their root DIE, which is what DW_TAG_imported_unit refers to
+type PartialUnits<'a, R> = HashMap<usize, (&'a gimli::Dwarf<R>, gimli::Unit<R>)>;
+
+/// Partial units can import each other, so give up after this many levels in case of a cycle.
+const MAX_IMPORT_DEPTH: usize = 8;
+
+/// Returns the offset of the root DIE of `unit` if it is a partial unit.
+fn partial_unit_root<R: Reader>(unit: &gimli::Unit<R>) -> Result<Option<usize>, Error> {
+    let mut entries = unit.entries();
+    Ok(match entries.next_dfs()? {
+        Some((_, root)) if root.tag() == gimli::DW_TAG_partial_unit => {
+            Some(section_offset(root.offset(), unit))
+        }
+        _ => None,
+    })
+}
+
+/// Loads the functions and variables of a unit. Those of a compile unit go in a new `File`;
+/// those of a partial unit it imports (`import_depth` deep) go in the same one.
+fn load_entries<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    offset_to_type: &HashMap<usize, Type>,
+    compilation_units: &mut Vec<File>,
+    partial_units: &PartialUnits<R>,
+    import_depth: usize,
+) -> Result<(), Error> {
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                        name
                     } else {
                         "<unknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+                    }
+                } else {
+                    "<unknown>".to_string()
+                };
+                compilation_units.push(File {
+                    name,
+                    global_variables: Vec::new(),
+                    functions: Vec::new(),
+                    lines: Vec::new(),
+                });
+            }
+            gimli::DW_TAG_imported_unit => {
+                let imported = get_attr_ref(entry, gimli::DW_AT_import, unit, dwarf)
+                    .and_then(|root| partial_units.get(&root));
+                if let Some((partial_dwarf, partial_unit)) = imported {
+                    if import_depth < MAX_IMPORT_DEPTH {
+                        load_entries(
+                            partial_dwarf,
+                            partial_unit,
+                            offset_to_type,
+                            compilation_units,
+                            partial_units,
+                            import_depth + 1,
+                        )?;
+                    }
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func: Function = Default::default();
+                let mut high_pc = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(value)) = val {
+                                high_pc = Some((value, attr.value()));
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_frame_base => {
+                            if let Some(frame_base) = get_frame_base(&attr, unit) {
+                                func.frame_base = frame_base;
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
+                        }
+                        gimli::DW_AT_ranges => {
+                            // Code moved out of line (main.cold, say) has a range of its
+                            // own. The function is entered through the first.
+                            let value = match attr.value() {
+                                // gimli doesn't apply a DWARF 4 split unit's
+                                // DW_AT_GNU_ranges_base, which we keep in rnglists_base
+                                gimli::AttributeValue::RangeListsRef(offset)
+                                    if unit.header.version() < 5 =>
+                                {
+                                    gimli::AttributeValue::RangeListsRef(
+                                        gimli::RangeListsOffset(offset.0 + unit.rnglists_base.0),
+                                    )
+                                }
+                                value => value,
+                            };
+                            if let Some(mut ranges) = dwarf.attr_ranges(unit, value)? {
+                                if let Some(range) = ranges.next()? {
+                                    func.address = range.begin.try_into().unwrap();
+                                    func.text_length =
+                                        (range.end - range.begin).try_into().unwrap();
                                 }
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
                 }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                // DW_AT_high_pc is usually an offset from DW_AT_low_pc, but may be an
+                // address
+                match high_pc {
+                    Some((high_pc, gimli::AttributeValue::Addr(_)))
+                    | Some((high_pc, gimli::AttributeValue::DebugAddrIndex(_))) => {
+                        func.text_length = (high_pc as usize).saturating_sub(func.address);
+                    }
+                    Some((high_pc, _)) => func.text_length = high_pc.try_into().unwrap(),
+                    None => {}
+                }
+                compilation_units.last_mut().unwrap().functions.push(func);
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if let Some(dtype) = offset_to_type.get(&offset) {
+                                    entity_type = Some(dtype.clone());
                                 }
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, unit, dwarf) {
+                                location = Some(loc);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
-                        }
+                }
+                if let (Some(entity_type), Some(location)) = (entity_type, location) {
+                    let var = Variable {
+                        name,
+                        entity_type,
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                        is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
+                    };
+                    if depth == 1 {
+                        compilation_units
+                            .last_mut()
+                            .unwrap()
+                            .global_variables
+                            .push(var);
+                    } else if depth > 1 {
+                        compilation_units
+                            .last_mut()
+                            .unwrap()
+                            .functions
+                            .last_mut()
+                            .unwrap()
+                            .variables
+                            .push(var);
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
-            }
-        }
-
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
-                        }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
+            }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Adds the rows of the line table of `unit` to the `File`s they belong to.
+fn load_lines<R: Reader>(
+    dwarf: &gimli::Dwarf<R>,
+    unit: &gimli::Unit<R>,
+    compilation_units: &mut [File],
+) -> Result<(), Error> {
+    if let Some(program) = unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                let mut path = path::PathBuf::new();
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        dwarf
+                            .attr_string(unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                // Get the File. Its name may be relative to the compilation directory,
+                // which the line table puts in front of it
+                let file = compilation_units
+                    .iter_mut()
+                    .find(|f| path.ends_with(&f.name));
 
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = row.line().unwrap_or(0);
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+                if let Some(file) = file {
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: row.address().try_into().unwrap(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A unit header, and the DWO ID of a skeleton or split unit
+type HeaderWithId<R> = (gimli::CompilationUnitHeader<R>, Option<u64>);
+
+/// Reads the unit header at the start of `input`, which is `offset` bytes into .debug_info,
+/// and moves past the unit. Returns `None` for type units, which we have no use for. Skeleton
+/// and split units (DWARF 5 -gsplit-dwarf) come back with their DWO ID, the rest as if they
+/// were DW_UT_compile units.
+fn parse_unit_header<R: Reader>(
+    input: &mut R,
+    offset: usize,
+) -> Result<Option<HeaderWithId<R>>, Error> {
+    let (unit_length, format) = input.read_initial_length()?;
+    let mut rest = input.split(unit_length)?;
+    let version = rest.read_u16()?;
+    let (unit_type, address_size, abbrev_offset) = match version {
+        2..=4 => {
+            let abbrev_offset = rest.read_offset(format)?;
+            (gimli::DW_UT_compile, rest.read_u8()?, abbrev_offset)
+        }
+        5 => {
+            let unit_type = gimli::DwUt(rest.read_u8()?);
+            let address_size = rest.read_u8()?;
+            (unit_type, address_size, rest.read_offset(format)?)
+        }
+        _ => return Err(gimli::Error::UnknownVersion(version.into()).into()),
+    };
+    let dwo_id = match unit_type {
+        gimli::DW_UT_compile | gimli::DW_UT_partial => None,
+        gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => Some(rest.read_u64()?),
+        _ => return Ok(None),
+    };
+    let encoding = gimli::Encoding {
+        format,
+        version,
+        address_size,
+    };
+    // gimli works out the size of the header from what is left for the DIEs, so skipping
+    // the DWO ID keeps the offsets right
+    let header = gimli::UnitHeader::new(
+        encoding,
+        unit_length,
+        gimli::DebugAbbrevOffset(abbrev_offset),
+        rest,
+    );
+    Ok(Some((
+        gimli::CompilationUnitHeader::new(header, gimli::DebugInfoOffset(offset)),
+        dwo_id,
+    )))
+}
+
+/// What a skeleton unit says about the split unit it stands in for.
+struct Skeleton {
+    /// The .dwo file the compiler wrote, made absolute with DW_AT_comp_dir
+    dwo_path: path::PathBuf,
+    /// From the DWARF 5 unit header or DW_AT_GNU_dwo_id
+    dwo_id: Option<u64>,
+    /// The split unit's DW_FORM_addrx values index the program's .debug_addr from here
+    addr_base: gimli::DebugAddrBase<usize>,
+    /// DW_AT_GNU_ranges_base: a DWARF 4 split unit's DW_AT_ranges are offsets into the
+    /// program's .debug_ranges from here
+    ranges_base: usize,
+    low_pc: u64,
+}
+
+/// Fills in what gimli doesn't read from the root DIE of `unit`: a DW_FORM_addrx DW_AT_low_pc
+/// and the pre-DWARF 5 DW_AT_GNU_addr_base. Returns what a skeleton unit says about its split
+/// unit, or `None` for an ordinary unit.
+fn read_unit_root<R: Reader>(
+    unit: &mut gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    dwo_id: Option<u64>,
+) -> Result<Option<Skeleton>, Error> {
+    let mut attrs = Vec::new();
+    {
+        let mut entries = unit.entries();
+        if let Some((_, root)) = entries.next_dfs()? {
+            let mut root_attrs = root.attrs();
+            while let Some(attr) = root_attrs.next()? {
+                attrs.push(attr);
+            }
+        }
+    }
+    let mut dwo_name = None;
+    let mut gnu_dwo_id = None;
+    let mut ranges_base = 0;
+    for attr in attrs {
+        match (attr.name(), attr.value()) {
+            (gimli::DW_AT_low_pc, gimli::AttributeValue::DebugAddrIndex(index)) => {
+                unit.low_pc = dwarf.address(unit, index)?;
+            }
+            (gimli::DW_AT_GNU_addr_base, gimli::AttributeValue::SecOffset(base)) => {
+                unit.addr_base = gimli::DebugAddrBase(base);
+            }
+            // Not the skeleton's own: its DW_AT_ranges are from the start of .debug_ranges
+            (gimli::DW_AT_GNU_ranges_base, gimli::AttributeValue::SecOffset(base)) => {
+                ranges_base = base;
+            }
+            (gimli::DW_AT_dwo_name, value) | (gimli::DW_AT_GNU_dwo_name, value) => {
+                dwo_name = Some(dwarf.attr_string(unit, value)?.to_string_lossy()?.to_string());
+            }
+            (gimli::DW_AT_GNU_dwo_id, value) => gnu_dwo_id = value.udata_value(),
+            _ => {}
+        }
+    }
+    let dwo_name = match dwo_name {
+        Some(dwo_name) => dwo_name,
+        None => return Ok(None),
+    };
+    let mut dwo_path = path::PathBuf::new();
+    if let Some(comp_dir) = &unit.comp_dir {
+        dwo_path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    dwo_path.push(dwo_name);
+    Ok(Some(Skeleton {
+        dwo_path,
+        dwo_id: dwo_id.or(gnu_dwo_id),
+        addr_base: unit.addr_base,
+        ranges_base,
+        low_pc: unit.low_pc,
+    }))
+}
+
+/// Where split units come from: the .dwo files that skeleton units name or, once they have been
+/// packed together with `dwp`, a .dwp package next to the program.
+struct SplitDwarf<'a, 'data> {
+    /// The program, whose .debug_addr split units refer to
+    object: &'a object::File<'data>,
+    dwp: Option<&'a object::File<'data>>,
+    endian: gimli::RunTimeEndian,
+}
+
+impl<'a, 'data> SplitDwarf<'a, 'data> {
+    /// Loads the split unit that `skeleton` stands in for, as `load_unit` does an ordinary one.
+    /// Its DIEs are numbered from `split_base`, which is moved past them. Returns false if the
+    /// unit can't be found.
+    fn load_unit(
+        &self,
+        skeleton: &Skeleton,
+        split_base: &mut usize,
+        offset_to_type: &mut HashMap<usize, Type>,
+        compilation_units: &mut Vec<File>,
+    ) -> Result<bool, Error> {
+        let dwo_mmap;
+        let dwo_object;
+        let package = self
+            .dwp
+            .and_then(|dwp| Some((dwp, dwp_contributions(dwp, skeleton.dwo_id?, self.endian)?)));
+        let (dwo, contributions) = match package {
+            Some(package) => package,
+            None => {
+                dwo_mmap = match std::fs::File::open(&skeleton.dwo_path)
+                    .and_then(|file| unsafe { memmap::Mmap::map(&file) })
+                {
+                    Ok(mmap) => mmap,
+                    Err(_) => return Ok(false),
+                };
+                dwo_object = object::File::parse(&dwo_mmap)
+                    .map_err(|e| Error::ObjectError(e.to_string()))?;
+                (&dwo_object, Vec::new())
+            }
+        };
+
+        let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+            // The addresses a split unit refers to stay behind in the program
+            if id == gimli::SectionId::DebugAddr || id == gimli::SectionId::DebugRanges {
+                return Ok(self
+                    .object
+                    .section_data_by_name(id.name())
+                    .unwrap_or(borrow::Cow::Borrowed(&[][..])));
+            }
+            let data = dwo
+                .section_data_by_name(&format!("{}.dwo", id.name()))
+                .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+            // In a package, each unit has its own part of most sections
+            Ok(match contributions.iter().find(|&&(section, _, _)| section == id) {
+                Some(&(_, start, len)) => {
+                    borrow::Cow::Owned(data.get(start..start + len).unwrap_or(&[]).to_vec())
+                }
+                None => data,
+            })
+        };
+        let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+        let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+        let endian = self.endian;
+        let borrow_section: &dyn for<'b> Fn(
+            &'b borrow::Cow<[u8]>,
+        ) -> gimli::EndianSlice<'b, gimli::RunTimeEndian> =
+            &|section| gimli::EndianSlice::new(section, endian);
+        let dwarf = dwarf_cow.borrow(&borrow_section);
+
+        let size = dwarf.debug_info.reader().len();
+        let mut input = *dwarf.debug_info.reader();
+        while !input.is_empty() {
+            let offset = size - input.len();
+            let (header, dwo_id) = match parse_unit_header(&mut input, offset)? {
+                Some(header) => header,
+                None => continue,
+            };
+            let header = header.header();
+            // The lists of a DWARF 5 split unit start after the header of its part of each
+            // section, where an ordinary unit would have a DW_AT_*_base to say so. A DWARF 4
+            // one's ranges are in the program's .debug_ranges, from where the skeleton says.
+            let (str_offsets_base, lists_base, ranges_base) = if header.version() >= 5 {
+                let length_size = header.format().initial_length_size() as usize;
+                (length_size + 4, length_size + 8, length_size + 8)
+            } else {
+                (0, 0, skeleton.ranges_base)
+            };
+            let unit = gimli::Unit {
+                // Numbered on from the units before it, so that its type offsets are its own
+                offset: UnitSectionOffset::DebugInfoOffset(gimli::DebugInfoOffset(
+                    *split_base + offset,
+                )),
+                abbreviations: header.abbreviations(&dwarf.debug_abbrev)?,
+                header,
+                name: None,
+                comp_dir: None,
+                low_pc: skeleton.low_pc,
+                str_offsets_base: gimli::DebugStrOffsetsBase(str_offsets_base),
+                addr_base: skeleton.addr_base,
+                loclists_base: gimli::DebugLocListsBase(lists_base),
+                rnglists_base: gimli::DebugRngListsBase(ranges_base),
+                line_program: None,
+            };
+            let dwo_id = match dwo_id {
+                Some(dwo_id) => Some(dwo_id),
+                None => {
+                    let mut entries = unit.entries();
+                    match entries.next_dfs()? {
+                        Some((_, root)) => get_attr_udata(root, gimli::DW_AT_GNU_dwo_id),
+                        None => None,
                     }
                 }
+            };
+            if skeleton.dwo_id.is_some() && dwo_id.is_some() && dwo_id != skeleton.dwo_id {
+                continue;
             }
+            load_unit(&dwarf, &unit, offset_to_type, compilation_units)?;
+            *split_base += size;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+/// Finds the parts of each section of a .dwp package that belong to the unit with `dwo_id`,
+/// using the package's .debug_cu_index.
+fn dwp_contributions(
+    dwp: &object::File,
+    dwo_id: u64,
+    endian: gimli::RunTimeEndian,
+) -> Option<Vec<(gimli::SectionId, usize, usize)>> {
+    use gimli::Endianity;
+    let index = dwp.section_data_by_name(".debug_cu_index")?;
+    let word = |offset: usize| -> Option<usize> {
+        Some(endian.read_u32(index.get(offset..offset + 4)?) as usize)
+    };
+    // Version 2 is the GNU extension for DWARF 4; DWARF 5 has a 2-byte version and padding
+    let version = word(0)?;
+    let (section_count, unit_count, slot_count) = (word(4)?, word(8)?, word(12)?);
+    let signatures = 16;
+    let indexes = signatures + 8 * slot_count;
+    let row = (0..slot_count).find_map(|slot| {
+        let offset = signatures + 8 * slot;
+        let signature = endian.read_u64(index.get(offset..offset + 8)?);
+        match word(indexes + 4 * slot)? {
+            row if signature == dwo_id && row != 0 => Some(row - 1),
+            _ => None,
+        }
+    })?;
+    let columns = indexes + 4 * slot_count;
+    let offsets = columns + 4 * section_count;
+    let sizes = offsets + 4 * section_count * unit_count;
+    let mut contributions = Vec::new();
+    for column in 0..section_count {
+        let section = match (version, word(columns + 4 * column)?) {
+            (_, 1) => gimli::SectionId::DebugInfo,
+            (_, 3) => gimli::SectionId::DebugAbbrev,
+            (_, 4) => gimli::SectionId::DebugLine,
+            (2, 5) => gimli::SectionId::DebugLoc,
+            (5, 5) => gimli::SectionId::DebugLocLists,
+            (_, 6) => gimli::SectionId::DebugStrOffsets,
+            (5, 8) => gimli::SectionId::DebugRngLists,
+            _ => continue,
+        };
+        let cell = 4 * (row * section_count + column);
+        contributions.push((section, word(offsets + cell)?, word(sizes + cell)?));
+    }
+    Some(contributions)
+}
+
+/// Fills in the names of derived types (`char *`, `const int`, `int [5]`) and the sizes that
+/// DWARF leaves implicit, such as those of typedefs and most arrays.
+fn resolve_types(types: &mut HashMap<usize, Type>, offsets: &[usize]) {
+    for &offset in offsets {
+        let name = type_name(types, Some(offset), 0);
+        let size = type_size(types, Some(offset), 0);
+        let entity_type = types.get_mut(&offset).unwrap();
+        entity_type.name = name;
+        entity_type.size = size;
+    }
+}
+
+// Types can refer to themselves (through typedefs of incomplete structs, say), so bail out
+// after this many levels.
+const MAX_TYPE_DEPTH: usize = 16;
+
+fn type_name(types: &HashMap<usize, Type>, offset: Option<usize>, depth: usize) -> String {
+    let entity_type = match offset.and_then(|offset| types.get(&offset)) {
+        Some(entity_type) => entity_type,
+        None => return "void".to_string(),
+    };
+    if depth > MAX_TYPE_DEPTH {
+        return entity_type.name.clone();
+    }
+    let target_name = |target: &Option<usize>| type_name(types, *target, depth + 1);
+    match &entity_type.kind {
+        TypeKind::Pointer(target) => {
+            if let Some(TypeKind::Function(ret)) =
+                target.and_then(|t| types.get(&t)).map(|t| &t.kind)
+            {
+                return format!("{} (*)()", target_name(ret));
+            }
+            let name = target_name(target);
+            if name.ends_with('*') {
+                format!("{}*", name)
+            } else {
+                format!("{} *", name)
+            }
+        }
+        TypeKind::Const(target) => format!("const {}", target_name(target)),
+        TypeKind::Volatile(target) => format!("volatile {}", target_name(target)),
+        TypeKind::Restrict(target) => format!("{} restrict", target_name(target)),
+        TypeKind::Array(elem, dims) => {
+            let dims: Vec<String> = dims
+                .iter()
+                .map(|dim| dim.map_or(String::new(), |count| count.to_string()))
+                .collect();
+            format!("{} [{}]", target_name(elem), dims.join("]["))
         }
+        TypeKind::Function(ret) => format!("{} ()", target_name(ret)),
+        _ => entity_type.name.clone(),
+    }
+}
+
+fn type_size(types: &HashMap<usize, Type>, offset: Option<usize>, depth: usize) -> usize {
+    let entity_type = match offset.and_then(|offset| types.get(&offset)) {
+        Some(entity_type) => entity_type,
+        None => return 0,
+    };
+    if depth > MAX_TYPE_DEPTH {
+        return entity_type.size;
+    }
+    match &entity_type.kind {
+        TypeKind::Typedef(target)
+        | TypeKind::Const(target)
+        | TypeKind::Volatile(target)
+        | TypeKind::Restrict(target) => type_size(types, *target, depth + 1),
+        TypeKind::Array(elem, dims) if entity_type.size == 0 => {
+            let count: usize = dims.iter().map(|dim| dim.unwrap_or(0)).product();
+            count * type_size(types, *elem, depth + 1)
+        }
+        _ => entity_type.size,
     }
-    Ok(compilation_units)
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
+    // Only looked at when dumping attributes
+    #[allow(dead_code)]
     Int(i64),
     Size(usize),
     NoVal,
 }
 
+type RcReader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// The call frame information (.eh_frame and .debug_frame) of an executable, which says how to
+/// recover the caller's registers at every instruction.
+pub struct CallFrameInfo {
+    eh_frame: gimli::EhFrame<RcReader>,
+    debug_frame: gimli::DebugFrame<RcReader>,
+    bases: gimli::BaseAddresses,
+}
+
+pub fn load_call_frame_info(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+    let load_section = |name: &str| {
+        let data = object
+            .section_data_by_name(name)
+            .unwrap_or(borrow::Cow::Borrowed(&[][..]));
+        gimli::EndianRcSlice::new(Rc::from(&*data), endian)
+    };
+    let section_address = |name: &str| object.section_by_name(name).map_or(0, |s| s.address());
+    let mut eh_frame = gimli::EhFrame::from(load_section(".eh_frame"));
+    eh_frame.set_address_size(8);
+    let mut debug_frame = gimli::DebugFrame::from(load_section(".debug_frame"));
+    debug_frame.set_address_size(8);
+    let bases = gimli::BaseAddresses::default()
+        .set_eh_frame(section_address(".eh_frame"))
+        .set_text(section_address(".text"))
+        .set_got(section_address(".got"));
+    CallFrameInfo {
+        eh_frame,
+        debug_frame,
+        bases,
+    }
+}
+
+impl Default for CallFrameInfo {
+    /// No call frame information at all.
+    fn default() -> Self {
+        let empty = || gimli::EndianRcSlice::new(Rc::from(&[][..]), gimli::RunTimeEndian::Little);
+        CallFrameInfo {
+            eh_frame: gimli::EhFrame::from(empty()),
+            debug_frame: gimli::DebugFrame::from(empty()),
+            bases: gimli::BaseAddresses::default(),
+        }
+    }
+}
+
+impl CallFrameInfo {
+    /// Returns the unwinding rule for the frame executing `addr`, or None if the CFI doesn't
+    /// cover it or describes it in a way we don't support (e.g. with DWARF expressions).
+    pub fn find_rule(&self, addr: usize) -> Option<FrameRule> {
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        let row = self
+            .eh_frame
+            .unwind_info_for_address(&self.bases, &mut ctx, addr as u64, gimli::EhFrame::cie_from_offset)
+            .or_else(|_| {
+                self.debug_frame.unwind_info_for_address(
+                    &self.bases,
+                    &mut ctx,
+                    addr as u64,
+                    gimli::DebugFrame::cie_from_offset,
+                )
+            })
+            .ok()?;
+        let (cfa_register, cfa_offset) = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => (register.0, *offset),
+            gimli::CfaRule::Expression(_) => return None,
+        };
+        let return_address = match row.register(gimli::X86_64::RA) {
+            gimli::RegisterRule::Offset(offset) => Some(offset),
+            gimli::RegisterRule::Undefined => None,
+            _ => return None,
+        };
+        let rbp = match row.register(gimli::X86_64::RBP) {
+            gimli::RegisterRule::Offset(offset) => Some(offset),
+            gimli::RegisterRule::Undefined | gimli::RegisterRule::SameValue => None,
+            _ => return None,
+        };
+        Some(FrameRule {
+            cfa_register,
+            cfa_offset,
+            return_address,
+            rbp,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
 }
@@ -266,12 +1035,6 @@ impl From<gimli::Error> for Error {
     }
 }
 
-impl From<addr2line::gimli::Error> for Error {
-    fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
-    }
-}
-
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
         Error::IoError
@@ -291,10 +1054,68 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+/// The .debug_info offsets of the supplementary file are moved up by this much, to keep its types
+/// apart from ours.
+const SUP_OFFSET: usize = 1 << 39;
+
+/// Converts an offset within `unit` to an offset within the whole .debug_info section, which is
+/// what DW_AT_type references resolve to (see `get_attr_value`).
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+fn get_attr_string<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn get_attr_ref<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
+fn get_attr_udata<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<u64> {
+    entry.attr_value(name).ok()??.udata_value()
+}
+
+const DW_OP_GNU_ADDR_INDEX: u8 = 0xfb;
+
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    // Split units from before DWARF 5 use DW_FORM_block1 rather than DW_FORM_exprloc
+    if let Some(data) = attr.exprloc_value() {
         let encoding = unit.encoding();
         let mut pc = data.0.clone();
+        // gimli doesn't know DW_OP_GNU_addr_index, the DW_OP_addrx of -gdwarf-4 -gsplit-dwarf
+        if pc.read_u8().ok()? == DW_OP_GNU_ADDR_INDEX {
+            let index = gimli::DebugAddrIndex(pc.read_uleb128().ok()? as usize);
+            let address = dwarf.address(unit, index).ok()?;
+            return Some(Location::Address(address.try_into().unwrap()));
+        }
+        let mut pc = data.0.clone();
         if pc.len() > 0 {
             if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
                 match op {
@@ -304,6 +1125,11 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
                     gimli::Operation::Address { address } => {
                         return Some(Location::Address(address.try_into().unwrap()));
                     }
+                    // DW_OP_addrx, in split units
+                    gimli::Operation::AddressIndex { index } => {
+                        let address = dwarf.address(unit, index).ok()?;
+                        return Some(Location::Address(address.try_into().unwrap()));
+                    }
                     _ => {}
                 }
             }
@@ -312,6 +1138,21 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
     None
 }
 
+fn get_frame_base<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+) -> Option<FrameBase> {
+    let mut pc = attr.exprloc_value()?.0;
+    match gimli::Operation::parse(&mut pc, unit.encoding()).ok()? {
+        gimli::Operation::CallFrameCFA => Some(FrameBase::Cfa),
+        gimli::Operation::Register { register } => Some(FrameBase::Register(register.0, 0)),
+        gimli::Operation::RegisterOffset {
+            register, offset, ..
+        } => Some(FrameBase::Register(register.0, offset)),
+        _ => None,
+    }
+}
+
 // based on dwarf_dump.rs
 fn get_attr_value<R: Reader>(
     attr: &gimli::Attribute<R>,
@@ -337,6 +1178,16 @@ fn get_attr_value<R: Reader>(
                 }
             }
         }
+        // DW_FORM_ref_addr, which is how dwz refers to the partial units it makes. A reference
+        // in the supplementary file is to somewhere else in that file
+        gimli::AttributeValue::DebugInfoRef(offset) => {
+            let in_sup = section_offset(UnitOffset(0), unit) >= SUP_OFFSET;
+            Ok(DebugValue::Size(if in_sup { SUP_OFFSET + offset.0 } else { offset.0 }))
+        }
+        // DW_FORM_GNU_ref_alt, into the supplementary file
+        gimli::AttributeValue::DebugInfoRefSup(offset) => {
+            Ok(DebugValue::Size(SUP_OFFSET + offset.0))
+        }
         gimli::AttributeValue::DebugStrRef(offset) => {
             if let Ok(s) = dwarf.debug_str.get_str(offset) {
                 Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
@@ -344,8 +1195,19 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        // DWARF 5 strings in .debug_line_str, or found through .debug_str_offsets, and strings
+        // that dwz moved into a supplementary file
+        gimli::AttributeValue::DebugLineStrRef(_)
+        | gimli::AttributeValue::DebugStrOffsetsIndex(_)
+        | gimli::AttributeValue::DebugStrRefSup(_) => match dwarf.attr_string(unit, value) {
+            Ok(s) => Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?))),
+            Err(_) => Ok(DebugValue::NoVal),
+        },
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
 
         gimli::AttributeValue::String(s) => {
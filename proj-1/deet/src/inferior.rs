@@ -1,11 +1,19 @@
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::sys::ptrace::Options;
 use nix::unistd::Pid;
 use std::process::Child;
-use crate::dwarf_data::{DwarfData}; // for milestone3
+use crate::dwarf_data::{DwarfData, Line}; // for milestone3
+use crate::registers::FpRegisters;
 use std::collections::HashMap;      // for milestone6
 use nix::sys::signal::Signal;       // for milestone6
+use std::fmt;
+use std::os::raw::c_ulong;
+
+// DWARF register numbers on x86-64
+pub const DWARF_RBP: u16 = 6;
+pub const DWARF_RSP: u16 = 7;
 
 #[derive(Clone)]
 pub struct Restorepoint {
@@ -13,6 +21,20 @@ pub struct Restorepoint {
     orig_byte: u8,
 }
 
+/// Called when the inferior hits the breakpoint at an address, to say whether it should stop
+/// there. It needn't if the breakpoint's condition is false or it is being ignored.
+pub type BreakpointFilter<'a> = dyn FnMut(&Inferior, usize) -> bool + 'a;
+
+/// Undoes our traps in `bytes`, which were read from the inferior at `addr`, so that they show
+/// the program's own code.
+pub fn restore_original_bytes(rs_map: &HashMap<usize, Restorepoint>, addr: usize, bytes: &mut [u8]) {
+    for restore in rs_map.values() {
+        if restore.addr >= addr && restore.addr < addr + bytes.len() {
+            bytes[restore.addr - addr] = restore.orig_byte;
+        }
+    }
+}
+
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -24,6 +46,208 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped at a fork or vfork that is being caught. Contains which it
+    /// was, the pid of the new process, and the instruction pointer.
+    Forked(CatchKind, Pid, usize),
+
+    /// Indicates the inferior exec'd a new program, and is stopped at its entry point.
+    Execed(usize),
+
+    /// Indicates the dynamic linker has just loaded or unloaded shared libraries, and is stopped
+    /// at the function it calls to let debuggers know.
+    LibrariesChanged,
+}
+
+/// The events `catch` can stop at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CatchKind {
+    Fork,
+    Vfork,
+    Exec,
+}
+
+/// What `wait` does when the inferior forks, from `set follow-fork-mode`, `set detach-on-fork`
+/// and `catch fork|vfork`.
+#[derive(Debug, Clone, Copy)]
+pub struct ForkOptions {
+    pub follow_child: bool,
+    pub detach_on_fork: bool,
+    pub catch_fork: bool,
+    pub catch_vfork: bool,
+}
+
+impl Default for ForkOptions {
+    fn default() -> Self {
+        ForkOptions {
+            follow_child: false,
+            detach_on_fork: true,
+            catch_fork: false,
+            catch_vfork: false,
+        }
+    }
+}
+
+/// What happens when the inferior receives a signal, as set by `handle`: whether deet stops and
+/// tells the user, and whether the program gets to see the signal when it resumes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalAction {
+    pub stop: bool,
+    pub print: bool,
+    pub pass: bool,
+}
+
+impl SignalAction {
+    /// The usual handling of `signal`. Signals programs get routinely are passed along quietly,
+    /// and SIGTRAP and SIGINT belong to the debugger.
+    pub fn default_for(signal: Signal) -> SignalAction {
+        match signal {
+            Signal::SIGALRM | Signal::SIGURG | Signal::SIGCHLD | Signal::SIGWINCH
+            | Signal::SIGIO | Signal::SIGVTALRM | Signal::SIGPROF => {
+                SignalAction { stop: false, print: false, pass: true }
+            }
+            Signal::SIGTRAP | Signal::SIGINT => SignalAction { stop: true, print: true, pass: false },
+            _ => SignalAction { stop: true, print: true, pass: true },
+        }
+    }
+}
+
+/// Describes `signal` the way strsignal(3) does, e.g. "User defined signal 1".
+pub fn signal_description(signal: Signal) -> String {
+    let description = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(signal as i32)) };
+    description.to_string_lossy().to_string()
+}
+
+/// What a hardware watchpoint fires on. x86 debug registers can't trap on reads alone, so `Read`
+/// is programmed like `Access` and told apart by whether the value changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+/// The registers that identify a stack frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRegs {
+    pub rip: usize,
+    pub rsp: usize,
+    pub rbp: usize,
+}
+
+impl FrameRegs {
+    /// The address to look up this frame's function, line and CFI with. In every frame but the
+    /// innermost, rip is a return address just past the call, which may already belong to the
+    /// next line (or even the next function), so we use the call instruction instead.
+    pub fn lookup_addr(&self, innermost: bool) -> usize {
+        if innermost {
+            self.rip
+        } else {
+            self.rip.saturating_sub(1)
+        }
+    }
+}
+
+/// A frame of the call stack, as `backtrace` lists them and `frame N` selects them. Level 0 is
+/// the innermost frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub level: usize,
+    pub regs: FrameRegs,
+    /// The canonical frame address: %rsp just before the call into this frame. Locals are found
+    /// relative to the function's frame base (DW_AT_frame_base) instead, which may differ
+    pub cfa: usize,
+    pub function: Option<String>,
+    pub line: Option<Line>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.function, &self.line) {
+            (Some(func_name), Some(line)) => write!(f, "{} ({})", func_name, line),
+            (Some(func_name), None) => write!(f, "{:#x} in {} ()", self.regs.rip, func_name),
+            _ => write!(f, "{:#x} in ?? ()", self.regs.rip),
+        }
+    }
+}
+
+// Give up unwinding after this many frames, in case the stack is corrupt
+const MAX_FRAMES: usize = 1024;
+
+// The auxiliary vector entries holding where the dynamic linker was loaded, and the program's
+// entry point
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+
+// Give up walking the dynamic linker's list of libraries after this many, in case it is corrupt
+const MAX_LIBRARIES: usize = 4096;
+
+// personality(2) flag that turns off address space randomization
+const ADDR_NO_RANDOMIZE: c_ulong = 0x0040000;
+
+// offsetof(struct user, u_debugreg) on x86-64
+const DEBUGREG_OFFSET: usize = 848;
+
+// The regset with the XSAVE area, for PTRACE_GETREGSET, and a buffer size that holds it
+const NT_X86_XSTATE: usize = 0x202;
+const XSAVE_MAX_SIZE: usize = 4096;
+
+/// The ptrace options we trace every inferior with, so we hear about new threads, forks and
+/// execs.
+fn trace_options() -> Options {
+    Options::PTRACE_O_TRACECLONE
+        | Options::PTRACE_O_TRACEFORK
+        | Options::PTRACE_O_TRACEVFORK
+        | Options::PTRACE_O_TRACEVFORKDONE
+        | Options::PTRACE_O_TRACEEXEC
+}
+
+/// Returns the process that thread `tid` belongs to, from /proc/<tid>/status.
+fn thread_group(tid: Pid) -> Option<Pid> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("Tgid:"))?;
+    Some(Pid::from_raw(line["Tgid:".len()..].trim().parse().ok()?))
+}
+
+/// Writes the traps in `rs_map` into process `pid`, or with `insert` unset puts the original
+/// bytes back. `pid` needn't be the inferior: the copy of it made by a fork carries our traps too.
+fn write_traps(pid: Pid, rs_map: &HashMap<usize, Restorepoint>, insert: bool) -> Result<(), nix::Error> {
+    for restore in rs_map.values() {
+        let aligned_addr = restore.addr & !7;
+        let shift = 8 * (restore.addr - aligned_addr);
+        let byte = if insert { 0xcc } else { restore.orig_byte as u64 };
+        let word = ptrace::read(pid, aligned_addr as ptrace::AddressType)? as u64;
+        let word = (word & !(0xff << shift)) | (byte << shift);
+        ptrace::write(pid, aligned_addr as ptrace::AddressType, word as *mut std::ffi::c_void)?;
+    }
+    Ok(())
+}
+
+/// Splits an auxiliary vector, as found in /proc/<pid>/auxv or a core dump's NT_AUXV note, into
+/// its (type, value) pairs.
+pub fn parse_auxv(bytes: &[u8]) -> Vec<(u64, u64)> {
+    bytes
+        .chunks_exact(16)
+        .map(|pair| {
+            let mut words = [0u8; 8];
+            words.copy_from_slice(&pair[..8]);
+            let key = u64::from_le_bytes(words);
+            words.copy_from_slice(&pair[8..]);
+            (key, u64::from_le_bytes(words))
+        })
+        .take_while(|&(key, _)| key != 0)
+        .collect()
+}
+
+/// Turns off address space randomization for this process and whatever it execs, like
+/// `setarch -R`. Runs in the child between fork and exec.
+fn disable_randomization() {
+    unsafe {
+        let persona = libc::personality(0xffff_ffff);
+        if persona != -1 {
+            libc::personality(persona as c_ulong | ADDR_NO_RANDOMIZE);
+        }
+    }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -35,65 +259,463 @@ fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// A stopped program whose registers and memory we can look at: a live inferior, or a core
+/// dump.
+pub trait Tracee {
+    fn get_regs(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Returns the x87, SSE and (if available) AVX registers.
+    fn get_fpregs(&self) -> Result<FpRegisters, nix::Error>;
+
+    /// Reads `len` bytes of the program's memory starting at `addr`.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    /// The auxiliary vector the kernel started the program with, if we can get at it.
+    fn auxv(&self) -> Option<Vec<(u64, u64)>>;
+
+    /// Where the program's entry point ended up in memory. Compared with the entry point in the
+    /// ELF header, this tells us how far a position-independent executable was moved.
+    fn entry_point(&self) -> Option<usize> {
+        self.auxv()?
+            .into_iter()
+            .find(|&(key, _)| key == AT_ENTRY)
+            .map(|(_, value)| value as usize)
+    }
+
+    /// Where the dynamic linker was loaded, or None for a statically linked program.
+    fn interpreter_base(&self) -> Option<usize> {
+        self.auxv()?
+            .into_iter()
+            .find(|&(key, value)| key == AT_BASE && value != 0)
+            .map(|(_, value)| value as usize)
+    }
+
+    /// Walks the dynamic linker's list of loaded objects, starting from its `r_debug` at
+    /// `r_debug`, and returns the path and load bias of each shared library. The program itself
+    /// heads the list with an empty name, and is left out.
+    fn link_map(&self, r_debug: usize) -> Result<Vec<(String, usize)>, nix::Error> {
+        // struct r_debug { int r_version; struct link_map *r_map; ... }
+        let mut entry = self.read_word(r_debug + 8)? as usize;
+        let mut libraries = Vec::new();
+        while entry != 0 && libraries.len() < MAX_LIBRARIES {
+            // struct link_map { ElfW(Addr) l_addr; char *l_name; ElfW(Dyn) *l_ld;
+            //                   struct link_map *l_next, *l_prev; }
+            let base = self.read_word(entry)? as usize;
+            let mut name = Vec::new();
+            let mut addr = self.read_word(entry + 8)? as usize;
+            'read: while addr != 0 && name.len() < libc::PATH_MAX as usize {
+                for &byte in self.read_word(addr)?.to_le_bytes().iter() {
+                    if byte == 0 {
+                        break 'read;
+                    }
+                    name.push(byte);
+                }
+                addr += 8;
+            }
+            if !name.is_empty() {
+                libraries.push((String::from_utf8_lossy(&name).to_string(), base));
+            }
+            entry = self.read_word(entry + 24)? as usize;
+        }
+        Ok(libraries)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        let bytes = self.read_memory(addr, 8)?;
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&bytes);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    /// Unwinds the stack using the call frame information, returning the registers of each
+    /// frame, innermost first. Like gdb, it stops at `main`; otherwise it ends at the outermost
+    /// frame (whose return address is undefined) or wherever there is no unwind information.
+    fn unwind(&self, debug_data: &DwarfData) -> Result<Vec<FrameRegs>, nix::Error> {
+        let regs = self.get_regs()?;
+        let mut frame = FrameRegs {
+            rip: regs.rip as usize,
+            rsp: regs.rsp as usize,
+            rbp: regs.rbp as usize,
+        };
+        let mut frames = vec![frame];
+        while frames.len() < MAX_FRAMES {
+            if debug_data.get_function_from_addr(frame.rip).as_deref() == Some("main") {
+                break;
+            }
+            let rule = match debug_data.get_frame_rule(frame.lookup_addr(frames.len() == 1)) {
+                Some(rule) => rule,
+                None => break,
+            };
+            let cfa = match rule.cfa_register {
+                DWARF_RBP => frame.rbp,
+                DWARF_RSP => frame.rsp,
+                _ => break,
+            } as i64
+                + rule.cfa_offset;
+            let rip = match rule.return_address {
+                Some(offset) => match self.read_word((cfa + offset) as usize) {
+                    Ok(rip) => rip as usize,
+                    Err(_) => break,
+                },
+                None => break,
+            };
+            let rbp = match rule.rbp {
+                Some(offset) => match self.read_word((cfa + offset) as usize) {
+                    Ok(rbp) => rbp as usize,
+                    Err(_) => break,
+                },
+                None => frame.rbp,
+            };
+            // The stack grows down, so a caller's frame is always above its callee's
+            if rip == 0 || cfa as usize <= frame.rsp {
+                break;
+            }
+            frame = FrameRegs {
+                rip,
+                rsp: cfa as usize,
+                rbp,
+            };
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    /// Unwinds the stack like `unwind`, and works out each frame's function, line and CFA.
+    fn frames(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.unwind(debug_data)?;
+        let mut frames = Vec::new();
+        for (level, frame) in regs.iter().enumerate() {
+            let addr = frame.lookup_addr(level == 0);
+            // A caller's %rsp is its callee's CFA. The innermost frame may still be in its
+            // prologue, and nothing is above the outermost one, so those are worked out
+            let cfa = if level == 0 {
+                self.get_cfa(debug_data)?
+            } else if let Some(caller) = regs.get(level + 1) {
+                caller.rsp
+            } else {
+                match debug_data.get_frame_rule(addr) {
+                    Some(rule) if rule.cfa_register == DWARF_RBP => {
+                        (frame.rbp as i64 + rule.cfa_offset) as usize
+                    }
+                    Some(rule) if rule.cfa_register == DWARF_RSP => {
+                        (frame.rsp as i64 + rule.cfa_offset) as usize
+                    }
+                    // Assume the usual frame pointer prologue
+                    _ => frame.rbp + 16,
+                }
+            };
+            frames.push(Frame {
+                level,
+                regs: *frame,
+                cfa,
+                function: debug_data.get_function_from_addr(addr),
+                line: debug_data.get_line_from_addr(addr),
+            });
+        }
+        Ok(frames)
+    }
+
+    fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        // For milestone3
+        for frame in self.frames(debug_data)? {
+            println!("#{:<3}{}", frame.level, frame);
+        }
+        Ok(())
+    }
+
+    /// Returns the canonical frame address of the current function: the value %rsp had just
+    /// before the call into it, which is also what gcc uses as the frame base for locals. It
+    /// comes from the CFI when there is some; otherwise, since %rbp still belongs to the caller
+    /// inside the prologue, we check how far through `push %rbp; mov %rsp,%rbp` we are.
+    fn get_cfa(&self, debug_data: &DwarfData) -> Result<usize, nix::Error> {
+        let regs = self.get_regs()?;
+        let (rip, rsp, rbp) = (regs.rip as usize, regs.rsp as usize, regs.rbp as usize);
+        if let Some(rule) = debug_data.get_frame_rule(rip) {
+            match rule.cfa_register {
+                DWARF_RBP => return Ok((rbp as i64 + rule.cfa_offset) as usize),
+                DWARF_RSP => return Ok((rsp as i64 + rule.cfa_offset) as usize),
+                _ => {}
+            }
+        }
+        // Without CFI, assume the usual frame pointer prologue
+        let cfa = match debug_data.get_function_for_addr(rip) {
+            Some(func) => {
+                let mut push_addr = func.address;
+                // skip endbr64
+                if self.read_word(push_addr)? as u32 == 0xfa1e0ff3 {
+                    push_addr += 4;
+                }
+                if rip <= push_addr {
+                    rsp + 8
+                } else if rip == push_addr + 1 {
+                    rsp + 16
+                } else {
+                    rbp + 16
+                }
+            }
+            None => rbp + 16,
+        };
+        Ok(cfa)
+    }
+}
+
+impl Tracee for Inferior {
+    fn get_regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.tid())
+    }
+
+    fn get_fpregs(&self) -> Result<FpRegisters, nix::Error> {
+        let mut fxsave = vec![0u8; std::mem::size_of::<libc::user_fpregs_struct>()];
+        let ret = unsafe {
+            libc::ptrace(libc::PTRACE_GETFPREGS, self.tid().as_raw(), 0, fxsave.as_mut_ptr())
+        };
+        if ret == -1 {
+            return Err(nix::Error::last());
+        }
+        // The XSAVE area holds the upper halves of the ymm registers. Without AVX there isn't
+        // one, and we just show the SSE registers
+        let mut xsave = vec![0u8; XSAVE_MAX_SIZE];
+        let mut iov = libc::iovec {
+            iov_base: xsave.as_mut_ptr() as *mut libc::c_void,
+            iov_len: xsave.len(),
+        };
+        let ret = unsafe {
+            libc::ptrace(libc::PTRACE_GETREGSET, self.tid().as_raw(), NT_X86_XSTATE, &mut iov)
+        };
+        let xsave = if ret == -1 { None } else { Some(&xsave[..iov.iov_len]) };
+        Ok(FpRegisters::new(fxsave, xsave))
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let aligned_addr = self.align_addr_to_word(addr);
+        let mut bytes = Vec::new();
+        let mut word_addr = aligned_addr;
+        while word_addr < addr + len {
+            bytes.extend_from_slice(&self.read_word(word_addr)?.to_le_bytes());
+            word_addr += 8;
+        }
+        Ok(bytes[addr - aligned_addr..addr - aligned_addr + len].to_vec())
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        Ok(ptrace::read(self.tid(), addr as ptrace::AddressType)? as u64)
+    }
+
+    fn auxv(&self) -> Option<Vec<(u64, u64)>> {
+        let bytes = std::fs::read(format!("/proc/{}/auxv", self.pid())).ok()?;
+        Some(parse_auxv(&bytes))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ThreadState {
+    Running,
+    Stopped,
+}
+
+/// A thread of the inferior. Threads are numbered in the order we first saw them, starting at 1.
+pub struct Thread {
+    pub num: usize,
+    pub tid: Pid,
+    state: ThreadState,
+    /// We sent it a SIGSTOP that it hasn't reported yet. New threads start out with one too
+    sigstop_pending: bool,
+    /// A signal it stopped with while we were stopping every thread, to deliver when it resumes
+    pending_signal: Option<Signal>,
+}
+
 pub struct Inferior {
-    child: Child,
+    // None if we attached to a process we didn't start
+    child: Option<Child>,
+    pid: Pid,
+    threads: Vec<Thread>,
+    next_thread_num: usize,
+    /// The selected thread, whose registers we look at and which is single-stepped
+    current: Pid,
+    /// The thread the user was last told about, so switching threads gets announced once
+    reported: Pid,
+    /// Whether every thread was resumed, or only the current one is being single-stepped
+    all_running: bool,
+    /// The hardware watchpoints, which each new thread has to be given as well
+    watch_slots: Vec<(usize, usize, WatchKind)>,
+    attached: bool,
+    fork_options: ForkOptions,
+    /// Processes from forks that we are keeping stopped because detach-on-fork is off
+    held: Vec<Pid>,
+    /// New processes whose first stop arrived before the fork event that created them
+    early_stops: Vec<Pid>,
+    /// Our traps were taken out of memory shared with a vfork child, and go back in once the
+    /// child has exec'd or exited
+    vfork_pending: bool,
+    /// How each signal is handled, where it differs from `SignalAction::default_for`
+    signal_actions: HashMap<Signal, SignalAction>,
+    /// The trap in the dynamic linker that fires whenever libraries are loaded or unloaded
+    solib_breakpoint: Option<usize>,
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>) -> Option<Inferior> {
+    /// With `no_randomization` set, the program is loaded at the same addresses every run.
+    pub fn new(target: &str, args: &Vec<String>, no_randomization: bool) -> Option<Inferior> {
         // TODO: implement me!
         use std::process::Command;
         let mut child_cmd = Command::new( target );
         let child_cmd = child_cmd.args( args );
         unsafe {
             use std::os::unix::process::CommandExt;
-            child_cmd.pre_exec( child_traceme );
+            child_cmd.pre_exec( move || {
+                if no_randomization {
+                    disable_randomization();
+                }
+                child_traceme()
+            } );
         }
         
         let child_ps: Child = child_cmd
             .spawn()
             .expect("Failed to spawn a child!");
         
-        let ret_obj: Inferior = Inferior { child: child_ps };
-        loop {
-            match ret_obj.wait(None) {
-                Ok(_i) => {
-                    return Some(ret_obj);
-                },
-                Err(_) => break,
+        let pid = Pid::from_raw(child_ps.id() as i32);
+        let mut ret_obj: Inferior = Inferior::with_process(Some(child_ps), pid);
+        match ret_obj.wait(None, &HashMap::new()) {
+            Ok(_i) => {
+                ptrace::setoptions(pid, trace_options()).ok()?;
+                Some(ret_obj)
             }
+            Err(_) => None,
         }
-        
-        None
     }
 
-    pub fn wake_up(&mut self, rs_map: &HashMap<usize, Restorepoint>) 
-        -> Result<Status, nix::Error> {
-        // In milestone1, you just return Ok(status)
-        ptrace::cont(self.pid(), None)?;
-        let status = self.wait(None)?;
+    fn with_process(child: Option<Child>, pid: Pid) -> Inferior {
+        let attached = child.is_none();
+        Inferior {
+            child,
+            pid,
+            threads: vec![Thread {
+                num: 1,
+                tid: pid,
+                state: ThreadState::Running,
+                sigstop_pending: false,
+                pending_signal: None,
+            }],
+            next_thread_num: 2,
+            current: pid,
+            reported: pid,
+            all_running: true,
+            watch_slots: Vec::new(),
+            attached,
+            fork_options: ForkOptions::default(),
+            held: Vec::new(),
+            early_stops: Vec::new(),
+            vfork_pending: false,
+            signal_actions: HashMap::new(),
+            solib_breakpoint: None,
+        }
+    }
 
-        // For milestone6
-        if let Status::Stopped(signal, rip) = status {
-            if !rs_map.is_empty() { // probabily stopped by SIGINT
-                if let Signal::SIGTRAP = signal {
-                    if let Some(i) = rs_map.get( &(rip - 1) ) {
-                        println!("breakpoint at {:#x}", rip-1);
-                        
-                        self.write_byte(i.addr, i.orig_byte)?;
-                    } 
-                }
+    /// Attaches to the running process `pid` with PTRACE_ATTACH and waits for it to stop. Every
+    /// thread listed in /proc/<pid>/task is attached to as well.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        ptrace::attach(pid)?;
+        let mut inferior = Inferior::with_process(None, pid);
+        waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        inferior.threads[0].state = ThreadState::Stopped;
+        ptrace::setoptions(pid, trace_options())?;
+        let mut tids: Vec<i32> = match std::fs::read_dir(format!("/proc/{}/task", pid)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        tids.sort();
+        for tid in tids.into_iter().map(Pid::from_raw).filter(|&tid| tid != pid) {
+            // It may have exited since we listed it
+            if ptrace::attach(tid).is_err() {
+                continue;
+            }
+            waitpid(tid, Some(WaitPidFlag::__WALL))?;
+            ptrace::setoptions(tid, trace_options())?;
+            inferior.add_thread(tid, ThreadState::Stopped, false);
+        }
+        Ok(inferior)
+    }
+
+    /// Removes every breakpoint and watchpoint from the inferior and lets it run on untraced.
+    pub fn detach(&mut self, rs_map: &mut HashMap<usize, Restorepoint>) -> Result<(), nix::Error> {
+        let addrs: Vec<usize> = rs_map.keys().cloned().collect();
+        for addr in addrs {
+            self.remove_breakpoint(addr, rs_map)?;
+        }
+        self.set_watchpoints(&[])?;
+        for thread in &self.threads {
+            ptrace::detach(thread.tid, thread.pending_signal)?;
+        }
+        for pid in self.held.drain(..) {
+            ptrace::detach(pid, None)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if we attached to this process rather than starting it.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    pub fn set_fork_options(&mut self, options: ForkOptions) {
+        self.fork_options = options;
+    }
+
+    pub fn set_signal_actions(&mut self, actions: HashMap<Signal, SignalAction>) {
+        self.signal_actions = actions;
+    }
+
+    /// Tells us which of the traps in the restore map is the dynamic linker's, so that hitting
+    /// it is reported as `Status::LibrariesChanged` rather than as a breakpoint.
+    pub fn set_solib_breakpoint(&mut self, addr: Option<usize>) {
+        self.solib_breakpoint = addr;
+    }
+
+    fn signal_action(&self, signal: Signal) -> SignalAction {
+        match self.signal_actions.get(&signal) {
+            Some(action) => *action,
+            None => SignalAction::default_for(signal),
+        }
+    }
+
+    pub fn wake_up(
+        &mut self,
+        rs_map: &HashMap<usize, Restorepoint>,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Status, nix::Error> {
+        loop {
+            // In milestone1, you just return Ok(status)
+            let status = self.cont(rs_map)?;
+
+            // For milestone6
+            if let Some(status) = self.check_breakpoint(status, rs_map, on_breakpoint)? {
+                return Ok(status);
             }
         }
-        
-        Ok(status)
     }
 
     pub fn kill_myself(&mut self) -> Result<(), std::io::Error> {
         // For milestone2
-        self.child.kill()
+        for pid in self.held.drain(..) {
+            let _ = signal::kill(pid, Signal::SIGKILL);
+        }
+        let result = match self.child.as_mut() {
+            Some(child) => child.kill(),
+            None => signal::kill(self.pid(), Signal::SIGKILL).map_err(|err| match err {
+                nix::Error::Sys(errno) => std::io::Error::from_raw_os_error(errno as i32),
+                err => std::io::Error::new(std::io::ErrorKind::Other, err),
+            }),
+        };
+        match result {
+            // It has already exited, which is what we wanted
+            Err(err) if err.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            result => result,
+        }
     }
     
     pub fn try_wait(&mut self) -> bool {
@@ -101,37 +723,237 @@ impl Inferior {
         // Attempts to collect the exit status of the child.
         // We got an Err(_e) because the child has already
         //   exited, so try_wait() couldn't find that process.
-        if let Err( _e ) = self.child.try_wait() {
-            return true;
+        match self.child.as_mut() {
+            Some(child) => {
+                if let Err( _e ) = child.try_wait() {
+                    return true;
+                }
+                false
+            }
+            // We're not its parent, so just check whether the process is still there
+            None => signal::kill(self.pid(), None).is_err(),
         }
-        false
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        // For milestone3
-        
-        // Get regs value
-        let regs_st = ptrace::getregs( self.pid() ).unwrap();
-        let mut rip: usize = regs_st.rip as usize;
-        let mut rbp: usize = regs_st.rbp as usize;
-        //println!("%rip = {:#x}", rip);
+    fn set_rip(&self, rip: usize) -> Result<(), nix::Error> {
+        let mut regs = self.get_regs()?;
+        regs.rip = rip as u64;
+        ptrace::setregs(self.tid(), regs)
+    }
+
+    /// If the inferior just trapped on one of the breakpoints in `rs_map`, rewinds %rip onto the
+    /// breakpoint and returns the updated status, or None if `on_breakpoint` says to carry on.
+    /// The trap itself stays in place so the breakpoint fires again next time; `cont` and
+    /// `step_instruction` know how to get past it.
+    fn check_breakpoint(
+        &mut self,
+        status: Status,
+        rs_map: &HashMap<usize, Restorepoint>,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Option<Status>, nix::Error> {
+        if let Status::Stopped(Signal::SIGTRAP, rip) = status {
+            if let Some(i) = rip.checked_sub(1).and_then(|addr| rs_map.get(&addr)) {
+                self.set_rip(i.addr)?;
+                if self.solib_breakpoint == Some(i.addr) {
+                    return Ok(Some(Status::LibrariesChanged));
+                }
+                if !on_breakpoint(self, i.addr) {
+                    return Ok(None);
+                }
+                self.announce_thread();
+                println!("breakpoint at {:#x}", i.addr);
+                return Ok(Some(Status::Stopped(Signal::SIGTRAP, i.addr)));
+            }
+        }
+        self.announce_thread();
+        Ok(Some(status))
+    }
 
-        // Print filename and linenumber
+    /// Restarts the inferior with PTRACE_CONT and waits for it to stop. If it is sitting on a
+    /// breakpoint, that instruction is single-stepped with the original byte first, so the trap
+    /// doesn't fire again straight away.
+    fn cont(&mut self, rs_map: &HashMap<usize, Restorepoint>) -> Result<Status, nix::Error> {
+        let rip = self.get_regs()?.rip as usize;
+        if rs_map.contains_key(&rip) {
+            match self.step_instruction(rs_map)? {
+                Status::Stopped(Signal::SIGTRAP, _) => {}
+                other => return Ok(other),
+            }
+        }
+        self.resume_all()?;
+        self.wait(None, rs_map)
+    }
+
+    /// Executes exactly one machine instruction. If %rip sits on an armed breakpoint, the
+    /// original byte is put back for the duration of the step and the trap reinserted afterwards.
+    pub fn step_instruction(
+        &mut self,
+        rs_map: &HashMap<usize, Restorepoint>,
+    ) -> Result<Status, nix::Error> {
+        let rip = self.get_regs()?.rip as usize;
+        let armed = match rs_map.get(&rip) {
+            Some(i) => self.write_byte(rip, i.orig_byte)? == 0xcc,
+            None => false,
+        };
+        self.step_thread()?;
+        let status = self.wait(None, rs_map)?;
+        if armed {
+            if let Status::Stopped(..) | Status::Forked(..) = status {
+                self.write_byte(rip, 0xcc)?;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Lets the inferior run until it reaches `addr` with %rsp at or above `cfa`, so that a
+    /// deeper recursive call passing through `addr` doesn't count. A temporary trap is placed at
+    /// `addr` and taken out again before returning.
+    fn run_until(
+        &mut self,
+        addr: usize,
+        cfa: usize,
+        rs_map: &HashMap<usize, Restorepoint>,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Status, nix::Error> {
+        let saved_byte = self.write_byte(addr, 0xcc)?;
+        let orig_byte = rs_map.get(&addr).map_or(saved_byte, |i| i.orig_byte);
+        let tid = self.current;
+        let status = loop {
+            match self.cont(rs_map)? {
+                Status::Stopped(Signal::SIGTRAP, rip) if rip.wrapping_sub(1) == addr => {
+                    self.set_rip(addr)?;
+                    if self.current == tid && self.get_regs()?.rsp as usize >= cfa {
+                        break Status::Stopped(Signal::SIGTRAP, addr);
+                    }
+                    // A deeper frame or another thread got here first: step past the trap and
+                    // keep going
+                    self.write_byte(addr, orig_byte)?;
+                    self.step_thread()?;
+                    match self.wait(None, rs_map)? {
+                        Status::Stopped(..) => {
+                            self.write_byte(addr, 0xcc)?;
+                        }
+                        other => break other,
+                    }
+                    self.current = tid;
+                }
+                Status::Stopped(Signal::SIGTRAP, rip)
+                    if rs_map.contains_key(&rip.wrapping_sub(1)) =>
+                {
+                    let status = Status::Stopped(Signal::SIGTRAP, rip);
+                    match self.check_breakpoint(status, rs_map, on_breakpoint)? {
+                        // The new libraries are picked up once we stop
+                        Some(Status::LibrariesChanged) | None => {}
+                        Some(status) => {
+                            self.write_byte(addr, saved_byte)?;
+                            return Ok(status);
+                        }
+                    }
+                }
+                other => break other,
+            }
+        };
+        // After an exec the trap went with the old program
+        if let Status::Stopped(..) | Status::Forked(..) = status {
+            self.write_byte(addr, saved_byte)?;
+        }
+        Ok(status)
+    }
+
+    /// Works out where the current function will return to, along with the value %rsp will
+    /// have once it has.
+    fn return_address(&self, debug_data: &DwarfData) -> Result<(usize, usize), nix::Error> {
+        let cfa = self.get_cfa(debug_data)?;
+        Ok((self.read_word(cfa - 8)? as usize, cfa))
+    }
+
+    /// Source-level step: runs until the inferior reaches the start of a different line. With
+    /// `step_into` set, calls into functions that have debug info are followed (`step`);
+    /// otherwise they are run to completion (`next`).
+    pub fn step_line(
+        &mut self,
+        debug_data: &DwarfData,
+        rs_map: &HashMap<usize, Restorepoint>,
+        step_into: bool,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Status, nix::Error> {
+        let mut start_line = debug_data.get_line_from_addr(self.get_regs()?.rip as usize);
         loop {
-            let line = DwarfData::get_line_from_addr(debug_data, rip).unwrap();
-            let func_name = DwarfData::get_function_from_addr(debug_data, rip).unwrap();
-            println!("{} ({})", func_name, line);
-        
-            if func_name == "main" {
-                break;
+            let regs = self.get_regs()?;
+            let (old_rip, old_rsp) = (regs.rip as usize, regs.rsp as usize);
+            let mut rip = match self.step_instruction(rs_map)? {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                other => return Ok(other),
+            };
+
+            if let Some(ret_addr) = self.called_from(old_rip, old_rsp)? {
+                if step_into && debug_data.get_function_for_addr(rip).is_some() {
+                    start_line = debug_data.get_line_from_addr(rip);
+                    continue;
+                }
+                rip = match self.run_until(ret_addr, old_rsp, rs_map, on_breakpoint)? {
+                    Status::Stopped(Signal::SIGTRAP, addr) if addr == ret_addr => addr,
+                    other => return Ok(other),
+                };
+            }
+
+            match debug_data.get_line_from_addr(rip) {
+                // Not our code any more (e.g. main returned into libc), so just let it run
+                None => return self.wake_up(rs_map, on_breakpoint),
+                Some(line) => {
+                    let same_line = match &start_line {
+                        Some(start) => start.file == line.file && start.number == line.number,
+                        None => false,
+                    };
+                    if !same_line && debug_data.is_line_start(rip) {
+                        return Ok(Status::Stopped(Signal::SIGTRAP, rip));
+                    }
+                }
             }
-            rbp = rbp + 8;
-            rip = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
-            rbp = rbp - 8;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
         }
-        
-        Ok(())
+    }
+
+    /// If the instruction just stepped from `old_rip` (with %rsp at `old_rsp`) was a call,
+    /// returns the address it will return to.
+    fn called_from(&self, old_rip: usize, old_rsp: usize) -> Result<Option<usize>, nix::Error> {
+        // A call pushes the address of the following instruction (at most 15 bytes on)
+        let rsp = self.get_regs()?.rsp as usize;
+        if rsp == old_rsp - 8 {
+            let ret_addr = self.read_word(rsp)? as usize;
+            if ret_addr > old_rip && ret_addr <= old_rip + 15 {
+                return Ok(Some(ret_addr));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Executes one machine instruction like `step_instruction`, except that a call is run to
+    /// completion (`nexti`).
+    pub fn next_instruction(
+        &mut self,
+        rs_map: &HashMap<usize, Restorepoint>,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Status, nix::Error> {
+        let regs = self.get_regs()?;
+        let (old_rip, old_rsp) = (regs.rip as usize, regs.rsp as usize);
+        match self.step_instruction(rs_map)? {
+            Status::Stopped(Signal::SIGTRAP, rip) => match self.called_from(old_rip, old_rsp)? {
+                Some(ret_addr) => self.run_until(ret_addr, old_rsp, rs_map, on_breakpoint),
+                None => Ok(Status::Stopped(Signal::SIGTRAP, rip)),
+            },
+            other => Ok(other),
+        }
+    }
+
+    /// Runs until the current function returns to its caller.
+    pub fn finish(
+        &mut self,
+        debug_data: &DwarfData,
+        rs_map: &HashMap<usize, Restorepoint>,
+        on_breakpoint: &mut BreakpointFilter,
+    ) -> Result<Status, nix::Error> {
+        let (ret_addr, cfa) = self.return_address(debug_data)?;
+        self.run_until(ret_addr, cfa, rs_map, on_breakpoint)
     }
 
     fn align_addr_to_word(&self, addr: usize) -> usize {
@@ -148,22 +970,25 @@ impl Inferior {
         let val = 0xcc; // INT instruction in x64
         for i in br_list {
             let addr: usize = *i;
+            if rs_map.contains_key(&addr) {
+                continue;
+            }
 
             let aligned_addr = self.align_addr_to_word(addr);
             let byte_offset = addr - aligned_addr;
-            let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
+            let word = ptrace::read(self.tid(), aligned_addr as ptrace::AddressType)? as u64;
             
-            let orig_byte = (word >> 8 * byte_offset) & 0xff;
+            let orig_byte = (word >> (8 * byte_offset)) & 0xff;
             let restore_st: Restorepoint = Restorepoint { 
-                addr: addr,
+                addr,
                 orig_byte: orig_byte as u8,
             };
             rs_map.insert(addr, restore_st);
             
-            let masked_word = word & !(0xff << 8 * byte_offset);
-            let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
+            let masked_word = word & !(0xff << (8 * byte_offset));
+            let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
             ptrace::write(
-                self.pid(),
+                self.tid(),
                 aligned_addr as ptrace::AddressType,
                 updated_word as *mut std::ffi::c_void,
                 )?;
@@ -171,38 +996,502 @@ impl Inferior {
         Ok(0)
     }
 
+    /// Takes the trap at `addr` out of the inferior, putting the original byte back.
+    pub fn remove_breakpoint(
+        &mut self,
+        addr: usize,
+        rs_map: &mut HashMap<usize, Restorepoint>,
+    ) -> Result<(), nix::Error> {
+        if let Some(i) = rs_map.remove(&addr) {
+            self.write_byte(i.addr, i.orig_byte)?;
+        }
+        Ok(())
+    }
+
     fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         // for milestone6
-        let aligned_addr = self.align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+        let orig_byte = self.read_memory(addr, 1)?[0];
+        self.write_memory(addr, &[val])?;
+        Ok(orig_byte)
+    }
+
+    /// Writes `bytes` into the inferior's memory starting at `addr`. ptrace only writes whole
+    /// words, so each word is read, patched and written back.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut word_addr = self.align_addr_to_word(addr);
+        while word_addr < addr + bytes.len() {
+            let mut word = self.read_word(word_addr)?.to_le_bytes();
+            for (i, byte) in word.iter_mut().enumerate() {
+                if word_addr + i >= addr && word_addr + i < addr + bytes.len() {
+                    *byte = bytes[word_addr + i - addr];
+                }
+            }
+            ptrace::write(
+                self.tid(),
+                word_addr as ptrace::AddressType,
+                u64::from_le_bytes(word) as *mut std::ffi::c_void,
+            )?;
+            word_addr += 8;
+        }
+        Ok(())
+    }
+
+    /// Programs the debug registers so that each `(addr, len, kind)` slot becomes a hardware
+    /// watchpoint: DR0-DR3 hold the addresses, and DR7 enables them. `len` must be 1, 2, 4 or 8
+    /// and `addr` aligned to it; there are at most four slots.
+    pub fn set_watchpoints(&mut self, slots: &[(usize, usize, WatchKind)]) -> Result<(), nix::Error> {
+        self.watch_slots = slots.to_vec();
+        for tid in self.threads.iter().map(|thread| thread.tid).collect::<Vec<_>>() {
+            self.program_debugregs(tid)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the watchpoint slots into the debug registers of thread `tid`, which every thread
+    /// has its own copy of.
+    fn program_debugregs(&self, tid: Pid) -> Result<(), nix::Error> {
+        let mut dr7 = 0u64;
+        for (i, (addr, len, kind)) in self.watch_slots.iter().enumerate().take(4) {
+            self.poke_debugreg(tid, i, *addr as u64)?;
+            let rw: u64 = match kind {
+                WatchKind::Write => 0b01,
+                WatchKind::Read | WatchKind::Access => 0b11,
+            };
+            let len_bits: u64 = match len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                _ => 0b11,
+            };
+            dr7 |= 1 << (2 * i);
+            dr7 |= (rw | (len_bits << 2)) << (16 + 4 * i);
+        }
+        self.poke_debugreg(tid, 7, dr7)
+    }
+
+    /// Returns the watchpoint slots (0-3) that have triggered, according to DR6, and clears them
+    /// since the CPU never does.
+    pub fn take_watchpoint_hits(&mut self) -> Result<Vec<usize>, nix::Error> {
+        let dr6 = self.peek_debugreg(6)?;
+        if dr6 & 0xf != 0 {
+            self.poke_debugreg(self.tid(), 6, dr6 & !0xf)?;
+        }
+        Ok((0..4).filter(|i| dr6 & (1 << i) != 0).collect())
+    }
+
+    fn peek_debugreg(&self, index: usize) -> Result<u64, nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * 8;
+        let ret = unsafe {
+            nix::errno::Errno::clear();
+            libc::ptrace(libc::PTRACE_PEEKUSER, self.tid().as_raw(), offset, 0)
+        };
+        if ret == -1 && nix::errno::errno() != 0 {
+            return Err(nix::Error::last());
+        }
+        Ok(ret as u64)
+    }
+
+    fn poke_debugreg(&self, tid: Pid, index: usize, value: u64) -> Result<(), nix::Error> {
+        let offset = DEBUGREG_OFFSET + index * 8;
+        let ret = unsafe { libc::ptrace(libc::PTRACE_POKEUSER, tid.as_raw(), offset, value) };
+        if ret == -1 {
+            return Err(nix::Error::last());
+        }
+        Ok(())
+    }
+
+    /// Overwrites the inferior's general purpose registers, for `set $reg = value`.
+    pub fn set_regs(&mut self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.tid(), regs)
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Returns the thread id of the selected thread, which ptrace requests go to.
+    pub fn tid(&self) -> Pid {
+        self.current
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    pub fn threads(&self) -> &[Thread] {
+        &self.threads
+    }
+
+    /// Returns the number of the selected thread.
+    pub fn current_thread(&self) -> usize {
+        self.threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .map_or(1, |thread| thread.num)
+    }
+
+    /// Makes thread `num` the one whose registers we look at and which steps. Returns false if
+    /// there is no such thread.
+    pub fn select_thread(&mut self, num: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.num == num) {
+            Some(thread) => {
+                self.current = thread.tid;
+                self.reported = thread.tid;
+                true
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+            None => false,
+        }
+    }
+
+    /// Returns the registers of thread `tid`, which needn't be the selected one.
+    pub fn get_thread_regs(&self, tid: Pid) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(tid)
+    }
+
+    /// Tells the user when the thread that stopped isn't the one they were last looking at.
+    pub fn announce_thread(&mut self) {
+        if self.current != self.reported {
+            println!("[Switching to thread {} (LWP {})]", self.current_thread(), self.current);
+            self.reported = self.current;
+        }
+    }
+
+    fn add_thread(&mut self, tid: Pid, state: ThreadState, sigstop_pending: bool) {
+        if self.threads.iter().any(|thread| thread.tid == tid) {
+            return;
+        }
+        let num = self.next_thread_num;
+        self.next_thread_num += 1;
+        println!("[New thread {} (LWP {})]", num, tid);
+        self.threads.push(Thread {
+            num,
+            tid,
+            state,
+            sigstop_pending,
+            pending_signal: None,
+        });
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            println!("[Thread {} (LWP {}) exited]", thread.num, tid);
+        }
+    }
+
+    fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|thread| thread.tid == tid)
+    }
+
+    /// Resumes every stopped thread, delivering any signal one of them was holding.
+    fn resume_all(&mut self) -> Result<(), nix::Error> {
+        self.all_running = true;
+        for thread in self.threads.iter_mut().filter(|thread| thread.state == ThreadState::Stopped) {
+            ptrace::cont(thread.tid, thread.pending_signal.take())?;
+            thread.state = ThreadState::Running;
+        }
+        Ok(())
+    }
+
+    /// Single-steps the selected thread while the others stay stopped. A signal it is holding is
+    /// delivered, so the step may land in the program's handler for it.
+    fn step_thread(&mut self) -> Result<(), nix::Error> {
+        self.all_running = false;
+        let tid = self.current;
+        let signal = self.thread_mut(tid).and_then(|thread| thread.pending_signal.take());
+        ptrace::step(tid, signal)?;
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.state = ThreadState::Running;
+        }
+        Ok(())
+    }
+
+    /// Lets thread `tid` carry on after a ptrace event or a signal that doesn't stop the
+    /// program, the same way it was resumed before, delivering `signal` if there is one.
+    fn resume_thread(&mut self, tid: Pid, signal: Option<Signal>) -> Result<(), nix::Error> {
+        if self.all_running {
+            ptrace::cont(tid, signal)
+        } else {
+            ptrace::step(tid, signal)
+        }
+    }
+
+    /// Deals with the current thread having forked `new_pid`: carries on debugging the parent or,
+    /// with `follow_child`, the child, and detaches from the other one, or holds it stopped if
+    /// detach-on-fork is off.
+    fn follow_fork(
+        &mut self,
+        kind: CatchKind,
+        new_pid: Pid,
+        follow_child: bool,
+        rs_map: &HashMap<usize, Restorepoint>,
+    ) -> Result<(), nix::Error> {
+        // The new process starts out stopped, and may have told us so already
+        match self.early_stops.iter().position(|&pid| pid == new_pid) {
+            Some(index) => {
+                self.early_stops.remove(index);
+            }
+            None => {
+                waitpid(new_pid, Some(WaitPidFlag::__WALL))?;
+            }
+        }
+        let what = if kind == CatchKind::Vfork { "vfork" } else { "fork" };
+        let released: Vec<Pid> = if follow_child {
+            println!("[Attaching after process {} {} to child process {}]", self.pid, what, new_pid);
+            let parent_threads = self.threads.iter().map(|thread| thread.tid).collect();
+            self.pid = new_pid;
+            self.child = None;
+            self.attached = false;
+            self.threads = vec![Thread {
+                num: 1,
+                tid: new_pid,
+                state: ThreadState::Stopped,
+                sigstop_pending: false,
+                pending_signal: None,
+            }];
+            self.next_thread_num = 2;
+            self.current = new_pid;
+            self.reported = new_pid;
+            self.program_debugregs(new_pid)?;
+            parent_threads
+        } else {
+            vec![new_pid]
+        };
+        // The process we let go has our traps in it too. A vfork child shares our memory, so
+        // they come out of the parent as well until the child execs or exits
+        write_traps(released[0], rs_map, false)?;
+        if kind == CatchKind::Vfork && !follow_child {
+            self.vfork_pending = true;
+        }
+        let side = if follow_child { "parent" } else { "child" };
+        if self.fork_options.detach_on_fork {
+            println!("[Detaching after {} from {} process {}]", what, side, released[0]);
+            for tid in released {
+                ptrace::detach(tid, None)?;
+            }
+        } else {
+            println!("[Holding {} process {} stopped after {}]", side, released[0], what);
+            self.held.extend(released);
+        }
+        Ok(())
+    }
+
+    /// Stops every thread that is still running, once one of them has stopped, so the user sees
+    /// the whole program standing still. Returns the process's status if it exited meanwhile.
+    fn stop_all(&mut self, rs_map: &HashMap<usize, Restorepoint>) -> Result<Option<Status>, nix::Error> {
+        for thread in self.threads.iter_mut() {
+            if thread.state == ThreadState::Running && !thread.sigstop_pending {
+                // It may be exiting already, which we'll hear about below
+                unsafe {
+                    libc::syscall(libc::SYS_tgkill, self.pid.as_raw(), thread.tid.as_raw(), libc::SIGSTOP);
+                }
+                thread.sigstop_pending = true;
+            }
+        }
+        while self.threads.iter().any(|thread| thread.state == ThreadState::Running) {
+            match waitpid(None, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => {
+                    return Ok(Some(Status::Exited(exit_code)));
+                }
+                WaitStatus::Signaled(tid, signal, _) if tid == self.pid => {
+                    return Ok(Some(Status::Signaled(signal)));
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, ..) => self.remove_thread(tid),
+                WaitStatus::PtraceEvent(tid, _, libc::PTRACE_EVENT_CLONE) => {
+                    let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    self.add_thread(new_tid, ThreadState::Running, true);
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.state = ThreadState::Stopped;
+                    }
+                }
+                WaitStatus::PtraceEvent(tid, _, event)
+                    if event == libc::PTRACE_EVENT_FORK || event == libc::PTRACE_EVENT_VFORK =>
+                {
+                    // Another thread forked while we were stopping it. We are busy reporting
+                    // something else, so this fork isn't caught or followed
+                    let new_pid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    let kind = if event == libc::PTRACE_EVENT_FORK { CatchKind::Fork } else { CatchKind::Vfork };
+                    self.follow_fork(kind, new_pid, false, rs_map)?;
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.state = ThreadState::Stopped;
+                    }
+                }
+                WaitStatus::Stopped(tid, signal) => {
+                    if self.thread_mut(tid).is_none() && thread_group(tid) != Some(self.pid) {
+                        self.early_stops.push(tid);
+                        continue;
+                    }
+                    if self.thread_mut(tid).is_none() {
+                        // A new thread's first stop, before we heard about the clone
+                        self.add_thread(tid, ThreadState::Stopped, false);
+                        self.program_debugregs(tid)?;
+                        continue;
+                    }
+                    // Only our own traps, and only while they are in memory: an int3 the program
+                    // was built with must not be re-executed forever
+                    let rip = ptrace::getregs(tid)?.rip as usize;
+                    let rewind = signal == Signal::SIGTRAP
+                        && rip > 0
+                        && rs_map.contains_key(&(rip - 1))
+                        && self.read_memory(rip - 1, 1).ok() == Some(vec![0xcc]);
+                    let thread = self.thread_mut(tid).unwrap();
+                    thread.state = ThreadState::Stopped;
+                    if signal == Signal::SIGSTOP && thread.sigstop_pending {
+                        // Possibly a new thread's first stop
+                        thread.sigstop_pending = false;
+                        self.program_debugregs(tid)?;
+                    } else if rewind {
+                        // It hit a breakpoint too. Back it up so the trap fires again when it
+                        // resumes, rather than reporting two stops at once
+                        let mut regs = ptrace::getregs(tid)?;
+                        regs.rip -= 1;
+                        ptrace::setregs(tid, regs)?;
+                    } else if signal != Signal::SIGTRAP && self.signal_action(signal).pass {
+                        self.thread_mut(tid).unwrap().pending_signal = Some(signal);
+                    }
+                }
+                WaitStatus::PtraceEvent(tid, ..) | WaitStatus::PtraceSyscall(tid) => {
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.state = ThreadState::Stopped;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits until some thread of the inferior stops for a reason worth reporting, then stops
+    /// all the others and makes that thread the selected one. Thread creation and exit are
+    /// dealt with along the way. Returns a Status to indicate the state of the process.
+    pub fn wait(
+        &mut self,
+        options: Option<WaitPidFlag>,
+        rs_map: &HashMap<usize, Restorepoint>,
+    ) -> Result<Status, nix::Error> {
+        let flags = options.unwrap_or_else(WaitPidFlag::empty) | WaitPidFlag::__WALL;
+        loop {
+            match waitpid(None, Some(flags))? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => {
+                    self.threads.clear();
+                    return Ok(Status::Exited(exit_code));
+                }
+                WaitStatus::Signaled(tid, signal, _core_dumped) if tid == self.pid => {
+                    self.threads.clear();
+                    return Ok(Status::Signaled(signal));
+                }
+                WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, ..) => self.remove_thread(tid),
+                WaitStatus::PtraceEvent(tid, _, libc::PTRACE_EVENT_CLONE) => {
+                    let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    self.add_thread(new_tid, ThreadState::Running, true);
+                    self.resume_thread(tid, None)?;
+                }
+                WaitStatus::PtraceEvent(tid, _, event)
+                    if event == libc::PTRACE_EVENT_FORK || event == libc::PTRACE_EVENT_VFORK =>
+                {
+                    let new_pid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    let kind = if event == libc::PTRACE_EVENT_FORK { CatchKind::Fork } else { CatchKind::Vfork };
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.state = ThreadState::Stopped;
+                    }
+                    self.current = tid;
+                    let follow_child = self.fork_options.follow_child;
+                    if follow_child {
+                        // The parent's other threads must be stopped to detach from them
+                        if let Some(status) = self.stop_all(rs_map)? {
+                            return Ok(status);
+                        }
+                    }
+                    self.follow_fork(kind, new_pid, follow_child, rs_map)?;
+                    let caught = match kind {
+                        CatchKind::Fork => self.fork_options.catch_fork,
+                        _ => self.fork_options.catch_vfork,
+                    };
+                    if caught {
+                        if let Some(status) = self.stop_all(rs_map)? {
+                            return Ok(status);
+                        }
+                        let regs = self.get_regs()?;
+                        return Ok(Status::Forked(kind, new_pid, regs.rip as usize));
+                    }
+                    let tid = self.current;
+                    self.thread_mut(tid).unwrap().state = ThreadState::Running;
+                    self.resume_thread(tid, None)?;
+                }
+                WaitStatus::PtraceEvent(tid, _, libc::PTRACE_EVENT_VFORK_DONE) => {
+                    if self.vfork_pending {
+                        write_traps(tid, rs_map, true)?;
+                        self.vfork_pending = false;
+                    }
+                    self.resume_thread(tid, None)?;
+                }
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => {
+                    // Every other thread is gone, and the one that called exec has taken over
+                    // the process's pid
+                    let pid = self.pid;
+                    self.threads.retain(|thread| thread.tid == pid);
+                    if self.threads.is_empty() {
+                        self.add_thread(pid, ThreadState::Stopped, false);
+                    }
+                    let thread = &mut self.threads[0];
+                    thread.state = ThreadState::Stopped;
+                    thread.sigstop_pending = false;
+                    thread.pending_signal = None;
+                    self.current = pid;
+                    self.reported = pid;
+                    self.watch_slots.clear();
+                    self.vfork_pending = false;
+                    let regs = self.get_regs()?;
+                    return Ok(Status::Execed(regs.rip as usize));
+                }
+                WaitStatus::Stopped(tid, Signal::SIGSTOP)
+                    if self.thread_mut(tid).is_none() && thread_group(tid) != Some(self.pid) =>
+                {
+                    // A forked process, before we have heard about the fork
+                    self.early_stops.push(tid);
+                }
+                WaitStatus::Stopped(tid, Signal::SIGSTOP)
+                    if self.thread_mut(tid).map_or(true, |thread| thread.sigstop_pending) =>
+                {
+                    // A new thread starting, or a leftover stop request from stop_all
+                    self.add_thread(tid, ThreadState::Running, false);
+                    let thread = self.thread_mut(tid).unwrap();
+                    thread.sigstop_pending = false;
+                    self.program_debugregs(tid)?;
+                    if self.all_running {
+                        ptrace::cont(tid, None)?;
+                    } else {
+                        self.thread_mut(tid).unwrap().state = ThreadState::Stopped;
+                    }
+                }
+                WaitStatus::Stopped(tid, signal) => {
+                    let action = self.signal_action(signal);
+                    let thread = match self.thread_mut(tid) {
+                        Some(thread) => thread,
+                        // Left over from an earlier inferior
+                        None => continue,
+                    };
+                    // Our own traps always stop, whatever `handle` says about SIGTRAP
+                    let pass = if action.pass && signal != Signal::SIGTRAP { Some(signal) } else { None };
+                    if !action.stop && signal != Signal::SIGTRAP {
+                        if action.print {
+                            println!("\nChild received signal: {}, {}.", signal, signal_description(signal));
+                        }
+                        self.resume_thread(tid, pass)?;
+                        continue;
+                    }
+                    // It gets the signal when it is next resumed
+                    thread.pending_signal = pass;
+                    thread.state = ThreadState::Stopped;
+                    self.current = tid;
+                    if let Some(status) = self.stop_all(rs_map)? {
+                        return Ok(status);
+                    }
+                    let regs = ptrace::getregs(tid)?;
+                    return Ok(Status::Stopped(signal, regs.rip as usize));
+                }
+                WaitStatus::PtraceEvent(tid, ..) | WaitStatus::PtraceSyscall(tid) => {
+                    self.resume_thread(tid, None)?;
+                }
+                WaitStatus::Continued(_) | WaitStatus::StillAlive => {}
+            }
+        }
     }
 }
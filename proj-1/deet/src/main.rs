@@ -3,22 +3,49 @@ mod debugger_command;
 mod inferior;
 mod dwarf_data;     // for milestone3
 mod gimli_wrapper;  // for milestone3
+mod expression;
+mod core_file;
+mod disassembler;
+mod registers;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
+fn usage(program: &str) -> ! {
+    println!("Usage: {} <target program> [core file]", program);
+    println!("       {} -p <pid>", program);
+    std::process::exit(1);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    // With -p, debug whatever program the running process was started from
+    let attach_pid = match (args.len(), args.get(1).map(|arg| arg.as_str())) {
+        (3, Some("-p")) => Some(args[2].parse::<usize>().unwrap_or_else(|_| usage(&args[0]))),
+        (2, _) | (3, _) => None,
+        _ => usage(&args[0]),
+    };
+    let target = match attach_pid {
+        Some(pid) => match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().to_string(),
+            Err(err) => {
+                println!("Could not find process {}: {}", pid, err);
+                std::process::exit(1);
+            }
+        },
+        None => args[1].clone(),
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if let Some(pid) = attach_pid {
+        debugger.attach(pid);
+    } else if let Some(core) = args.get(2) {
+        debugger.load_core(core);
+    }
+    debugger.run();
 }
@@ -0,0 +1,386 @@
+//! Register names and formatting for `info registers`, `info all-registers`, and `$reg` in
+//! expressions.
+
+use std::convert::TryInto;
+
+/// The general purpose registers, in the order gdb lists them.
+pub const GENERAL_REGISTERS: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+/// The base addresses of the fs and gs segments, listed after the segment registers.
+pub const BASE_REGISTERS: [&str; 2] = ["fs_base", "gs_base"];
+
+// The 32, 16 and 8 bit names for the low parts of rax..r15, indexed like `SUBREGISTER_BASES`
+const SUBREGISTER_BASES: [&str; 16] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGISTERS32: [&str; 16] = [
+    "eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGISTERS16: [&str; 16] = [
+    "ax", "bx", "cx", "dx", "si", "di", "bp", "sp", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGISTERS8: [&str; 16] = [
+    "al", "bl", "cl", "dl", "sil", "dil", "bpl", "spl", "r8l", "r9l", "r10l", "r11l", "r12l",
+    "r13l", "r14l", "r15l",
+];
+const REGISTERS8_HIGH: [&str; 4] = ["ah", "bh", "ch", "dh"];
+
+const EFLAGS: [(u32, &str); 15] = [
+    (0, "CF"), (2, "PF"), (4, "AF"), (6, "ZF"), (7, "SF"), (8, "TF"), (9, "IF"), (10, "DF"),
+    (11, "OF"), (14, "NT"), (16, "RF"), (17, "VM"), (18, "AC"), (19, "VIF"), (21, "ID"),
+];
+
+const MXCSR_FLAGS: [(u32, &str); 14] = [
+    (0, "IE"), (1, "DE"), (2, "ZE"), (3, "OE"), (4, "UE"), (5, "PE"), (6, "DAZ"), (7, "IM"),
+    (8, "DM"), (9, "ZM"), (10, "OM"), (11, "UM"), (12, "PM"), (15, "FZ"),
+];
+
+// Offsets into the FXSAVE area (libc's user_fpregs_struct)
+const FX_CWD: usize = 0;
+const FX_SWD: usize = 2;
+const FX_FTW: usize = 4;
+const FX_FOP: usize = 6;
+const FX_RIP: usize = 8;
+const FX_RDP: usize = 16;
+const FX_MXCSR: usize = 24;
+const FX_ST: usize = 32;
+const FX_XMM: usize = 160;
+
+/// Offset of the upper halves of the ymm registers in the XSAVE area (PTRACE_GETREGSET with
+/// NT_X86_XSTATE), and of the bitmap saying which parts of the area are in use.
+const XSAVE_YMM_HIGH: usize = 576;
+const XSAVE_XSTATE_BV: usize = 512;
+const XSTATE_AVX: u64 = 4;
+
+/// The floating point and vector registers: the 512 byte FXSAVE area, and the upper halves of
+/// ymm0-15 if the CPU has AVX.
+pub struct FpRegisters {
+    pub fxsave: Vec<u8>,
+    pub ymm_high: Option<Vec<u8>>,
+}
+
+impl FpRegisters {
+    /// Builds the registers from an FXSAVE area and, if there is one, an XSAVE area.
+    pub fn new(fxsave: Vec<u8>, xsave: Option<&[u8]>) -> FpRegisters {
+        let ymm_high = xsave.and_then(|xsave| {
+            let bytes = xsave.get(XSAVE_YMM_HIGH..XSAVE_YMM_HIGH + 256)?;
+            let xstate_bv = u64::from_le_bytes(
+                xsave.get(XSAVE_XSTATE_BV..XSAVE_XSTATE_BV + 8)?.try_into().ok()?,
+            );
+            // Components in their initial state aren't saved and read as zero
+            if xstate_bv & XSTATE_AVX != 0 {
+                Some(bytes.to_vec())
+            } else {
+                Some(vec![0; 256])
+            }
+        });
+        FpRegisters { fxsave, ymm_high }
+    }
+
+    fn u16_at(&self, offset: usize) -> u64 {
+        u16::from_le_bytes(self.fxsave[offset..offset + 2].try_into().unwrap()) as u64
+    }
+
+    fn u32_at(&self, offset: usize) -> u64 {
+        u32::from_le_bytes(self.fxsave[offset..offset + 4].try_into().unwrap()) as u64
+    }
+
+    /// The 80 bit st(i) register, which is physical register (top + i) % 8.
+    fn st(&self, i: usize) -> &[u8] {
+        &self.fxsave[FX_ST + 16 * i..FX_ST + 16 * i + 10]
+    }
+
+    /// The full x87 tag word. FXSAVE only records whether each register is empty, so like the
+    /// kernel we work out the valid/zero/special tags from the register contents.
+    fn tag_word(&self) -> u64 {
+        let abridged = self.fxsave[FX_FTW];
+        let top = ((self.u16_at(FX_SWD) >> 11) & 7) as usize;
+        let mut tag = 0;
+        for physical in 0..8 {
+            let bits = if abridged & (1 << physical) == 0 {
+                3
+            } else {
+                let st = self.st((physical + 8 - top) % 8);
+                let exponent = u16::from_le_bytes([st[8], st[9]]) & 0x7fff;
+                let mantissa = u64::from_le_bytes(st[..8].try_into().unwrap());
+                match exponent {
+                    0x7fff => 2,
+                    0 if mantissa == 0 => 1,
+                    0 => 2,
+                    _ if mantissa >> 63 == 0 => 2,
+                    _ => 0,
+                }
+            };
+            tag |= bits << (2 * physical);
+        }
+        tag
+    }
+}
+
+/// Returns the 64 bit register that `name` lives in, and the bit offset and size in bytes of
+/// `name` within it, so that e.g. `eax` is (rax, 0, 4) and `ah` is (rax, 8, 1).
+fn locate(name: &str) -> Option<(&'static str, u32, usize)> {
+    let alias = match name {
+        "pc" => Some("rip"),
+        "sp" => Some("rsp"),
+        "fp" => Some("rbp"),
+        _ => None,
+    };
+    if let Some(full) = alias
+        .or_else(|| GENERAL_REGISTERS.iter().chain(BASE_REGISTERS.iter()).cloned().find(|r| *r == name))
+    {
+        return Some((full, 0, 8));
+    }
+    let find = |names: &[&str]| names.iter().position(|r| *r == name);
+    if let Some(i) = find(&REGISTERS32) {
+        Some((SUBREGISTER_BASES[i], 0, 4))
+    } else if let Some(i) = find(&REGISTERS16) {
+        Some((SUBREGISTER_BASES[i], 0, 2))
+    } else if let Some(i) = find(&REGISTERS8) {
+        Some((SUBREGISTER_BASES[i], 0, 1))
+    } else if let Some(i) = find(&REGISTERS8_HIGH) {
+        Some((SUBREGISTER_BASES[i], 8, 1))
+    } else {
+        None
+    }
+}
+
+fn field<'a>(regs: &'a mut libc::user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    })
+}
+
+/// Returns the value of the register `name` (without the `$`) and its size in bytes.
+/// Sub-registers such as `eax` and `al`, and gdb's `pc`, `sp` and `fp`, are understood.
+pub fn get_register(regs: &libc::user_regs_struct, name: &str) -> Option<(u64, usize)> {
+    let (full, shift, size) = locate(name)?;
+    let mut regs = *regs;
+    let value = *field(&mut regs, full)? >> shift;
+    let mask = if size == 8 { !0 } else { (1u64 << (size * 8)) - 1 };
+    Some((value & mask, size))
+}
+
+/// Sets the register `name` to `value`. Writing a sub-register leaves the rest of the full
+/// register alone, as gdb does.
+pub fn set_register(regs: &mut libc::user_regs_struct, name: &str, value: u64) -> Option<()> {
+    let (full, shift, size) = locate(name)?;
+    let reg = field(regs, full)?;
+    let mask = if size == 8 { !0 } else { ((1u64 << (size * 8)) - 1) << shift };
+    *reg = (*reg & !mask) | ((value << shift) & mask);
+    Some(())
+}
+
+fn format_flags(value: u64, flags: &[(u32, &str)]) -> String {
+    let set: Vec<&str> = flags
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", set.join(" "))
+}
+
+/// Formats rflags as gdb does, e.g. `[ PF ZF IF ]`.
+pub fn format_eflags(value: u64) -> String {
+    format_flags(value, &EFLAGS)
+}
+
+/// Converts an 80 bit x87 extended precision number to the nearest f64.
+fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    if exponent == 0x7fff {
+        return if mantissa << 1 == 0 { sign * f64::INFINITY } else { f64::NAN };
+    }
+    // The integer bit is explicit, so the value is mantissa * 2^(exponent - bias - 63)
+    let exponent = if exponent == 0 { 1 } else { exponent };
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+/// Formats a floating point number, switching to exponent notation for very large or small
+/// magnitudes as gdb's %g does.
+fn format_float(value: f64) -> String {
+    let magnitude = value.abs();
+    if value == 0.0 || !value.is_finite() || (1e-4..1e17).contains(&magnitude) {
+        value.to_string()
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+/// Formats the lanes of a vector register the way gdb shows its unions, e.g.
+/// `{v4_float = {...}, v2_double = {...}, ..., uint128 = 0x...}`.
+fn format_vector(bytes: &[u8]) -> String {
+    let lanes = |width: usize, format: &dyn Fn(&[u8]) -> String| {
+        let values: Vec<String> = bytes.chunks(width).map(format).collect();
+        format!("{{{}}}", values.join(", "))
+    };
+    let uint = |lane: &[u8]| {
+        let mut buf = [0u8; 8];
+        buf[..lane.len()].copy_from_slice(lane);
+        u64::from_le_bytes(buf)
+    };
+    let hex = |lane: &[u8]| format!("{:#x}", uint(lane));
+    let int128 = |lane: &[u8]| {
+        let high = uint(&lane[8..]);
+        let low = uint(&lane[..8]);
+        if high == 0 {
+            format!("{:#x}", low)
+        } else {
+            format!("{:#x}{:016x}", high, low)
+        }
+    };
+    let count = |width: usize| bytes.len() / width;
+    let mut parts = vec![
+        format!("v{}_float = {}", count(4), lanes(4, &|lane| format_float(f32::from_bits(uint(lane) as u32) as f64))),
+        format!("v{}_double = {}", count(8), lanes(8, &|lane| format_float(f64::from_bits(uint(lane))))),
+        format!("v{}_int8 = {}", count(1), lanes(1, &hex)),
+        format!("v{}_int16 = {}", count(2), lanes(2, &hex)),
+        format!("v{}_int32 = {}", count(4), lanes(4, &hex)),
+        format!("v{}_int64 = {}", count(8), lanes(8, &hex)),
+    ];
+    if bytes.len() == 16 {
+        parts.push(format!("uint128 = {}", int128(bytes)));
+    } else {
+        parts.push(format!("v{}_int128 = {}", count(16), lanes(16, &int128)));
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+/// Returns the rows `info all-registers` adds after the general purpose registers: the x87
+/// stack and control registers, the SSE and AVX registers, and mxcsr. Each row is a name,
+/// a raw value and a natural value.
+pub fn format_fp_registers(fp: &FpRegisters) -> Vec<(String, String, String)> {
+    let mut rows = Vec::new();
+    for i in 0..8 {
+        let st = fp.st(i);
+        let raw: String = st.iter().rev().map(|b| format!("{:02x}", b)).collect();
+        rows.push((
+            format!("st{}", i),
+            format_float(extended_to_f64(st)),
+            format!("(raw 0x{})", raw),
+        ));
+    }
+    let control = [
+        ("fctrl", fp.u16_at(FX_CWD)),
+        ("fstat", fp.u16_at(FX_SWD)),
+        ("ftag", fp.tag_word()),
+        ("fiseg", 0),
+        ("fioff", fp.u32_at(FX_RIP)),
+        ("foseg", 0),
+        ("fooff", fp.u32_at(FX_RDP)),
+        ("fop", fp.u16_at(FX_FOP)),
+    ];
+    for (name, value) in control.iter() {
+        rows.push((name.to_string(), format!("{:#x}", value), value.to_string()));
+    }
+    for i in 0..16 {
+        let xmm = &fp.fxsave[FX_XMM + 16 * i..FX_XMM + 16 * (i + 1)];
+        rows.push((format!("xmm{}", i), format_vector(xmm), String::new()));
+    }
+    if let Some(ymm_high) = &fp.ymm_high {
+        for i in 0..16 {
+            let mut ymm = fp.fxsave[FX_XMM + 16 * i..FX_XMM + 16 * (i + 1)].to_vec();
+            ymm.extend_from_slice(&ymm_high[16 * i..16 * (i + 1)]);
+            rows.push((format!("ymm{}", i), format_vector(&ymm), String::new()));
+        }
+    }
+    let mxcsr = fp.u32_at(FX_MXCSR);
+    rows.push((
+        "mxcsr".to_string(),
+        format!("{:#x}", mxcsr),
+        format_flags(mxcsr, &MXCSR_FLAGS),
+    ));
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn regs_with_rax(rax: u64) -> libc::user_regs_struct {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = rax;
+        regs
+    }
+
+    #[test]
+    fn test_get_register() {
+        let mut regs = regs_with_rax(0x1122_3344_5566_7788);
+        regs.rip = 0x401000;
+        regs.r10 = 0xffff_ffff_8000_0001;
+        assert_eq!(get_register(&regs, "rax"), Some((0x1122_3344_5566_7788, 8)));
+        assert_eq!(get_register(&regs, "eax"), Some((0x5566_7788, 4)));
+        assert_eq!(get_register(&regs, "ax"), Some((0x7788, 2)));
+        assert_eq!(get_register(&regs, "al"), Some((0x88, 1)));
+        assert_eq!(get_register(&regs, "ah"), Some((0x77, 1)));
+        assert_eq!(get_register(&regs, "r10d"), Some((0x8000_0001, 4)));
+        assert_eq!(get_register(&regs, "r10l"), Some((0x01, 1)));
+        // gdb's aliases
+        assert_eq!(get_register(&regs, "pc"), Some((0x401000, 8)));
+        assert_eq!(get_register(&regs, "fs_base"), Some((0, 8)));
+        // Not general purpose registers, or not registers at all
+        assert_eq!(get_register(&regs, "xmm0"), None);
+        assert_eq!(get_register(&regs, "r8h"), None);
+        assert_eq!(get_register(&regs, "RAX"), None);
+    }
+
+    #[test]
+    fn test_set_register() {
+        let mut regs = regs_with_rax(0x1122_3344_5566_7788);
+        // A sub-register leaves the rest of the register alone
+        set_register(&mut regs, "ah", 0xff).unwrap();
+        assert_eq!(regs.rax, 0x1122_3344_5566_ff88);
+        set_register(&mut regs, "eax", 0).unwrap();
+        assert_eq!(regs.rax, 0x1122_3344_0000_0000);
+        // Extra bits are dropped
+        set_register(&mut regs, "al", 0x1234).unwrap();
+        assert_eq!(regs.rax, 0x1122_3344_0000_0034);
+        set_register(&mut regs, "rax", !0).unwrap();
+        assert_eq!(regs.rax, !0);
+        set_register(&mut regs, "sp", 0x7ffe_0000).unwrap();
+        assert_eq!(regs.rsp, 0x7ffe_0000);
+        assert_eq!(set_register(&mut regs, "st0", 1), None);
+    }
+
+    #[test]
+    fn test_format_eflags() {
+        assert_eq!(format_eflags(0x246), "[ PF ZF IF ]");
+        assert_eq!(format_eflags(0x10203), "[ CF IF RF ]");
+        assert_eq!(format_eflags(0), "[  ]");
+    }
+}